@@ -0,0 +1,162 @@
+use crate::cache::ScanCache;
+use crate::cancel::CancellationToken;
+use crate::centroiding::Centroiding;
+use crate::config::Config;
+use crate::error::Error;
+use crate::loading::{process_file_streaming, process_files_in_parallel, FileResult};
+use crate::measurements::Compound;
+use crate::processing::integrate_xics;
+use crate::progress::{ProgressEvent, ProgressReporter};
+use futures::future::join_all;
+use std::sync::Arc;
+
+/// Process files with the settings of `config`, the way every front end does
+///
+/// Batches of more than `parallelism.batch_threshold` files go through
+/// [`process_large_batch`], smaller ones through
+/// [`process_files_in_parallel`](crate::process_files_in_parallel). The XICs of every
+/// processed file are then integrated with [`integrate`].
+pub async fn process_batch(
+    file_paths: &[String],
+    ion_list: Vec<Compound>,
+    config: &Config,
+    cache: Option<ScanCache>,
+    progress: Arc<dyn ProgressReporter>,
+    cancel: CancellationToken,
+) -> Vec<FileResult> {
+    let mass_accuracy = config.tolerances.mass_accuracy;
+    let centroiding = config.centroiding;
+    let retain_scans = config.output.keep_scans;
+    let mut results = if file_paths.len() > config.parallelism.batch_threshold {
+        // For large batches, use hybrid approach (Tokio + Rayon)
+        process_large_batch(file_paths, ion_list, mass_accuracy, centroiding, retain_scans, cache, progress, cancel).await
+    } else {
+        // For smaller batches, use standard Rayon approach
+        process_files_in_parallel(file_paths, &ion_list, mass_accuracy, centroiding, retain_scans, cache.as_ref(), progress.as_ref(), &cancel)
+    };
+    for measurement in results.iter_mut().filter_map(|r| r.result.as_mut().ok()) {
+        integrate(&mut measurement.xics, config);
+    }
+    results
+}
+
+/// Integrate extracted XICs with the peak picking and integration settings of `config`
+pub fn integrate(compounds: &mut [Compound], config: &Config) {
+    integrate_xics(compounds, config.peak_picking.min_intensity, config.integration.rt_range);
+}
+
+/// Process a large number of files, splitting them into batches that are run as
+/// Tokio tasks, reporting the overall progress to `progress`
+///
+/// Returns one result per file, like [`process_files_in_parallel`](crate::process_files_in_parallel).
+/// Once `cancel` is triggered no further files are started, and the files that were not
+/// completed fail with [`Error::Cancelled`].
+#[allow(clippy::too_many_arguments)]
+pub async fn process_large_batch(
+    file_paths: &[String],
+    ion_list: Vec<Compound>,
+    mass_accuracy: f64,
+    centroiding: Centroiding,
+    retain_scans: bool,
+    cache: Option<ScanCache>,
+    progress: Arc<dyn ProgressReporter>,
+    cancel: CancellationToken,
+) -> Vec<FileResult> {
+    // Share the ion list across all tasks
+    let ion_list = Arc::new(ion_list);
+
+    let start = std::time::Instant::now();
+    progress.report(ProgressEvent::BatchStarted {
+        files: file_paths.len(),
+    });
+    let cache = Arc::new(cache);
+    
+    // Determine optimal batch size based on available cores
+    let num_physical_cores = num_cpus::get_physical();
+    let batch_size = std::cmp::max(5, file_paths.len() / (num_physical_cores * 2));
+    
+    // Split files into batches for processing, keeping the position of each batch's
+    // first file so results can be numbered in input order
+    let batches: Vec<(usize, Vec<String>)> = file_paths
+        .chunks(batch_size)
+        .enumerate()
+        .map(|(i, chunk)| (i * batch_size, chunk.to_vec()))
+        .collect();
+    
+    // Process each batch on Tokio's blocking pool, so the worker threads stay free for
+    // other tasks such as the Ctrl-C handler
+    let batch_futures = batches.iter().cloned().map(|(offset, batch)| {
+        let ion_list_clone = Arc::clone(&ion_list);
+        let progress_clone = Arc::clone(&progress);
+        let cache_clone = Arc::clone(&cache);
+        let cancel_clone = cancel.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            // Process this batch of files
+            process_file_batch(offset, batch, ion_list_clone, mass_accuracy, centroiding, retain_scans, cache_clone, progress_clone, cancel_clone)
+        })
+    });
+    
+    // Wait for all batches to complete; join_all keeps them in input order
+    let batch_results = join_all(batch_futures).await;
+    
+    // Flatten the results, failing every file of a batch whose task panicked rather
+    // than dropping them
+    let results: Vec<FileResult> = batch_results
+        .into_iter()
+        .zip(batches)
+        .flat_map(|(result, (offset, batch))| match result {
+            Ok(results) => results,
+            Err(e) => batch
+                .into_iter()
+                .enumerate()
+                .map(|(i, file)| FileResult::new(offset + i, file, Err(Error::Panicked { message: e.to_string() })))
+                .collect(),
+        })
+        .collect();
+
+    // Count total processed files
+    let total_processed = results.iter().filter(|r| r.result.is_ok()).count();
+    
+    progress.report(ProgressEvent::BatchFinished {
+        files: total_processed,
+        seconds: start.elapsed().as_secs_f64(),
+    });
+    
+    results
+}
+
+/// Process a batch of files, blocking until they are done
+///
+/// This function handles a smaller subset of files within the large batch processing,
+/// starting at position `offset` of it
+#[allow(clippy::too_many_arguments)]
+fn process_file_batch(
+    offset: usize,
+    batch: Vec<String>,
+    ion_list: Arc<Vec<Compound>>,
+    mass_accuracy: f64,
+    centroiding: Centroiding,
+    retain_scans: bool,
+    cache: Arc<Option<ScanCache>>,
+    progress: Arc<dyn ProgressReporter>,
+    cancel: CancellationToken,
+) -> Vec<FileResult> {
+    let mut results = Vec::with_capacity(batch.len());
+    
+    for (i, file_path) in batch.into_iter().enumerate() {
+        if cancel.is_cancelled() {
+            results.push(FileResult::new(offset + i, file_path, Err(Error::Cancelled)));
+            continue;
+        }
+        // File operations could be made async for further optimization
+        // But keeping synchronous for compatibility with existing code
+        // Process the file, streaming its spectra, and store the result
+        let result = process_file_streaming(
+            &file_path, &ion_list, mass_accuracy, centroiding, retain_scans, cache.as_ref().as_ref(), progress.as_ref(), &cancel
+        );
+        results.push(FileResult::new(offset + i, file_path, result));
+    }
+    
+    results
+}
@@ -0,0 +1,603 @@
+use crate::processing::peak_arrays;
+use memmap2::Mmap;
+use mzdata::params::ParamList;
+use mzdata::spectrum::bindata::{ArrayType, BinaryArrayMap, BinaryDataArrayType, DataArray};
+use mzdata::spectrum::{
+    Acquisition, MultiLayerSpectrum, Precursor, ScanEvent, ScanPolarity, SelectedIon,
+    SignalContinuity, SpectrumDescription, SpectrumLike,
+};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+/// Magic bytes identifying a scan cache file
+const MAGIC: &[u8; 8] = b"LCMSCACH";
+/// Bump whenever the layout below changes, old caches are then rebuilt
+const VERSION: u32 = 2;
+const RECORD_SIZE: usize = 64;
+const FOOTER_SIZE: usize = 72;
+/// How much of the start and end of the source file goes into its fingerprint
+const FINGERPRINT_SAMPLE: u64 = 64 * 1024;
+
+/// Numbers the temp files of this process, so concurrent writers never share one
+static NEXT_WRITER: AtomicU64 = AtomicU64::new(0);
+
+/// On-disk cache of decoded spectra, one file per MS data file.
+///
+/// Parsing XML and decoding base64 payloads dominates loading time, so the decoded
+/// arrays are stored in a columnar binary file and read back through a memory map.
+/// The cache keeps what the processing pipeline uses: native ID, position, MS level,
+/// polarity, signal continuity (unknown, profile or centroid), start time, first precursor m/z and charge, and
+/// the m/z and intensity arrays.
+///
+/// Layout (little endian): the m/z (`f64`) and intensity (`f32`) arrays of each
+/// spectrum back to back, then the native IDs, then one 64-byte record per spectrum,
+/// and finally a 72-byte footer holding the offsets and the source fingerprint.
+/// Writing the index last means the cache can be filled while a file is streamed.
+#[derive(Debug, Clone)]
+pub struct ScanCache {
+    dir: PathBuf,
+}
+
+impl ScanCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        ScanCache { dir: dir.into() }
+    }
+
+    /// `$LCMSPECTOR_CACHE_DIR` if set, otherwise `lcmspector` in the user cache directory
+    pub fn default_location() -> Self {
+        if let Some(dir) = std::env::var_os("LCMSPECTOR_CACHE_DIR") {
+            return Self::new(dir);
+        }
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir);
+        Self::new(base.join("lcmspector"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the cache for `file_path` lives, named after the hash of its canonical path
+    pub fn cache_path(&self, file_path: &str) -> io::Result<PathBuf> {
+        let canonical = fs::canonicalize(file_path)?;
+        let digest = Sha256::digest(canonical.to_string_lossy().as_bytes());
+        Ok(self.dir.join(format!("{}.scans", hex(&digest[..16]))))
+    }
+
+    /// Open the cached spectra of `file_path`, or `None` if there is no cache for it
+    /// or the file changed since the cache was written
+    pub fn open(&self, file_path: &str) -> io::Result<Option<CachedSpectra>> {
+        let cache_path = self.cache_path(file_path)?;
+        let file = match File::open(&cache_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // Safety: cache files are only ever replaced by an atomic rename, never modified in place
+        let mmap = unsafe { Mmap::map(&file)? };
+        let Some(footer) = Footer::parse(&mmap) else {
+            return Ok(None);
+        };
+        if footer.fingerprint != fingerprint(file_path)? {
+            return Ok(None);
+        }
+        Ok(Some(CachedSpectra {
+            mmap: Arc::new(mmap),
+            footer,
+            next: 0,
+        }))
+    }
+
+    /// Start writing the cache for `file_path`; nothing is visible until [`CacheWriter::finish`].
+    ///
+    /// Every writer gets its own temp file, so several writers for the same source,
+    /// in this or another process, do not interfere; the last one to finish wins.
+    pub fn writer(&self, file_path: &str) -> io::Result<CacheWriter> {
+        fs::create_dir_all(&self.dir)?;
+        let final_path = self.cache_path(file_path)?;
+        let writer = NEXT_WRITER.fetch_add(1, Ordering::Relaxed);
+        let temp_path = final_path.with_extension(format!("tmp{}-{writer}", std::process::id()));
+        Ok(CacheWriter {
+            data: BufWriter::new(File::options().write(true).create_new(true).open(&temp_path)?),
+            offset: 0,
+            ids: Vec::new(),
+            records: Vec::new(),
+            fingerprint: fingerprint(file_path)?,
+            temp_path,
+            final_path,
+        })
+    }
+}
+
+/// Identifies the exact version of a source file without reading all of it:
+/// canonical path, size, modification time and a hash of its first and last bytes
+fn fingerprint(file_path: &str) -> io::Result<[u8; 32]> {
+    let canonical = fs::canonicalize(file_path)?;
+    let metadata = fs::metadata(&canonical)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    let mut hasher = Sha256::new();
+    hasher.update(canonical.to_string_lossy().as_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(modified.to_le_bytes());
+
+    let mut file = File::open(&canonical)?;
+    let mut sample = Vec::new();
+    (&mut file).take(FINGERPRINT_SAMPLE).read_to_end(&mut sample)?;
+    if metadata.len() > FINGERPRINT_SAMPLE {
+        file.seek(SeekFrom::End(-(FINGERPRINT_SAMPLE as i64)))?;
+        file.read_to_end(&mut sample)?;
+    }
+    hasher.update(&sample);
+    Ok(hasher.finalize().into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Metadata of one cached spectrum, stored as a fixed-size record
+#[derive(Debug, Clone, Default)]
+struct Record {
+    index: u64,
+    /// Byte offset of the m/z array; intensities follow right after
+    data_offset: u64,
+    n_points: u64,
+    start_time: f64,
+    precursor_mz: f64,
+    id_offset: u64,
+    id_len: u32,
+    precursor_charge: i32,
+    ms_level: u8,
+    polarity: i8,
+    continuity: SignalContinuity,
+    has_precursor: bool,
+}
+
+impl Record {
+    fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut buf = [0u8; RECORD_SIZE];
+        buf[0..8].copy_from_slice(&self.index.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.data_offset.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.n_points.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.start_time.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.precursor_mz.to_le_bytes());
+        buf[40..48].copy_from_slice(&self.id_offset.to_le_bytes());
+        buf[48..52].copy_from_slice(&self.id_len.to_le_bytes());
+        buf[52..56].copy_from_slice(&self.precursor_charge.to_le_bytes());
+        buf[56] = self.ms_level;
+        buf[57] = self.polarity as u8;
+        buf[58] = match self.continuity {
+            SignalContinuity::Unknown => 0,
+            SignalContinuity::Profile => 1,
+            SignalContinuity::Centroid => 2,
+        };
+        buf[59] = self.has_precursor as u8;
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Self {
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        Record {
+            index: u64_at(0),
+            data_offset: u64_at(8),
+            n_points: u64_at(16),
+            start_time: f64::from_bits(u64_at(24)),
+            precursor_mz: f64::from_bits(u64_at(32)),
+            id_offset: u64_at(40),
+            id_len: u32::from_le_bytes(buf[48..52].try_into().unwrap()),
+            precursor_charge: i32::from_le_bytes(buf[52..56].try_into().unwrap()),
+            ms_level: buf[56],
+            polarity: buf[57] as i8,
+            continuity: match buf[58] {
+                1 => SignalContinuity::Profile,
+                2 => SignalContinuity::Centroid,
+                _ => SignalContinuity::Unknown,
+            },
+            has_precursor: buf[59] != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Footer {
+    n_spectra: u64,
+    ids_offset: u64,
+    records_offset: u64,
+    fingerprint: [u8; 32],
+}
+
+impl Footer {
+    fn to_bytes(&self) -> [u8; FOOTER_SIZE] {
+        let mut buf = [0u8; FOOTER_SIZE];
+        buf[0..8].copy_from_slice(MAGIC);
+        buf[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.n_spectra.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.ids_offset.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.records_offset.to_le_bytes());
+        buf[40..72].copy_from_slice(&self.fingerprint);
+        buf
+    }
+
+    /// Read and sanity check the footer at the end of a cache file
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FOOTER_SIZE {
+            return None;
+        }
+        let buf = &bytes[bytes.len() - FOOTER_SIZE..];
+        if &buf[0..8] != MAGIC || u32::from_le_bytes(buf[8..12].try_into().ok()?) != VERSION {
+            return None;
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        let footer = Footer {
+            n_spectra: u64_at(16),
+            ids_offset: u64_at(24),
+            records_offset: u64_at(32),
+            fingerprint: buf[40..72].try_into().ok()?,
+        };
+        let records_end = footer
+            .n_spectra
+            .checked_mul(RECORD_SIZE as u64)?
+            .checked_add(footer.records_offset)?;
+        if footer.ids_offset > footer.records_offset
+            || records_end + FOOTER_SIZE as u64 != bytes.len() as u64
+        {
+            return None;
+        }
+        Some(footer)
+    }
+}
+
+/// Writes a cache file spectrum by spectrum while the source file is being read
+pub struct CacheWriter {
+    data: BufWriter<File>,
+    /// Number of bytes written to `data` so far
+    offset: u64,
+    ids: Vec<u8>,
+    records: Vec<Record>,
+    fingerprint: [u8; 32],
+    temp_path: PathBuf,
+    final_path: PathBuf,
+}
+
+impl CacheWriter {
+    /// Append one spectrum; spectra without m/z and intensity arrays are stored empty.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the arrays cannot be decoded or differ
+    /// in length, so that a damaged spectrum is never cached as a valid one.
+    pub fn write(&mut self, spectrum: &MultiLayerSpectrum) -> io::Result<()> {
+        let (mzs, intensities) = peak_arrays(spectrum)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            .unwrap_or_default();
+        let n_points = mzs.len();
+
+        let id = spectrum.id().as_bytes();
+        let precursor = spectrum.precursor().and_then(|p| p.ions.first());
+        self.records.push(Record {
+            index: spectrum.index() as u64,
+            data_offset: self.offset,
+            n_points: n_points as u64,
+            start_time: spectrum.start_time(),
+            precursor_mz: precursor.map(|ion| ion.mz).unwrap_or(0.0),
+            id_offset: self.ids.len() as u64,
+            id_len: id.len() as u32,
+            precursor_charge: precursor.and_then(|ion| ion.charge).unwrap_or(0),
+            ms_level: spectrum.ms_level(),
+            polarity: spectrum.polarity() as i8,
+            continuity: spectrum.signal_continuity(),
+            has_precursor: precursor.is_some(),
+        });
+        self.ids.extend_from_slice(id);
+
+        for mz in mzs.iter() {
+            self.data.write_all(&mz.to_le_bytes())?;
+        }
+        for intensity in intensities.iter() {
+            self.data.write_all(&intensity.to_le_bytes())?;
+        }
+        self.offset += n_points as u64 * 12;
+        Ok(())
+    }
+
+    /// Write the index and footer and move the cache into place
+    pub fn finish(mut self) -> io::Result<()> {
+        let ids_offset = self.offset;
+        self.data.write_all(&self.ids)?;
+        let records_offset = ids_offset + self.ids.len() as u64;
+        for record in &self.records {
+            self.data.write_all(&record.to_bytes())?;
+        }
+        let footer = Footer {
+            n_spectra: self.records.len() as u64,
+            ids_offset,
+            records_offset,
+            fingerprint: self.fingerprint,
+        };
+        self.data.write_all(&footer.to_bytes())?;
+        self.data.flush()?;
+        fs::rename(&self.temp_path, &self.final_path)
+    }
+
+    /// Drop a partially written cache, e.g. when the source file turned out to be truncated
+    pub fn abandon(self) {
+        let _ = fs::remove_file(&self.temp_path);
+    }
+}
+
+/// Spectra read back from a cache file, decoded lazily from the memory map
+pub struct CachedSpectra {
+    mmap: Arc<Mmap>,
+    footer: Footer,
+    next: usize,
+}
+
+impl CachedSpectra {
+    pub fn len(&self) -> usize {
+        self.footer.n_spectra as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn record(&self, i: usize) -> Record {
+        let start = self.footer.records_offset as usize + i * RECORD_SIZE;
+        Record::from_bytes(&self.mmap[start..start + RECORD_SIZE])
+    }
+
+    /// Rebuild the spectrum at position `i` of the cache
+    pub fn get(&self, i: usize) -> Option<MultiLayerSpectrum> {
+        if i >= self.len() {
+            return None;
+        }
+        let record = self.record(i);
+        let n = record.n_points as usize;
+        let mz_start = record.data_offset as usize;
+        let intensity_start = mz_start + n * 8;
+        let intensity_end = intensity_start + n * 4;
+        let id_start = (self.footer.ids_offset + record.id_offset) as usize;
+        if intensity_end > self.footer.ids_offset as usize
+            || id_start + record.id_len as usize > self.footer.records_offset as usize
+        {
+            return None;
+        }
+
+        let mut arrays = BinaryArrayMap::new();
+        arrays.add(DataArray::wrap(
+            &ArrayType::MZArray,
+            BinaryDataArrayType::Float64,
+            self.mmap[mz_start..intensity_start].to_vec(),
+        ));
+        arrays.add(DataArray::wrap(
+            &ArrayType::IntensityArray,
+            BinaryDataArrayType::Float32,
+            self.mmap[intensity_start..intensity_end].to_vec(),
+        ));
+
+        let precursor = record.has_precursor.then(|| Precursor {
+            ions: vec![SelectedIon {
+                mz: record.precursor_mz,
+                charge: (record.precursor_charge != 0).then_some(record.precursor_charge),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let polarity = match record.polarity {
+            1 => ScanPolarity::Positive,
+            -1 => ScanPolarity::Negative,
+            _ => ScanPolarity::Unknown,
+        };
+        let id = String::from_utf8_lossy(&self.mmap[id_start..id_start + record.id_len as usize]);
+        let description = SpectrumDescription::new(
+            id.into_owned(),
+            record.index as usize,
+            record.ms_level,
+            polarity,
+            record.continuity,
+            ParamList::new(),
+            Acquisition {
+                scans: vec![ScanEvent {
+                    start_time: record.start_time,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            precursor,
+        );
+        Some(MultiLayerSpectrum::from_arrays_and_description(arrays, description))
+    }
+}
+
+impl Iterator for CachedSpectra {
+    type Item = MultiLayerSpectrum;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let spectrum = self.get(self.next)?;
+        self.next += 1;
+        Some(spectrum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancellationToken;
+    use crate::centroiding::Centroiding;
+    use crate::loading::process_file_streaming;
+    use crate::progress::NoProgress;
+    use crate::measurements::Compound;
+    use crate::test_utils::{synthetic_run, temp_path, write_mzml};
+
+    #[test]
+    fn test_cache_round_trip_and_invalidation() {
+        let spectra = synthetic_run();
+        let path = write_mzml("run.mzML", &spectra);
+        let file_path = path.to_str().unwrap();
+        let cache = ScanCache::new(temp_path("cache"));
+
+        assert!(cache.open(file_path).unwrap().is_none());
+        let mut writer = cache.writer(file_path).unwrap();
+        for spectrum in &spectra {
+            writer.write(spectrum).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let cached: Vec<MultiLayerSpectrum> = cache.open(file_path).unwrap().unwrap().collect();
+        assert_eq!(cached.len(), spectra.len());
+        for (a, b) in cached.iter().zip(&spectra) {
+            assert_eq!(a.id(), b.id());
+            assert_eq!(a.index(), b.index());
+            assert_eq!(a.ms_level(), b.ms_level());
+            assert_eq!(a.polarity(), b.polarity());
+            assert_eq!(a.start_time(), b.start_time());
+            let (a, b) = (a.arrays.as_ref().unwrap(), b.arrays.as_ref().unwrap());
+            assert_eq!(a.mzs().unwrap(), b.mzs().unwrap());
+            assert_eq!(a.intensities().unwrap(), b.intensities().unwrap());
+        }
+
+        // Rewriting the source file makes the cache stale
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        write_mzml_to(&path, &spectra[..4]);
+        assert!(cache.open(file_path).unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writers_for_one_file() {
+        let spectra = synthetic_run();
+        let path = write_mzml("concurrent.mzML", &spectra);
+        let file_path = path.to_str().unwrap();
+        let cache = ScanCache::new(temp_path("cache"));
+
+        let mut first = cache.writer(file_path).unwrap();
+        let mut second = cache.writer(file_path).unwrap();
+        for spectrum in &spectra {
+            first.write(spectrum).unwrap();
+            second.write(spectrum).unwrap();
+        }
+        // Abandoning one writer leaves the other's temp file alone
+        first.abandon();
+        second.finish().unwrap();
+
+        let cached: Vec<MultiLayerSpectrum> = cache.open(file_path).unwrap().unwrap().collect();
+        assert_eq!(cached.len(), spectra.len());
+        assert_eq!(std::fs::read_dir(cache.dir()).unwrap().count(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_continuity_survives_the_cache() {
+        // mzXML scans without a centroided attribute have unknown continuity; their sparse
+        // peaks are used as they are instead of being centroided as profile data
+        let scans: String = (0..5)
+            .map(|i| {
+                format!(
+                    r#"<scan num="{}" msLevel="1" peaksCount="4" polarity="-" retentionTime="PT{}S">
+      <peaks precision="32" byteOrder="network" contentType="m/z-int">{}</peaks>
+    </scan>
+    "#,
+                    i + 1,
+                    i * 6,
+                    crate::test_utils::encode_peaks(&[(50.0, 10.0), (59.0139, 100.0), (73.0295, 50.0), (100.0, 200.0)])
+                )
+            })
+            .collect();
+        let path = temp_path("unknown.mzXML");
+        std::fs::write(&path, format!("<mzXML>\n  <msRun scanCount=\"5\">\n    {scans}</msRun>\n</mzXML>\n")).unwrap();
+        let file_path = path.to_str().unwrap();
+        let cache = ScanCache::new(temp_path("cache"));
+        let ion_list = vec![
+            Compound::new("Acetate".to_string(), vec![59.0139], Vec::new()),
+            Compound::new("Propionate".to_string(), vec![73.0295], Vec::new()),
+        ];
+        let cancel = CancellationToken::new();
+        let process = |cache: Option<&ScanCache>| {
+            process_file_streaming(file_path, &ion_list, 0.0001, Centroiding::default(), true, cache, &NoProgress, &cancel).unwrap()
+        };
+
+        let uncached = process(None);
+        assert!(uncached.ms1_scans.iter().all(|scan| scan.signal_continuity() == SignalContinuity::Unknown));
+        assert_eq!(uncached.xics[1].ions[0].ms_intensity, Some(250.0));
+        let filled = process(Some(&cache));
+        let cached = process(Some(&cache));
+        assert!(cache.open(file_path).unwrap().is_some());
+        for measurement in [&filled, &cached] {
+            assert_eq!(measurement.xics[0].ions, uncached.xics[0].ions);
+            assert_eq!(measurement.xics[1].ions, uncached.xics[1].ions);
+            assert_eq!(measurement.xics[1].xics, uncached.xics[1].xics);
+        }
+        assert!(cached.ms1_scans.iter().all(|scan| scan.signal_continuity() == SignalContinuity::Unknown));
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_spectra_are_not_cached() {
+        let mut spectra = synthetic_run();
+        // An MS2 scan with more m/z values than intensities
+        spectra[3] = crate::test_utils::synthetic_spectrum(3, 2, 0.15, &[41.0, 59.0139], &[3.0]);
+        let path = write_mzml("damaged.mzML", &spectra);
+        let file_path = path.to_str().unwrap();
+        let cache = ScanCache::new(temp_path("cache"));
+
+        let mut writer = cache.writer(file_path).unwrap();
+        let error = writer.write(&spectra[3]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        writer.abandon();
+
+        // The file is still processed from its spectra, but no cache is left behind
+        let ion_list = vec![Compound::new("Acetate".to_string(), vec![59.0139], Vec::new())];
+        let cancel = CancellationToken::new();
+        let measurement =
+            process_file_streaming(file_path, &ion_list, 0.0001, Centroiding::default(), false, Some(&cache), &NoProgress, &cancel).unwrap();
+        assert_eq!(measurement.xics[0].ions[0].ms_intensity, Some(25500.0));
+        assert!(cache.open(file_path).unwrap().is_none());
+        assert_eq!(std::fs::read_dir(cache.dir()).unwrap().count(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    fn write_mzml_to(path: &Path, spectra: &[MultiLayerSpectrum]) {
+        let written = write_mzml("rewrite.mzML", spectra);
+        std::fs::copy(&written, path).unwrap();
+        std::fs::remove_file(written).unwrap();
+    }
+
+    #[test]
+    fn test_streaming_with_cache() {
+        let path = write_mzml("run.mzML", &synthetic_run());
+        let file_path = path.to_str().unwrap();
+        let cache = ScanCache::new(temp_path("cache"));
+        let ion_list = vec![Compound::new("Acetate".to_string(), vec![59.0139], vec!["Acetate-neg".to_string()])];
+        let cancel = CancellationToken::new();
+
+        let uncached = process_file_streaming(file_path, &ion_list, 0.0001, Centroiding::default(), false, None, &NoProgress, &cancel).unwrap();
+        let first = process_file_streaming(file_path, &ion_list, 0.0001, Centroiding::default(), true, Some(&cache), &NoProgress, &cancel).unwrap();
+        assert!(cache.cache_path(file_path).unwrap().exists());
+        let second = process_file_streaming(file_path, &ion_list, 0.0001, Centroiding::default(), true, Some(&cache), &NoProgress, &cancel).unwrap();
+
+        assert_eq!(first.xics[0].ions, uncached.xics[0].ions);
+        assert_eq!(second.xics[0].ions, uncached.xics[0].ions);
+        assert_eq!((second.ms1_scans.len(), second.ms2_scans.len()), (10, 10));
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+}
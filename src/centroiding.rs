@@ -0,0 +1,252 @@
+//! Centroiding of profile spectra.
+//!
+//! Profile spectra sample every peak with many data points across its shape. XIC
+//! extraction treats every data point as a peak, so on profile data it would add up
+//! all points of a peak within the m/z tolerance, and the intensity would depend on
+//! how densely the instrument sampled. Loading therefore reduces profile spectra to
+//! one centroid per peak: every local maximum becomes a peak at its centroid m/z, with
+//! the height of the apex or of a Gaussian fitted through it.
+//!
+//! Spectra are treated as profile data if the file says so, or, if it does not, when
+//! their data points are spaced like profile data (see [`looks_like_profile`]).
+//! Spectra the file marks as centroided, e.g. by the vendor software, are used as they
+//! are unless [`Centroiding::vendor_centroids`] is turned off.
+
+use crate::error::{Error, Result};
+use crate::processing::peak_arrays;
+use mzdata::spectrum::bindata::{ArrayType, BinaryArrayMap, BinaryDataArrayType, DataArray};
+use mzdata::spectrum::{MultiLayerSpectrum, SignalContinuity, SpectrumLike};
+use serde::{Deserialize, Serialize};
+
+/// Median spacing of data points in m/z below which a spectrum is taken for profile data
+pub const PROFILE_SPACING: f64 = 0.05;
+
+/// How a peak of a profile spectrum is reduced to a centroid
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CentroidMethod {
+    /// Keep profile spectra as they are
+    Off,
+    /// The intensity-weighted mean m/z of the points above half the apex height,
+    /// with the apex height
+    #[default]
+    WeightedAverage,
+    /// The apex of a Gaussian through the highest point and its two neighbours,
+    /// falling back to the weighted average where no Gaussian fits
+    Gaussian,
+}
+
+/// Whether and how spectra are centroided while they are loaded
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Centroiding {
+    pub method: CentroidMethod,
+    /// Use the spectra the file marks as centroided as they are. If off, they are
+    /// centroided as well when their data points look like profile data.
+    pub vendor_centroids: bool,
+}
+
+impl Default for Centroiding {
+    fn default() -> Self {
+        Centroiding {
+            method: CentroidMethod::WeightedAverage,
+            vendor_centroids: true,
+        }
+    }
+}
+
+impl Centroiding {
+    /// No centroiding: spectra are used as they are stored
+    pub fn off() -> Self {
+        Centroiding {
+            method: CentroidMethod::Off,
+            ..Centroiding::default()
+        }
+    }
+}
+
+/// Whether the data points of a spectrum, sorted by m/z, are spaced like profile data:
+/// half of them less than [`PROFILE_SPACING`] apart
+pub fn looks_like_profile(mzs: &[f64]) -> bool {
+    if mzs.len() < 3 {
+        return false;
+    }
+    let mut gaps: Vec<f64> = mzs.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    let middle = gaps.len() / 2;
+    let (_, median, _) = gaps.select_nth_unstable_by(middle, f64::total_cmp);
+    *median < PROFILE_SPACING
+}
+
+/// Centroid a spectrum in place if it holds profile data, replacing its arrays with
+/// one m/z and intensity per peak and marking it as centroided.
+///
+/// Returns whether the spectrum was centroided. Fails with [`Error::Spectrum`] if its
+/// arrays cannot be decoded or differ in length.
+pub fn centroid_spectrum(spectrum: &mut MultiLayerSpectrum, centroiding: &Centroiding) -> Result<bool> {
+    if centroiding.method == CentroidMethod::Off {
+        return Ok(false);
+    }
+    let continuity = spectrum.signal_continuity();
+    if continuity == SignalContinuity::Centroid && centroiding.vendor_centroids {
+        return Ok(false);
+    }
+    let Some((mzs, intensities)) = peak_arrays(spectrum)? else {
+        return Ok(false);
+    };
+    if continuity != SignalContinuity::Profile && !looks_like_profile(&mzs) {
+        return Ok(false);
+    }
+    let (mzs, intensities) = centroid_peaks(&mzs, &intensities, centroiding.method);
+    let decode_error = |e: mzdata::spectrum::bindata::ArrayRetrievalError| Error::Spectrum {
+        index: spectrum.index(),
+        message: e.to_string(),
+    };
+
+    let mut mz_array = DataArray::from_name_and_type(&ArrayType::MZArray, BinaryDataArrayType::Float64);
+    let mut intensity_array = DataArray::from_name_and_type(&ArrayType::IntensityArray, BinaryDataArrayType::Float32);
+    mz_array.update_buffer(&mzs).map_err(decode_error)?;
+    intensity_array.update_buffer(&intensities).map_err(decode_error)?;
+    let mut arrays = BinaryArrayMap::new();
+    arrays.add(mz_array);
+    arrays.add(intensity_array);
+    spectrum.arrays = Some(arrays);
+    spectrum.description.signal_continuity = SignalContinuity::Centroid;
+    Ok(true)
+}
+
+/// Reduce profile data, sorted by m/z, to one centroid per local maximum.
+///
+/// A peak extends from its apex down both flanks for as long as the intensity keeps
+/// falling and stays above zero. With [`CentroidMethod::Off`] the data is returned as is.
+pub fn centroid_peaks(mzs: &[f64], intensities: &[f32], method: CentroidMethod) -> (Vec<f64>, Vec<f32>) {
+    if method == CentroidMethod::Off {
+        return (mzs.to_vec(), intensities.to_vec());
+    }
+    let intensities = &intensities[..mzs.len().min(intensities.len())];
+    let intensity = |i: usize| intensities.get(i).copied().unwrap_or(0.0);
+    let (mut centroid_mzs, mut centroid_intensities) = (Vec::new(), Vec::new());
+    for apex in 0..intensities.len() {
+        let height = intensities[apex];
+        let left = if apex > 0 { intensity(apex - 1) } else { 0.0 };
+        // The last point of a plateau is its apex
+        if height <= 0.0 || height < left || height <= intensity(apex + 1) {
+            continue;
+        }
+        let mut start = apex;
+        while start > 0 && intensities[start - 1] > 0.0 && intensities[start - 1] <= intensities[start] {
+            start -= 1;
+        }
+        let mut end = apex;
+        while end + 1 < intensities.len() && intensities[end + 1] > 0.0 && intensities[end + 1] <= intensities[end] {
+            end += 1;
+        }
+
+        let fitted = match method {
+            CentroidMethod::Gaussian if start < apex && apex < end => gaussian_apex(
+                (mzs[apex - 1], intensities[apex - 1]),
+                (mzs[apex], height),
+                (mzs[apex + 1], intensities[apex + 1]),
+            ),
+            _ => None,
+        };
+        let (mz, height) = fitted.unwrap_or_else(|| {
+            let half_height = height / 2.0;
+            let (mut total, mut weighted) = (0.0, 0.0);
+            for i in (start..=end).filter(|i| intensities[*i] >= half_height) {
+                total += intensities[i] as f64;
+                weighted += mzs[i] * intensities[i] as f64;
+            }
+            (weighted / total, height)
+        });
+        centroid_mzs.push(mz);
+        centroid_intensities.push(height);
+    }
+    (centroid_mzs, centroid_intensities)
+}
+
+/// m/z and height of the apex of the Gaussian through three points, the middle one the
+/// highest, from the parabola through their log intensities. `None` if the points
+/// are not peak-shaped.
+fn gaussian_apex(left: (f64, f32), apex: (f64, f32), right: (f64, f32)) -> Option<(f64, f32)> {
+    // Relative to the apex, so the parabola is not fitted at large m/z values
+    let (u1, u3) = (left.0 - apex.0, right.0 - apex.0);
+    let (y1, y2, y3) = ((left.1 as f64).ln(), (apex.1 as f64).ln(), (right.1 as f64).ln());
+    let (slope1, slope3) = ((y1 - y2) / u1, (y3 - y2) / u3);
+    let a = (slope1 - slope3) / (u1 - u3);
+    let b = slope1 - a * u1;
+    if a >= 0.0 {
+        return None;
+    }
+    let offset = -b / (2.0 * a);
+    if !(u1..=u3).contains(&offset) {
+        return None;
+    }
+    Some((apex.0 + offset, (y2 - b * b / (4.0 * a)).exp() as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::synthetic_spectrum;
+
+    /// A Gaussian peak at `center` sampled every `spacing` m/z from 59.0 to 59.03
+    fn profile_peak(center: f64, spacing: f64) -> (Vec<f64>, Vec<f32>) {
+        let sigma = 0.002;
+        let points = (0.03 / spacing).round() as usize;
+        let mzs: Vec<f64> = (0..=points).map(|i| 59.0 + i as f64 * spacing).collect();
+        let intensities = mzs
+            .iter()
+            .map(|mz| (1000.0 * (-(mz - center).powi(2) / (2.0 * sigma * sigma)).exp()) as f32)
+            .collect();
+        (mzs, intensities)
+    }
+
+    #[test]
+    fn test_centroid_peaks_is_independent_of_sampling() {
+        for spacing in [0.0005, 0.001] {
+            let (mzs, intensities) = profile_peak(59.0139, spacing);
+            let (centroid_mzs, centroid_intensities) = centroid_peaks(&mzs, &intensities, CentroidMethod::Gaussian);
+            assert_eq!(centroid_mzs.len(), 1);
+            assert!((centroid_mzs[0] - 59.0139).abs() < 1e-6, "{centroid_mzs:?}");
+            assert!((centroid_intensities[0] - 1000.0).abs() < 1.0, "{centroid_intensities:?}");
+
+            let (centroid_mzs, centroid_intensities) =
+                centroid_peaks(&mzs, &intensities, CentroidMethod::WeightedAverage);
+            assert_eq!(centroid_mzs.len(), 1);
+            assert!((centroid_mzs[0] - 59.0139).abs() < 2e-4, "{centroid_mzs:?}");
+            assert!(centroid_intensities[0] > 900.0);
+        }
+        // Two peaks separated by a valley, and a plateau
+        let (mzs, intensities) = centroid_peaks(
+            &[100.0, 100.01, 100.02, 100.03, 100.04, 100.05, 100.06, 100.07],
+            &[0.0, 10.0, 30.0, 5.0, 20.0, 20.0, 5.0, 0.0],
+            CentroidMethod::WeightedAverage,
+        );
+        assert_eq!(intensities, [30.0, 20.0]);
+        assert!((mzs[0] - 100.02).abs() < 1e-9);
+        assert!((mzs[1] - 100.045).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_centroid_spectrum() {
+        let (mzs, intensities) = profile_peak(59.0139, 0.0005);
+        let mut spectrum = synthetic_spectrum(0, 1, 0.5, &mzs, &intensities);
+        spectrum.description.signal_continuity = SignalContinuity::Profile;
+        assert!(centroid_spectrum(&mut spectrum, &Centroiding::default()).unwrap());
+        assert_eq!(spectrum.signal_continuity(), SignalContinuity::Centroid);
+        assert_eq!(spectrum.arrays.as_ref().unwrap().mzs().unwrap().len(), 1);
+
+        // Vendor centroids are kept unless they look like profile data and are not trusted
+        let mut vendor = synthetic_spectrum(0, 1, 0.5, &mzs, &intensities);
+        assert!(!centroid_spectrum(&mut vendor, &Centroiding::default()).unwrap());
+        let own = Centroiding { vendor_centroids: false, ..Centroiding::default() };
+        assert!(centroid_spectrum(&mut vendor, &own).unwrap());
+        let mut sparse = synthetic_spectrum(0, 1, 0.5, &[50.0, 59.0139, 73.0295], &[10.0, 5000.0, 20.0]);
+        assert!(!centroid_spectrum(&mut sparse, &own).unwrap());
+
+        let mut profile = synthetic_spectrum(0, 1, 0.5, &mzs, &intensities);
+        profile.description.signal_continuity = SignalContinuity::Profile;
+        assert!(!centroid_spectrum(&mut profile, &Centroiding::off()).unwrap());
+        assert_eq!(profile.arrays.as_ref().unwrap().mzs().unwrap().len(), mzs.len());
+    }
+}
@@ -0,0 +1,159 @@
+//! Processing parameters, read from a TOML or JSON configuration file.
+//!
+//! Every section and field is optional and falls back to its default, so a file only
+//! needs to list what differs:
+//!
+//! ```toml
+//! [tolerances]
+//! mass_accuracy = 0.0002
+//!
+//! [centroiding]
+//! method = "gaussian"
+//!
+//! [integration]
+//! rt_range = [1.5, 12.0]
+//!
+//! [ion_lists]
+//! paths = ["/data/ion_lists.json"]
+//! ```
+
+use crate::centroiding::Centroiding;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the configuration file picked up from the working directory
+pub const DEFAULT_CONFIG_FILE: &str = "lcmspector.toml";
+
+/// All processing parameters
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub tolerances: Tolerances,
+    pub centroiding: Centroiding,
+    pub peak_picking: PeakPicking,
+    pub integration: Integration,
+    pub ion_lists: IonListSource,
+    pub parallelism: Parallelism,
+    pub output: Output,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tolerances {
+    /// m/z tolerance; ions are matched within +/- 3 times this value
+    pub mass_accuracy: f64,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Tolerances { mass_accuracy: 0.0001 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeakPicking {
+    /// Matched peaks below this intensity are treated as noise
+    pub min_intensity: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Integration {
+    /// Retention time window in minutes that is integrated, the whole run if unset
+    pub rt_range: Option<(f64, f64)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IonListSource {
+    /// The JSON files holding the ion lists, merged; searched for if empty
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Parallelism {
+    /// Batches with more files than this are split into Tokio tasks
+    pub batch_threshold: usize,
+    /// Tokio worker threads
+    pub worker_threads: usize,
+    /// Rayon threads used to process files, 0 for one per core
+    pub threads: usize,
+}
+
+impl Default for Parallelism {
+    fn default() -> Self {
+        Parallelism {
+            batch_threshold: 25,
+            worker_threads: 4,
+            threads: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+    /// Where to write the results, as CSV if it ends in `.csv` and as JSON otherwise
+    pub path: Option<PathBuf>,
+    /// Keep the raw MS1/MS2 scans of every file in memory
+    pub keep_scans: bool,
+    /// Cache decoded spectra on disk
+    pub cache: bool,
+    /// Report progress as JSON lines on stderr instead of a progress bar
+    pub json_progress: bool,
+}
+
+impl Config {
+    /// Read a configuration file, as JSON if it ends in `.json` and as TOML otherwise
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
+        let display = path.display().to_string();
+        let text = fs::read_to_string(path).map_err(|e| Error::io(&display, e))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let parsed = if is_json {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|message| Error::Config { path: display, message })
+    }
+
+    /// The configuration as TOML, as it would be written to a file
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("The configuration serializes to TOML")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    #[test]
+    fn test_partial_files_fall_back_to_defaults() {
+        let path = temp_path("config.toml");
+        fs::write(&path, "[tolerances]\nmass_accuracy = 0.0005\n\n[integration]\nrt_range = [1.0, 2.5]\n").unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.tolerances.mass_accuracy, 0.0005);
+        assert_eq!(config.integration.rt_range, Some((1.0, 2.5)));
+        assert_eq!(config.parallelism, Parallelism::default());
+        // What is echoed can be read back as is
+        fs::write(&path, config.to_toml()).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        let json_path = temp_path("config.json");
+        fs::write(&json_path, r#"{"parallelism": {"threads": 2}}"#).unwrap();
+        assert_eq!(Config::load(&json_path).unwrap().parallelism.threads, 2);
+
+        fs::write(&path, "[tolerances]\nmass_acuracy = 0.0005\n").unwrap();
+        assert!(matches!(Config::load(&path), Err(Error::Config { .. })));
+        fs::remove_file(path).unwrap();
+        fs::remove_file(json_path).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+//! The error type shared by loading and processing.
+
+use std::fmt;
+use std::io;
+
+/// Why loading or processing failed
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be opened or read
+    Io { path: String, source: io::Error },
+    /// A file is not in one of the supported formats
+    UnknownFormat { path: String },
+    /// A configuration file is not valid or has unknown settings
+    Config { path: String, message: String },
+    /// The ion lists file is not valid JSON
+    IonListsJson { path: String, source: serde_json::Error },
+    /// A row of a target table cannot be imported
+    IonTable { path: String, line: usize, message: String },
+    /// The ion lists file has no list with this name, with the closest name if one is close
+    UnknownIonList { name: String, suggestion: Option<String> },
+    /// Two ion lists files both define a list with this name
+    DuplicateIonList { name: String, first: String, second: String },
+    /// A compound is in more than one of the ion lists being combined
+    DuplicateCompound { name: String, first_list: String, second_list: String },
+    /// An ion of a compound is not an m/z value
+    InvalidIon { compound: String, ion: String },
+    /// A parameter of a call is out of range, e.g. a negative bin width
+    InvalidParameter { name: String, message: String },
+    /// The m/z or intensity array of a spectrum could not be decoded
+    Spectrum { index: usize, message: String },
+    /// Processing a file stopped unexpectedly, e.g. because its task panicked
+    Panicked { message: String },
+    /// The operation was stopped through a [`CancellationToken`](crate::CancellationToken)
+    Cancelled,
+}
+
+/// Result with the crate's [`Error`]
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Attach the path of the file being read to an I/O error
+    pub fn io(path: &str, source: io::Error) -> Self {
+        Error::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "Could not read {path}: {source}"),
+            Error::UnknownFormat { path } => write!(f, "Could not recognise the file format of {path}"),
+            Error::Config { path, message } => write!(f, "Invalid configuration in {path}: {message}"),
+            Error::IonListsJson { path, source } => write!(f, "Could not parse the ion lists in {path}: {source}"),
+            Error::IonTable { path, line, message } => write!(f, "Could not import line {line} of {path}: {message}"),
+            Error::UnknownIonList { name, suggestion: None } => write!(f, "There is no ion list named {name}"),
+            Error::UnknownIonList { name, suggestion: Some(suggestion) } => {
+                write!(f, "There is no ion list named {name}, did you mean {suggestion}?")
+            }
+            Error::DuplicateIonList { name, first, second } => {
+                write!(f, "Ion list {name} is defined in both {first} and {second}")
+            }
+            Error::DuplicateCompound { name, first_list, second_list } => {
+                write!(f, "Compound {name} is in both ion lists {first_list} and {second_list}")
+            }
+            Error::InvalidIon { compound, ion } => write!(f, "Ion {ion} of {compound} is not an m/z value"),
+            Error::InvalidParameter { name, message } => write!(f, "Invalid {name}: {message}"),
+            Error::Spectrum { index, message } => write!(f, "Could not decode spectrum {index}: {message}"),
+            Error::Panicked { message } => write!(f, "Processing stopped unexpectedly: {message}"),
+            Error::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::IonListsJson { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use crate::measurements::{MSMeasurement, Polarity};
+use crate::metadata::FileSummary;
+use crate::processing::Chromatogram;
+use crate::provenance::Provenance;
+use crate::quantification::Quantity;
+use crate::spectra::ExtractedSpectrum;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Column order of the per-ion results table
+const CSV_HEADER: [&str; 8] = ["sample", "file", "compound", "ion", "m/z", "RT", "MS Intensity", "LC Intensity"];
+
+/// Column order of the `info` table of file summaries
+const INFO_HEADER: [&str; 17] = [
+    "file", "format", "gzip", "instrument", "ion_source", "analyzer", "acquired", "polarity", "ms1_scans",
+    "ms2_scans", "msn_scans", "rt_start", "rt_end", "mz_min", "mz_max", "mode", "activation",
+];
+
+/// Column order of the chromatogram table
+const CHROMATOGRAM_HEADER: [&str; 9] = ["sample", "file", "polarity", "mz_start", "mz_end", "RT", "TIC", "BPC", "BPC m/z"];
+
+/// JSON representation of processed measurements: the `provenance` of the run and
+/// the `results`, one object per sample with its position in the batch, file, mass
+/// accuracy, compounds in ion list order and TIC/BPC chromatograms
+pub fn results_to_json(measurements: &[MSMeasurement], provenance: &Provenance) -> Value {
+    let results: Vec<Value> = measurements
+            .iter()
+            .map(|measurement| {
+                json!({
+                    "sample": measurement.sample_index,
+                    "file": measurement.path,
+                    "mass_accuracy": measurement.mass_accuracy,
+                    "compounds": measurement.xics,
+                    "chromatograms": measurement.chromatograms,
+                })
+            })
+            .collect();
+    json!({"provenance": provenance, "results": results})
+}
+
+/// Write processed measurements as pretty-printed JSON
+pub fn write_json<W: Write>(writer: W, measurements: &[MSMeasurement], provenance: &Provenance) -> io::Result<()> {
+    serde_json::to_writer_pretty(writer, &results_to_json(measurements, provenance))?;
+    Ok(())
+}
+
+/// Write processed measurements as a CSV table with one row per sample, compound and
+/// ion, labelled with its `info` from the ion list, below the provenance as `#` comment
+/// lines
+pub fn write_csv<W: Write>(mut writer: W, measurements: &[MSMeasurement], provenance: &Provenance) -> io::Result<()> {
+    provenance.write_comments(&mut writer)?;
+    writeln!(writer, "{}", CSV_HEADER.join(","))?;
+    for measurement in measurements {
+        for compound in &measurement.xics {
+            for ion in &compound.ions {
+                let field = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{}",
+                    measurement.sample_index,
+                    csv_escape(&measurement.path),
+                    csv_escape(&compound.name),
+                    csv_escape(ion.label.as_deref().unwrap_or_default()),
+                    ion.mz,
+                    field(ion.rt),
+                    field(ion.ms_intensity),
+                    field(ion.lc_intensity),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the TIC/BPC chromatograms of processed measurements as a CSV table with one
+/// row per sample, chromatogram and scan, below the provenance as `#` comment lines.
+/// Filters that are not set are left empty.
+pub fn write_chromatograms_csv<W: Write>(mut writer: W, measurements: &[MSMeasurement], provenance: &Provenance) -> io::Result<()> {
+    provenance.write_comments(&mut writer)?;
+    writeln!(writer, "{}", CHROMATOGRAM_HEADER.join(","))?;
+    for measurement in measurements {
+        for chromatogram in &measurement.chromatograms {
+            let polarity = chromatogram.filter.polarity.map_or("", |polarity| polarity.as_str());
+            let (mz_start, mz_end) = match chromatogram.filter.mz_range {
+                Some((start, end)) => (start.to_string(), end.to_string()),
+                None => (String::new(), String::new()),
+            };
+            for i in 0..chromatogram.scan_times.len() {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{},{}",
+                    measurement.sample_index,
+                    csv_escape(&measurement.path),
+                    polarity,
+                    mz_start,
+                    mz_end,
+                    chromatogram.scan_times[i],
+                    chromatogram.tic[i],
+                    chromatogram.bpc[i],
+                    chromatogram.bpc_mz[i].map(|mz| mz.to_string()).unwrap_or_default(),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write results to `path`, as CSV if it ends in `.csv` and as JSON otherwise.
+///
+/// CSV results are a table per ion; the chromatograms go to a second table next to it,
+/// named after it with a `_chromatograms` suffix, e.g. `results_chromatograms.csv`.
+pub fn export_results<P: AsRef<Path>>(path: P, measurements: &[MSMeasurement], provenance: &Provenance) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut writer = BufWriter::new(File::create(path)?);
+    let is_csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        write_csv(&mut writer, measurements, provenance)?;
+        writer.flush()?;
+        let mut writer = BufWriter::new(File::create(chromatograms_path(path))?);
+        write_chromatograms_csv(&mut writer, measurements, provenance)?;
+        writer.flush()
+    } else {
+        write_json(&mut writer, measurements, provenance)?;
+        writer.flush()
+    }
+}
+
+/// Write the quantities of every sample, given with its file, as a CSV table with
+/// `sample`, `compound`, `intensity` and `concentration` columns after the
+/// [`Provenance`] comments
+pub fn write_quantities_csv<W: Write>(mut writer: W, quantities: &[(&str, Vec<Quantity>)], provenance: &Provenance) -> io::Result<()> {
+    provenance.write_comments(&mut writer)?;
+    writeln!(writer, "sample,compound,intensity,concentration")?;
+    let format = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+    for (file, quantities) in quantities {
+        for quantity in quantities {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                csv_escape(file),
+                csv_escape(&quantity.compound),
+                format(quantity.intensity),
+                format(quantity.concentration)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a single chromatogram as a CSV table with `rt`, `tic`, `bpc` and `bpc_mz`
+/// columns, one row per scan
+pub fn write_tic_csv<W: Write>(mut writer: W, chromatogram: &Chromatogram) -> io::Result<()> {
+    writeln!(writer, "rt,tic,bpc,bpc_mz")?;
+    for i in 0..chromatogram.scan_times.len() {
+        let bpc_mz = chromatogram.bpc_mz[i].map(|mz| mz.to_string()).unwrap_or_default();
+        writeln!(writer, "{},{},{},{}", chromatogram.scan_times[i], chromatogram.tic[i], chromatogram.bpc[i], bpc_mz)?;
+    }
+    Ok(())
+}
+
+/// Write the `info` table, a header and one row per file summary, separating columns
+/// with `delimiter`; fields holding it or quotes are quoted
+pub fn write_info_table<W: Write>(mut writer: W, summaries: &[FileSummary], delimiter: char) -> io::Result<()> {
+    let separator = delimiter.to_string();
+    writeln!(writer, "{}", INFO_HEADER.join(&separator))?;
+    for summary in summaries {
+        let range = |range: Option<(f64, f64)>, decimals: usize| {
+            range.map_or((String::new(), String::new()), |(start, end)| {
+                (format!("{:.*}", decimals, start), format!("{:.*}", decimals, end))
+            })
+        };
+        let (rt_start, rt_end) = range(summary.rt_range, 3);
+        let (mz_min, mz_max) = range(summary.mz_range, 4);
+        let polarities: Vec<&str> = summary.polarities.iter().map(Polarity::as_str).collect();
+        let msn_scans: usize = summary.ms_levels.range(3..).map(|(_, count)| count).sum();
+        let fields = [
+            summary.path.clone(),
+            summary.format.to_string(),
+            summary.gzip.to_string(),
+            summary.header.instruments.join("; "),
+            summary.header.ion_sources.join("; "),
+            summary.header.analyzers.join("; "),
+            summary.header.acquired_at.clone().unwrap_or_default(),
+            polarities.join("; "),
+            summary.scans(1).to_string(),
+            summary.scans(2).to_string(),
+            msn_scans.to_string(),
+            rt_start,
+            rt_end,
+            mz_min,
+            mz_max,
+            summary.spectrum_mode().unwrap_or_default().to_string(),
+            summary.activations.join("; "),
+        ];
+        let fields: Vec<String> = fields
+            .into_iter()
+            .map(|field| match field.contains([delimiter, '"']) {
+                true => format!("\"{}\"", field.replace('"', "\"\"")),
+                false => field,
+            })
+            .collect();
+        writeln!(writer, "{}", fields.join(&separator))?;
+    }
+    Ok(())
+}
+
+/// Write a spectrum as a CSV table of its peaks, with `mz` and `intensity` columns
+pub fn write_spectrum_csv<W: Write>(mut writer: W, spectrum: &ExtractedSpectrum) -> io::Result<()> {
+    writeln!(writer, "mz,intensity")?;
+    for (mz, intensity) in spectrum.mzs.iter().zip(&spectrum.intensities) {
+        writeln!(writer, "{},{}", mz, intensity)?;
+    }
+    Ok(())
+}
+
+/// Write spectra in MGF format, one `BEGIN IONS` block per spectrum with its title,
+/// retention time in seconds and, for single MSn scans, the precursor m/z
+pub fn write_mgf<W: Write>(mut writer: W, spectra: &[ExtractedSpectrum]) -> io::Result<()> {
+    for spectrum in spectra {
+        writeln!(writer, "BEGIN IONS")?;
+        writeln!(writer, "TITLE={}", spectrum.title())?;
+        writeln!(writer, "RTINSECONDS={}", spectrum.rt() * 60.0)?;
+        if let Some(precursor_mz) = spectrum.precursor_mz {
+            writeln!(writer, "PEPMASS={}", precursor_mz)?;
+        }
+        for (mz, intensity) in spectrum.mzs.iter().zip(&spectrum.intensities) {
+            writeln!(writer, "{} {}", mz, intensity)?;
+        }
+        writeln!(writer, "END IONS")?;
+    }
+    Ok(())
+}
+
+/// Write a spectrum to `path`, as MGF if it ends in `.mgf` and as CSV otherwise
+pub fn export_spectrum<P: AsRef<Path>>(path: P, spectrum: &ExtractedSpectrum) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut writer = BufWriter::new(File::create(path)?);
+    let is_mgf = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mgf"));
+    if is_mgf {
+        write_mgf(&mut writer, std::slice::from_ref(spectrum))?;
+    } else {
+        write_spectrum_csv(&mut writer, spectrum)?;
+    }
+    writer.flush()
+}
+
+/// The path of the chromatogram table exported next to the CSV results at `path`
+pub fn chromatograms_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}_chromatograms.{extension}"))
+}
+
+/// Quote a CSV field if it contains separators, quotes or line breaks
+pub(crate) fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurements::Compound;
+    use crate::metadata::FileHeader;
+    use crate::processing::ChromatogramFilter;
+
+    #[test]
+    fn test_write_csv() {
+        let mut compound = Compound::new(
+            "Lactic, acid".to_string(),
+            vec![89.0244, 45.0],
+            vec!["[M-H]-".to_string(), "fragment, -CO2".to_string()],
+        );
+        compound.ions[0].rt = Some(1.5);
+        compound.ions[0].ms_intensity = Some(200.0);
+        let measurement = MSMeasurement::from_data(Vec::new(), Vec::new(), vec![compound], 0.0001)
+            .with_source("data/STMIX_BIG_2.5mM_pos.mzml", 2);
+
+        let provenance = Provenance::start(&json!({}), &["acids"], std::slice::from_ref(&measurement.xics[0]));
+
+        let mut out = Vec::new();
+        write_csv(&mut out, &[measurement], &provenance).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        // The provenance comes first, as comment lines
+        let lines: Vec<&str> = csv.lines().skip_while(|line| line.starts_with('#')).collect();
+        assert!(csv.starts_with("# backend_version: "));
+        assert_eq!(lines[0], "sample,file,compound,ion,m/z,RT,MS Intensity,LC Intensity");
+        // Ions are listed in ion list order with their labels, missing values are left empty
+        assert_eq!(lines[1], "2,data/STMIX_BIG_2.5mM_pos.mzml,\"Lactic, acid\",[M-H]-,89.0244,1.5,200,");
+        assert_eq!(lines[2], "2,data/STMIX_BIG_2.5mM_pos.mzml,\"Lactic, acid\",\"fragment, -CO2\",45,,,");
+    }
+
+    #[test]
+    fn test_write_chromatograms_csv() {
+        let chromatogram = Chromatogram {
+            filter: ChromatogramFilter { polarity: Some(Polarity::Negative), mz_range: Some((50.0, 75.0)) },
+            scan_times: vec![0.0, 0.1],
+            tic: vec![300.0, 0.0],
+            bpc: vec![200.0, 0.0],
+            bpc_mz: vec![Some(59.0139), None],
+        };
+        let measurement = MSMeasurement::from_data(Vec::new(), Vec::new(), Vec::new(), 0.0001)
+            .with_chromatograms(vec![chromatogram])
+            .with_source("run.mzML", 1);
+        let provenance = Provenance::start(&json!({}), &["scfas"], &[]);
+
+        let mut out = Vec::new();
+        write_chromatograms_csv(&mut out, std::slice::from_ref(&measurement), &provenance).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().skip_while(|line| line.starts_with('#')).collect();
+        assert_eq!(lines, [
+            "sample,file,polarity,mz_start,mz_end,RT,TIC,BPC,BPC m/z",
+            "1,run.mzML,negative,50,75,0,300,200,59.0139",
+            "1,run.mzML,negative,50,75,0.1,0,0,",
+        ]);
+
+        let json = results_to_json(&[measurement], &provenance);
+        assert_eq!(
+            json["results"][0]["chromatograms"][0]["mz_range"],
+            json!([50.0, 75.0])
+        );
+        assert_eq!(json["results"][0]["chromatograms"][0]["polarity"], "negative");
+        assert_eq!(chromatograms_path(Path::new("out/results.csv")), Path::new("out/results_chromatograms.csv"));
+    }
+
+    #[test]
+    fn test_write_quantities_csv() {
+        let quantities = vec![Quantity { compound: "Lactic, acid".to_string(), intensity: Some(200.0), concentration: None }];
+        let provenance = Provenance::start(&json!({}), &["acids"], &[]);
+        let mut out = Vec::new();
+        write_quantities_csv(&mut out, &[("runs/a,b.mzML", quantities)], &provenance).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().skip_while(|line| line.starts_with('#')).collect();
+        assert_eq!(lines, ["sample,compound,intensity,concentration", "\"runs/a,b.mzML\",\"Lactic, acid\",200,"]);
+    }
+
+    #[test]
+    fn test_write_tic_csv() {
+        let chromatogram = Chromatogram {
+            filter: ChromatogramFilter::default(),
+            scan_times: vec![0.0, 0.1],
+            tic: vec![300.0, 0.0],
+            bpc: vec![200.0, 0.0],
+            bpc_mz: vec![Some(59.0139), None],
+        };
+        let mut out = Vec::new();
+        write_tic_csv(&mut out, &chromatogram).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "rt,tic,bpc,bpc_mz\n0,300,200,59.0139\n0.1,0,0,\n");
+    }
+
+    #[test]
+    fn test_write_info_table() {
+        let summary = FileSummary {
+            path: "runs/a,b.mzML".to_string(),
+            format: crate::loading::MSFileFormat::MzML,
+            gzip: false,
+            header: FileHeader { instruments: vec!["Orbitrap".to_string()], ..FileHeader::default() },
+            polarities: vec![Polarity::Negative, Polarity::Positive],
+            ms_levels: [(1, 10), (2, 4), (3, 1)].into_iter().collect(),
+            rt_range: Some((0.0, 0.9)),
+            mz_range: Some((50.0, 500.0)),
+            centroid_scans: 15,
+            profile_scans: 0,
+            activations: vec!["CID".to_string()],
+        };
+        let mut out = Vec::new();
+        write_info_table(&mut out, std::slice::from_ref(&summary), ',').unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], INFO_HEADER.join(","));
+        assert_eq!(
+            lines[1],
+            "\"runs/a,b.mzML\",mzML,false,Orbitrap,,,,negative; positive,10,4,1,0.000,0.900,50.0000,500.0000,centroid,CID"
+        );
+
+        // Only fields holding the delimiter are quoted
+        let mut out = Vec::new();
+        write_info_table(&mut out, &[summary], '\t').unwrap();
+        assert!(String::from_utf8(out).unwrap().lines().nth(1).unwrap().starts_with("runs/a,b.mzML\tmzML\t"));
+    }
+
+    #[test]
+    fn test_write_mgf() {
+        let spectrum = ExtractedSpectrum {
+            file: "run.mzML".to_string(),
+            ms_level: 1,
+            rt_range: (0.5, 0.7),
+            scans: 3,
+            background_scans: 0,
+            precursor_mz: None,
+            mzs: vec![59.0139, 73.0295],
+            intensities: vec![5000.0, 20.0],
+        };
+        let mut out = Vec::new();
+        write_mgf(&mut out, std::slice::from_ref(&spectrum)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "BEGIN IONS\nTITLE=run.mzML MS1 0.5000-0.7000 min\nRTINSECONDS=36\n59.0139 5000\n73.0295 20\nEND IONS\n"
+        );
+        let mut out = Vec::new();
+        write_spectrum_csv(&mut out, &spectrum).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "mz,intensity\n59.0139,5000\n73.0295,20\n");
+    }
+
+    #[test]
+    fn test_results_json_keeps_ion_objects() {
+        let mut compound = Compound::new("Acetate".to_string(), vec![61.0284, 59.0139], vec!["Acetate-(+)".to_string()]);
+        compound.ions[1].ms_intensity = Some(25500.0);
+        let measurement = MSMeasurement::from_data(Vec::new(), Vec::new(), vec![compound], 0.0001);
+
+        let provenance = Provenance::start(&json!({"mass_accuracy": 0.0001}), &["scfas"], &measurement.xics);
+
+        let json = results_to_json(&[measurement], &provenance);
+        assert_eq!(json["provenance"]["parameters"]["mass_accuracy"], 0.0001);
+        assert_eq!(json["provenance"]["ion_list"]["names"], json!(["scfas"]));
+        let compound = &json["results"][0]["compounds"][0];
+        // Ions are keyed in ion list order, not sorted
+        let names: Vec<&String> = compound["ions"].as_object().unwrap().keys().collect();
+        assert_eq!(names, ["61.0284", "59.0139"]);
+        assert_eq!(
+            compound["ions"]["59.0139"],
+            json!({"m/z": 59.0139, "RT": null, "MS Intensity": 25500.0, "LC Intensity": null})
+        );
+        assert_eq!(compound["ion_info"], json!(["Acetate-(+)", ""]));
+        assert_eq!(compound["calibration_curve"], json!({}));
+    }
+}
@@ -0,0 +1,554 @@
+//! HTTP API for submitting batch jobs from other machines.
+//!
+//! Endpoints:
+//!
+//! - `POST /jobs` with `{"files": [...], "ion_list": "...", "mass_accuracy": 0.0001}`,
+//!   optionally with `"centroiding": {"method": "gaussian", "vendor_centroids": false}`,
+//!   queues a job and answers `202 Accepted` with its id, or `503 Service Unavailable`
+//!   when the queue is full. The ion list is read from the ion lists files of the
+//!   server's [`Config`], which also supplies `mass_accuracy` and `centroiding` when
+//!   they are left out, and the peak picking and integration settings.
+//! - `GET /jobs` lists all jobs, `GET /jobs/{id}` reports the status and progress of one,
+//!   including a `file_errors` list of the files that could not be processed and why.
+//! - `GET /jobs/{id}/results.json` and `GET /jobs/{id}/results.csv` download the
+//!   results of a finished job, with the provenance of the job, and
+//!   `GET /jobs/{id}/chromatograms.csv` the TIC/BPC chromatograms of its files.
+//! - `POST /jobs/{id}/cancel` cancels a queued or running job. The files a running job
+//!   completed before it was cancelled can still be downloaded.
+//! - `DELETE /jobs/{id}` removes a queued or finished job with its results.
+//!
+//! Jobs are run one at a time, in submission order; the files of a job are processed
+//! in parallel. Only the most recent finished jobs are kept, so the results of older
+//! ones are released even if they are never deleted.
+
+use crate::cancel::CancellationToken;
+use crate::centroiding::Centroiding;
+use crate::config::Config;
+use crate::error::Error;
+use crate::export::{write_chromatograms_csv, write_csv, write_json};
+use crate::ion_lists::IonLists;
+use crate::batch::process_batch;
+use crate::loading::FileResult;
+use crate::measurements::MSMeasurement;
+use crate::progress::{CallbackProgress, ProgressEvent};
+use crate::provenance::Provenance;
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Default number of jobs that may wait in the queue
+pub const DEFAULT_QUEUE_CAPACITY: usize = 16;
+
+/// Default number of finished jobs whose status and results are kept
+pub const DEFAULT_RETAINED_JOBS: usize = 64;
+
+/// A job as submitted with `POST /jobs`. Settings left out are filled in from the
+/// server's [`Config`] when the job is queued.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRequest {
+    pub files: Vec<String>,
+    pub ion_list: String,
+    #[serde(default)]
+    pub mass_accuracy: Option<f64>,
+    /// How profile spectra are centroided, see [`Centroiding`]
+    #[serde(default)]
+    pub centroiding: Option<Centroiding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled)
+    }
+}
+
+struct Job {
+    request: JobRequest,
+    status: JobStatus,
+    /// Number of files processed so far
+    completed: Arc<AtomicUsize>,
+    cancel: CancellationToken,
+    /// The measurements of the files that succeeded, with the provenance of the job
+    results: Option<Arc<(Vec<MSMeasurement>, Provenance)>>,
+    /// Files that failed, with the reason
+    file_errors: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+impl Job {
+    fn status_json(&self, id: u64) -> Value {
+        let mut status = json!({
+            "id": id,
+            "status": self.status.as_str(),
+            "completed": self.completed.load(Ordering::SeqCst),
+            "total": self.request.files.len(),
+            "ion_list": self.request.ion_list,
+        });
+        if let Some(error) = &self.error {
+            status["error"] = json!(error);
+        }
+        if !self.file_errors.is_empty() {
+            status["file_errors"] = self
+                .file_errors
+                .iter()
+                .map(|(file, error)| json!({"file": file, "error": error}))
+                .collect();
+        }
+        status
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: Arc<Config>,
+    jobs: Arc<Mutex<BTreeMap<u64, Job>>>,
+    /// How many finished jobs are kept
+    retained_jobs: usize,
+    next_id: Arc<AtomicUsize>,
+    queue: mpsc::Sender<u64>,
+}
+
+/// Serve the HTTP API on `listener` until the task is dropped, reading ion lists and
+/// default settings from `config`. Up to `queue_capacity` jobs wait in the queue, and
+/// the `retained_jobs` most recent finished jobs are kept.
+pub async fn serve_http(
+    listener: TcpListener,
+    queue_capacity: usize,
+    retained_jobs: usize,
+    config: Config,
+) -> io::Result<()> {
+    let (queue, receiver) = mpsc::channel(queue_capacity.max(1));
+    let state = AppState {
+        config: Arc::new(config),
+        jobs: Arc::new(Mutex::new(BTreeMap::new())),
+        retained_jobs,
+        next_id: Arc::new(AtomicUsize::new(1)),
+        queue,
+    };
+    tokio::spawn(run_jobs(receiver, state.clone()));
+
+    let app = Router::new()
+        .route("/jobs", post(submit_job).get(list_jobs))
+        .route("/jobs/{id}", get(job_status).delete(delete_job))
+        .route("/jobs/{id}/cancel", post(cancel_job))
+        .route("/jobs/{id}/results.json", get(job_results_json))
+        .route("/jobs/{id}/results.csv", get(job_results_csv))
+        .route("/jobs/{id}/chromatograms.csv", get(job_chromatograms_csv))
+        .with_state(state);
+    axum::serve(listener, app).await
+}
+
+/// Run queued jobs one after the other
+async fn run_jobs(mut receiver: mpsc::Receiver<u64>, state: AppState) {
+    while let Some(id) = receiver.recv().await {
+        let (request, completed, cancel) = {
+            let mut jobs = state.jobs.lock().unwrap();
+            let Some(job) = jobs.get_mut(&id) else {
+                continue;
+            };
+            // Jobs cancelled while queued are never started
+            if job.status == JobStatus::Cancelled {
+                continue;
+            }
+            job.status = JobStatus::Running;
+            (job.request.clone(), Arc::clone(&job.completed), job.cancel.clone())
+        };
+
+        let config = Arc::clone(&state.config);
+        let outcome = tokio::task::spawn_blocking(move || run_job(&request, &config, completed, &cancel)).await;
+
+        let mut jobs = state.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(&id) {
+            match outcome {
+                Ok(Ok((file_results, provenance))) => {
+                    job.status = if job.cancel.is_cancelled() {
+                        JobStatus::Cancelled
+                    } else {
+                        JobStatus::Done
+                    };
+                    let mut results = Vec::new();
+                    for FileResult { file, result } in file_results {
+                        match result {
+                            Ok(measurement) => results.push(measurement),
+                            // Files stopped by a cancel did not fail
+                            Err(Error::Cancelled) => (),
+                            Err(e) => job.file_errors.push((file, e.to_string())),
+                        }
+                    }
+                    job.results = Some(Arc::new((results, provenance)));
+                }
+                Ok(Err(error)) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(error);
+                }
+                Err(_) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some("The job failed unexpectedly".to_string());
+                }
+            }
+        }
+        evict_finished_jobs(&mut jobs, state.retained_jobs);
+    }
+}
+
+/// Drop the oldest finished jobs, with their results, beyond the `retained` most recent
+fn evict_finished_jobs(jobs: &mut BTreeMap<u64, Job>, retained: usize) {
+    let finished: Vec<u64> = jobs
+        .iter()
+        .filter(|(_, job)| job.status.is_finished())
+        .map(|(id, _)| *id)
+        .collect();
+    for id in &finished[..finished.len().saturating_sub(retained)] {
+        jobs.remove(id);
+    }
+}
+
+/// Run a job on the blocking pool, through the same batch path as the CLI
+fn run_job(
+    request: &JobRequest,
+    config: &Config,
+    completed: Arc<AtomicUsize>,
+    cancel: &CancellationToken,
+) -> Result<(Vec<FileResult>, Provenance), String> {
+    let progress = Arc::new(CallbackProgress(move |event| {
+        if let ProgressEvent::FileFinished { .. } = event {
+            completed.fetch_add(1, Ordering::SeqCst);
+        }
+    }));
+    let ion_list = IonLists::from_files(&config.ion_lists.paths)
+        .and_then(|ion_lists| ion_lists.compounds(&request.ion_list))
+        .map_err(|e| e.to_string())?;
+    // The job's own settings, the rest from the configuration; scans are never kept
+    let mut config = config.clone();
+    config.tolerances.mass_accuracy = request.mass_accuracy.unwrap_or(config.tolerances.mass_accuracy);
+    config.centroiding = request.centroiding.unwrap_or(config.centroiding);
+    config.output.keep_scans = false;
+    let mut parameters = json!(request);
+    parameters["peak_picking"] = json!(config.peak_picking);
+    parameters["integration"] = json!(config.integration);
+    let mut provenance = Provenance::start(&parameters, &[&request.ion_list], &ion_list);
+    let results = tokio::runtime::Handle::current().block_on(process_batch(
+        &request.files,
+        ion_list,
+        &config,
+        None,
+        progress,
+        cancel.clone(),
+    ));
+    provenance.finish(&request.files);
+    Ok((results, provenance))
+}
+
+async fn submit_job(State(state): State<AppState>, Json(mut request): Json<JobRequest>) -> Response {
+    if request.files.is_empty() {
+        return error(StatusCode::BAD_REQUEST, "No files given");
+    }
+    // The job records the settings it runs with, e.g. for its provenance
+    request.mass_accuracy.get_or_insert(state.config.tolerances.mass_accuracy);
+    request.centroiding.get_or_insert(state.config.centroiding);
+    let id = state.next_id.fetch_add(1, Ordering::SeqCst) as u64;
+    let job = Job {
+        request,
+        status: JobStatus::Queued,
+        completed: Arc::new(AtomicUsize::new(0)),
+        cancel: CancellationToken::new(),
+        results: None,
+        file_errors: Vec::new(),
+        error: None,
+    };
+    let status = job.status_json(id);
+    state.jobs.lock().unwrap().insert(id, job);
+
+    if state.queue.try_send(id).is_err() {
+        state.jobs.lock().unwrap().remove(&id);
+        return error(StatusCode::SERVICE_UNAVAILABLE, "The job queue is full, try again later");
+    }
+    (StatusCode::ACCEPTED, Json(status)).into_response()
+}
+
+async fn list_jobs(State(state): State<AppState>) -> Json<Value> {
+    let jobs = state.jobs.lock().unwrap();
+    Json(json!({
+        "jobs": jobs.iter().map(|(id, job)| job.status_json(*id)).collect::<Vec<_>>()
+    }))
+}
+
+async fn job_status(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    match state.jobs.lock().unwrap().get(&id) {
+        Some(job) => Json(job.status_json(id)).into_response(),
+        None => error(StatusCode::NOT_FOUND, "No such job"),
+    }
+}
+
+async fn cancel_job(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    let mut jobs = state.jobs.lock().unwrap();
+    let Some(job) = jobs.get_mut(&id) else {
+        return error(StatusCode::NOT_FOUND, "No such job");
+    };
+    match job.status {
+        JobStatus::Queued => job.status = JobStatus::Cancelled,
+        // The worker marks the job as cancelled once its current files are stopped
+        JobStatus::Running => (),
+        _ => return error(StatusCode::CONFLICT, &format!("The job is {}", job.status.as_str())),
+    }
+    job.cancel.cancel();
+    let status = job.status_json(id);
+    evict_finished_jobs(&mut jobs, state.retained_jobs);
+    Json(status).into_response()
+}
+
+async fn delete_job(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    let mut jobs = state.jobs.lock().unwrap();
+    let Some(job) = jobs.get(&id) else {
+        return error(StatusCode::NOT_FOUND, "No such job");
+    };
+    if job.status == JobStatus::Running {
+        return error(StatusCode::CONFLICT, "The job is running, cancel it first");
+    }
+    // A queued job is skipped by the worker once it is gone
+    job.cancel.cancel();
+    jobs.remove(&id);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn job_results_json(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    job_results(&state, id, "application/json", |body, (results, provenance)| {
+        write_json(body, results, provenance)
+    })
+}
+
+async fn job_results_csv(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    job_results(&state, id, "text/csv", |body, (results, provenance)| {
+        write_csv(body, results, provenance)
+    })
+}
+
+async fn job_chromatograms_csv(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    job_results(&state, id, "text/csv", |body, (results, provenance)| {
+        write_chromatograms_csv(body, results, provenance)
+    })
+}
+
+fn job_results(
+    state: &AppState,
+    id: u64,
+    content_type: &'static str,
+    write: impl Fn(&mut Vec<u8>, &(Vec<MSMeasurement>, Provenance)) -> io::Result<()>,
+) -> Response {
+    let results = match state.jobs.lock().unwrap().get(&id) {
+        None => return error(StatusCode::NOT_FOUND, "No such job"),
+        Some(job) => match &job.results {
+            Some(results) => Arc::clone(results),
+            None => return error(StatusCode::CONFLICT, &format!("The job is {}", job.status.as_str())),
+        },
+    };
+    let mut body = Vec::new();
+    if let Err(e) = write(&mut body, &results) {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{synthetic_run, write_mzml};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Send one HTTP/1.1 request and return the status code and body
+    async fn request(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, String) {
+        let body = body.map(|b| b.to_string()).unwrap_or_default();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    /// Poll a job until it has finished and return its status
+    async fn wait_for_job(addr: SocketAddr, id: u64) -> Value {
+        let mut job_status = Value::Null;
+        for _ in 0..200 {
+            let (_, body) = request(addr, "GET", &format!("/jobs/{id}"), None).await;
+            job_status = serde_json::from_str(&body).unwrap();
+            if ["done", "failed", "cancelled"].iter().any(|status| job_status["status"] == *status) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(25)).await;
+        }
+        job_status
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_submit_poll_and_download() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(listener, DEFAULT_QUEUE_CAPACITY, DEFAULT_RETAINED_JOBS, Config::default()));
+
+        let path = write_mzml("http.mzML", &synthetic_run());
+        // A file that cannot be read fails on its own, without failing the job
+        let job = json!({"files": [path.to_str().unwrap(), "missing.mzML"], "ion_list": "scfas"});
+        let (status, body) = request(addr, "POST", "/jobs", Some(job)).await;
+        assert_eq!(status, 202);
+        let id = serde_json::from_str::<Value>(&body).unwrap()["id"].as_u64().unwrap();
+
+        let job_status = wait_for_job(addr, id).await;
+        assert_eq!(job_status["status"], "done");
+        assert_eq!(job_status["completed"], 2);
+        assert_eq!(job_status["file_errors"][0]["file"], "missing.mzML");
+
+        let (status, csv) = request(addr, "GET", &format!("/jobs/{id}/results.csv"), None).await;
+        assert_eq!(status, 200);
+        assert!(csv.starts_with("# backend_version: "));
+        assert!(csv.contains(&format!("0,{},Acetate,Acetate-(-),59.0139,0.5,25500,", path.display())));
+        let (status, csv) = request(addr, "GET", &format!("/jobs/{id}/chromatograms.csv"), None).await;
+        assert_eq!(status, 200);
+        assert_eq!(csv.lines().filter(|line| line.starts_with(&format!("0,{},,,,", path.display()))).count(), 10);
+        let (status, body) = request(addr, "GET", &format!("/jobs/{id}/results.json"), None).await;
+        assert_eq!(status, 200);
+        let results: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(results["results"][0]["compounds"].as_array().unwrap().len(), 4);
+        assert_eq!(results["provenance"]["parameters"]["ion_list"], "scfas");
+        assert_eq!(results["provenance"]["files"][1]["path"], "missing.mzML");
+
+        let (status, _) = request(addr, "GET", "/jobs/999", None).await;
+        assert_eq!(status, 404);
+        let (status, _) = request(addr, "POST", "/jobs", Some(json!({"files": [], "ion_list": "scfas"}))).await;
+        assert_eq!(status, 400);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_finished_jobs_are_evicted_and_deleted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(listener, DEFAULT_QUEUE_CAPACITY, 1, Config::default()));
+
+        let path = write_mzml("http_retention.mzML", &synthetic_run());
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let job = json!({"files": [path.to_str().unwrap()], "ion_list": "scfas"});
+            let (_, body) = request(addr, "POST", "/jobs", Some(job)).await;
+            let id = serde_json::from_str::<Value>(&body).unwrap()["id"].as_u64().unwrap();
+            assert_eq!(wait_for_job(addr, id).await["status"], "done");
+            ids.push(id);
+        }
+        // Only the most recent finished job is kept
+        let (status, _) = request(addr, "GET", &format!("/jobs/{}", ids[0]), None).await;
+        assert_eq!(status, 404);
+        let (status, _) = request(addr, "GET", &format!("/jobs/{}/results.csv", ids[1]), None).await;
+        assert_eq!(status, 200);
+
+        let (status, _) = request(addr, "DELETE", &format!("/jobs/{}", ids[1]), None).await;
+        assert_eq!(status, 204);
+        let (status, _) = request(addr, "GET", &format!("/jobs/{}", ids[1]), None).await;
+        assert_eq!(status, 404);
+        let (status, _) = request(addr, "DELETE", &format!("/jobs/{}", ids[1]), None).await;
+        assert_eq!(status, 404);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_jobs_integrate_like_the_cli() {
+        let mut config = Config::default();
+        config.peak_picking.min_intensity = 2500.0;
+        config.integration.rt_range = Some((0.3, 0.8));
+        let path = write_mzml("http_integrated.mzML", &synthetic_run());
+        let files = [path.to_str().unwrap().to_string()];
+        let ion_list = IonLists::from_files(&config.ion_lists.paths).unwrap().compounds("scfas").unwrap();
+        let batch = process_batch(&files, ion_list, &config, None, Arc::new(crate::NoProgress), CancellationToken::new()).await;
+        let expected = json!(batch[0].result.as_ref().unwrap().xics);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(listener, DEFAULT_QUEUE_CAPACITY, DEFAULT_RETAINED_JOBS, config));
+        let (_, body) = request(addr, "POST", "/jobs", Some(json!({"files": files, "ion_list": "scfas"}))).await;
+        let id = serde_json::from_str::<Value>(&body).unwrap()["id"].as_u64().unwrap();
+        assert_eq!(wait_for_job(addr, id).await["status"], "done");
+        let (_, body) = request(addr, "GET", &format!("/jobs/{id}/results.json"), None).await;
+        let results: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(results["results"][0]["compounds"], expected);
+        assert_eq!(results["results"][0]["compounds"][1]["ions"]["59.0139"]["MS Intensity"], 19000.0);
+        assert_eq!(results["provenance"]["parameters"]["integration"]["rt_range"], json!([0.3, 0.8]));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cancelled_job_keeps_completed_files() {
+        // Files large enough that the job is still running when it is cancelled
+        let mzs: Vec<f64> = (0..500).map(|i| 50.0 + i as f64 * 0.1).collect();
+        let intensities: Vec<f32> = (0..500).map(|i| (i % 50) as f32 + 1.0).collect();
+        let spectra: Vec<_> = (0..300)
+            .map(|i| crate::test_utils::synthetic_spectrum(i, 1, i as f64 * 0.01, &mzs, &intensities))
+            .collect();
+        let path = write_mzml("http_cancel.mzML", &spectra);
+        let total = rayon::current_num_threads() * 8 + 8;
+        let files = vec![path.to_str().unwrap(); total];
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(listener, DEFAULT_QUEUE_CAPACITY, DEFAULT_RETAINED_JOBS, Config::default()));
+        let (_, body) = request(addr, "POST", "/jobs", Some(json!({"files": files, "ion_list": "scfas"}))).await;
+        let id = serde_json::from_str::<Value>(&body).unwrap()["id"].as_u64().unwrap();
+        loop {
+            let (_, body) = request(addr, "GET", &format!("/jobs/{id}"), None).await;
+            if serde_json::from_str::<Value>(&body).unwrap()["completed"].as_u64().unwrap() > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        let (status, _) = request(addr, "POST", &format!("/jobs/{id}/cancel"), None).await;
+        assert_eq!(status, 200);
+
+        let job_status = wait_for_job(addr, id).await;
+        assert_eq!(job_status["status"], "cancelled");
+        assert!(job_status.get("file_errors").is_none());
+        let (status, body) = request(addr, "GET", &format!("/jobs/{id}/results.json"), None).await;
+        assert_eq!(status, 200);
+        let results: Value = serde_json::from_str(&body).unwrap();
+        let completed = results["results"].as_array().unwrap().len();
+        assert!(completed > 0 && completed < total, "{completed} of {total} files");
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,426 @@
+//! Ion lists: named sets of compounds and the m/z values of their ions.
+//!
+//! Ion lists are read from JSON files mapping list names to compounds:
+//!
+//! ```json
+//! {"scfas": {"Acetate": {"ions": [59.0139, 61.0284], "info": ["Acetate-(+)", "Acetate-(-)"]}}}
+//! ```
+//!
+//! Unless files are given explicitly, the first of these is used:
+//!
+//! 1. `ion_lists.json` in the working directory
+//! 2. `ion_lists.json` in `$LCMSPECTOR_CONFIG_DIR`, or in `lcmspector` in the user
+//!    configuration directory
+//! 3. the ion lists built into the library
+//!
+//! Several files can be merged into one [`IonLists`], and several lists combined into
+//! one set of compounds; a name defined twice is an error rather than silently
+//! shadowing the other definition. Compounds keep the order they are written in, so
+//! results list them in the same order on every run.
+//!
+//! Ion lists can also be kept as CSV or TSV target tables, see [`crate::ion_table`];
+//! files ending in `.csv` or `.tsv` are read as tables.
+//!
+//! Files are parsed against a typed schema, so a non-numeric ion or a misspelt field
+//! fails with its position in the file. [`IonLists::validate`] then checks what the
+//! schema cannot express, such as `ions` and `info` having the same length.
+
+use crate::error::{Error, Result};
+use crate::ion_table;
+use crate::measurements::Compound;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the ion lists file searched for in the working and configuration directories
+pub const ION_LISTS_FILE: &str = "ion_lists.json";
+
+/// How the ion lists built into the library are named in messages
+pub const BUILT_IN: &str = "<built-in>";
+
+/// The ion lists shipped with the source, used when no ion lists file is found
+const BUILT_IN_ION_LISTS: &str = include_str!("../ion_lists.json");
+
+/// A compound as written in an ion lists file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompoundEntry {
+    /// m/z of every ion
+    #[serde(default)]
+    pub ions: Vec<f64>,
+    /// Label of every ion, paired with `ions` by position; may be left empty
+    #[serde(default)]
+    pub info: Vec<String>,
+    /// Molecular formula
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formula: Option<String>,
+    /// Adduct of every ion, e.g. `[M+H]+`, paired with `ions` by position; may be left empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adducts: Vec<String>,
+    /// Expected retention time in minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rt: Option<f64>,
+}
+
+/// The compounds of one ion list by name, in the order of its file
+pub type IonList = IndexMap<String, CompoundEntry>;
+
+/// A set of named ion lists, possibly read from several files
+#[derive(Debug, Clone, Default)]
+pub struct IonLists {
+    /// Every list by name, with where it was read from
+    lists: BTreeMap<String, (String, IonList)>,
+}
+
+/// What is wrong with a compound of an ion list
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The compound has no ions, so nothing is extracted for it
+    NoIons,
+    /// `info` has labels, but not one per ion
+    InfoLengthMismatch { ions: usize, info: usize },
+    /// `adducts` has adducts, but not one per ion
+    AdductLengthMismatch { ions: usize, adducts: usize },
+    /// The same m/z is listed twice
+    DuplicateIon(f64),
+    /// The m/z is zero or negative
+    InvalidMass(f64),
+}
+
+impl Problem {
+    /// Errors make the results of the compound unreliable, warnings are worth a look
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Problem::NoIons)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NoIons => write!(f, "has no ions"),
+            Problem::InfoLengthMismatch { ions, info } => write!(f, "has {ions} ions but {info} info labels"),
+            Problem::AdductLengthMismatch { ions, adducts } => write!(f, "has {ions} ions but {adducts} adducts"),
+            Problem::DuplicateIon(mz) => write!(f, "lists ion {mz} more than once"),
+            Problem::InvalidMass(mz) => write!(f, "has an ion with a mass of {mz}"),
+        }
+    }
+}
+
+/// A problem found by [`IonLists::validate`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct IonListIssue {
+    pub list: String,
+    pub compound: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for IonListIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = if self.problem.is_error() { "error" } else { "warning" };
+        write!(f, "{}: {} in {} {}", severity, self.compound, self.list, self.problem)
+    }
+}
+
+/// `$LCMSPECTOR_CONFIG_DIR` if set, otherwise `lcmspector` in the user configuration directory
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("LCMSPECTOR_CONFIG_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|base| base.join("lcmspector"))
+}
+
+/// The ion lists file found in the working or configuration directory, if any
+pub fn find_ion_lists_file() -> Option<PathBuf> {
+    let candidates = [Some(PathBuf::from(ION_LISTS_FILE)), config_dir().map(|dir| dir.join(ION_LISTS_FILE))];
+    candidates.into_iter().flatten().find(|path| path.is_file())
+}
+
+impl IonLists {
+    /// The ion lists built into the library
+    pub fn built_in() -> Self {
+        Self::from_json(BUILT_IN, BUILT_IN_ION_LISTS).expect("The built-in ion lists are valid")
+    }
+
+    /// Read the ion lists file at `path`, or a target table if it ends in `.csv` or `.tsv`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if ion_table::is_table(path) {
+            return ion_table::read_ion_table(path, None);
+        }
+        let path = path.display().to_string();
+        let text = fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+        Self::from_json(&path, &text)
+    }
+
+    /// Ion lists that were all read from `source`
+    pub fn from_lists(source: &str, lists: BTreeMap<String, IonList>) -> Self {
+        let lists = lists
+            .into_iter()
+            .map(|(name, compounds)| (name, (source.to_string(), compounds)))
+            .collect();
+        IonLists { lists }
+    }
+
+    /// Parse ion lists from JSON text, naming them after `source` in messages
+    pub fn from_json(source: &str, text: &str) -> Result<Self> {
+        let parsed: BTreeMap<String, IonList> =
+            serde_json::from_str(text).map_err(|e| Error::IonListsJson {
+                path: source.to_string(),
+                source: e,
+            })?;
+        Ok(Self::from_lists(source, parsed))
+    }
+
+    /// The lists `names`, or all lists if `names` is empty, in the ion lists file format
+    pub fn to_json<S: AsRef<str>>(&self, names: &[S]) -> Result<String> {
+        let mut selected: BTreeMap<&str, &IonList> = BTreeMap::new();
+        if names.is_empty() {
+            selected.extend(self.lists.iter().map(|(name, (_, list))| (name.as_str(), list)));
+        }
+        for name in names {
+            selected.insert(name.as_ref(), self.get(name.as_ref())?);
+        }
+        Ok(serde_json::to_string_pretty(&selected).expect("Ion lists serialize to JSON"))
+    }
+
+    /// The ion lists file found by [`find_ion_lists_file`], or the built-in ion lists
+    pub fn locate() -> Result<Self> {
+        match find_ion_lists_file() {
+            Some(path) => Self::from_file(path),
+            None => Ok(Self::built_in()),
+        }
+    }
+
+    /// Read and merge the ion lists of all `paths`, or [`locate`](Self::locate) them
+    /// if no path is given
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        if paths.is_empty() {
+            return Self::locate();
+        }
+        let mut ion_lists = IonLists::default();
+        for path in paths {
+            ion_lists.merge(Self::from_file(path)?)?;
+        }
+        Ok(ion_lists)
+    }
+
+    /// Add the lists of `other`.
+    ///
+    /// Fails with [`Error::DuplicateIonList`] if both define a list of the same name.
+    pub fn merge(&mut self, other: IonLists) -> Result<()> {
+        for (name, (source, compounds)) in other.lists {
+            if let Some((first, _)) = self.lists.get(&name) {
+                return Err(Error::DuplicateIonList {
+                    name,
+                    first: first.clone(),
+                    second: source,
+                });
+            }
+            self.lists.insert(name, (source, compounds));
+        }
+        Ok(())
+    }
+
+    /// The names of all lists, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.lists.keys().map(String::as_str)
+    }
+
+    /// Where the list `name` was read from: a file path or [`BUILT_IN`]
+    pub fn source(&self, name: &str) -> Option<&str> {
+        self.lists.get(name).map(|(source, _)| source.as_str())
+    }
+
+    /// The list `name` as written in its file.
+    ///
+    /// Fails with [`Error::UnknownIonList`], suggesting the closest name, if there is no
+    /// such list.
+    pub fn get(&self, name: &str) -> Result<&IonList> {
+        match self.lists.get(name) {
+            Some((_, list)) => Ok(list),
+            None => Err(Error::UnknownIonList {
+                name: name.to_string(),
+                suggestion: closest_name(name, self.names()).map(String::from),
+            }),
+        }
+    }
+
+    /// The compounds of the list `name`.
+    ///
+    /// Fails with [`Error::UnknownIonList`] if there is no such list.
+    pub fn compounds(&self, name: &str) -> Result<Vec<Compound>> {
+        let compounds = self
+            .get(name)?
+            .iter()
+            .map(|(name, entry)| Compound::from_ion_list_entry(name.clone(), entry))
+            .collect();
+        Ok(compounds)
+    }
+
+    /// Check every compound of every list, returning the problems found in list and
+    /// compound order
+    pub fn validate(&self) -> Vec<IonListIssue> {
+        let mut issues = Vec::new();
+        for (list_name, (_, list)) in &self.lists {
+            for (compound, entry) in list {
+                for problem in entry_problems(entry) {
+                    issues.push(IonListIssue {
+                        list: list_name.clone(),
+                        compound: compound.clone(),
+                        problem,
+                    });
+                }
+            }
+        }
+        issues
+    }
+
+    /// The compounds of several lists combined, in the order the lists are given.
+    ///
+    /// Fails with [`Error::DuplicateCompound`] if a compound is in more than one of them.
+    pub fn combined<S: AsRef<str>>(&self, names: &[S]) -> Result<Vec<Compound>> {
+        let mut combined: Vec<Compound> = Vec::new();
+        let mut list_of: BTreeMap<String, &str> = BTreeMap::new();
+        for name in names {
+            let name = name.as_ref();
+            for compound in self.compounds(name)? {
+                if let Some(first_list) = list_of.insert(compound.name.clone(), name) {
+                    return Err(Error::DuplicateCompound {
+                        name: compound.name,
+                        first_list: first_list.to_string(),
+                        second_list: name.to_string(),
+                    });
+                }
+                combined.push(compound);
+            }
+        }
+        Ok(combined)
+    }
+}
+
+/// The problems of a single compound
+fn entry_problems(entry: &CompoundEntry) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if entry.ions.is_empty() {
+        problems.push(Problem::NoIons);
+    }
+    if !entry.info.is_empty() && entry.info.len() != entry.ions.len() {
+        problems.push(Problem::InfoLengthMismatch {
+            ions: entry.ions.len(),
+            info: entry.info.len(),
+        });
+    }
+    if !entry.adducts.is_empty() && entry.adducts.len() != entry.ions.len() {
+        problems.push(Problem::AdductLengthMismatch {
+            ions: entry.ions.len(),
+            adducts: entry.adducts.len(),
+        });
+    }
+    for (i, &mz) in entry.ions.iter().enumerate() {
+        if mz <= 0.0 {
+            problems.push(Problem::InvalidMass(mz));
+        }
+        if entry.ions[..i].contains(&mz) {
+            problems.push(Problem::DuplicateIon(mz));
+        }
+    }
+    problems
+}
+
+/// The name closest to `name`, if one is close enough to be a likely typo
+fn closest_name<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    candidates
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    #[test]
+    fn test_merge_and_combine_ion_lists() {
+        let built_in = IonLists::built_in();
+        assert_eq!(built_in.source("scfas"), Some(BUILT_IN));
+        let combined = built_in.combined(&["scfas", "fatty_acids"]).unwrap();
+        assert_eq!(combined.len(), 4 + 3);
+        // Compounds keep the order of the file rather than being sorted
+        let names: Vec<&str> = combined[..4].iter().map(|compound| compound.name.as_str()).collect();
+        assert_eq!(names, ["Formate", "Acetate", "Butyrate", "Propionate"]);
+        // Combining a list with itself duplicates all of its compounds
+        let duplicate = built_in.combined(&["scfas", "scfas"]);
+        assert!(matches!(duplicate, Err(Error::DuplicateCompound { first_list, .. }) if first_list == "scfas"));
+
+        let path = temp_path("extra_ion_lists.json");
+        fs::write(&path, r#"{"extra": {"Lactate": {"ions": [89.0244], "info": ["Lactate-(-)"]}}}"#).unwrap();
+        let mut ion_lists = IonLists::from_files(&[&path]).unwrap();
+        assert_eq!(ion_lists.names().collect::<Vec<_>>(), ["extra"]);
+        ion_lists.merge(IonLists::built_in()).unwrap();
+        assert_eq!(ion_lists.combined(&["extra", "scfas"]).unwrap().len(), 5);
+        assert!(matches!(
+            ion_lists.merge(IonLists::from_file(&path).unwrap()),
+            Err(Error::DuplicateIonList { name, .. }) if name == "extra"
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_validate_ion_lists() {
+        let ion_lists = IonLists::from_json(
+            "test",
+            r#"{"acids": {
+                "Good": {"ions": [59.0139, 61.0284], "info": ["(+)", "(-)"]},
+                "Unlabelled": {"ions": [87.04515]},
+                "Placeholder": {"ions": [], "info": []},
+                "Broken": {"ions": [45.0, 45.0, -1.0], "info": ["a", "b"]}
+            }}"#,
+        )
+        .unwrap();
+        let issues = ion_lists.validate();
+        assert_eq!(
+            issues.iter().map(|issue| (issue.compound.as_str(), issue.problem.clone())).collect::<Vec<_>>(),
+            [
+                ("Placeholder", Problem::NoIons),
+                ("Broken", Problem::InfoLengthMismatch { ions: 3, info: 2 }),
+                ("Broken", Problem::DuplicateIon(45.0)),
+                ("Broken", Problem::InvalidMass(-1.0)),
+            ]
+        );
+        assert!(!issues[0].problem.is_error());
+
+        // Non-numeric ions and misspelt fields are rejected with their position
+        let invalid = IonLists::from_json("test", r#"{"acids": {"Bad": {"ions": ["59.01"]}}}"#);
+        assert!(matches!(invalid, Err(Error::IonListsJson { .. })));
+        assert!(IonLists::from_json("test", r#"{"acids": {"Bad": {"ion": [59.01]}}}"#).is_err());
+
+        let unknown = ion_lists.compounds("Acids");
+        assert!(matches!(unknown, Err(Error::UnknownIonList { suggestion: Some(s), .. }) if s == "acids"));
+        let unknown = ion_lists.compounds("terpenoids");
+        assert!(matches!(unknown, Err(Error::UnknownIonList { suggestion: None, .. })));
+    }
+}
@@ -0,0 +1,277 @@
+//! Target tables: ion lists kept as spreadsheets, with one row per ion.
+//!
+//! The first row names the columns, in any order and case: `compound` and `m/z` are
+//! required, `list`, `formula`, `adduct`, `label` and `rt` are optional. The rows of a
+//! compound, consecutive or not, are its ions in order; a row without an m/z declares
+//! a compound without ions.
+//!
+//! ```text
+//! list,compound,formula,adduct,m/z,label,rt
+//! scfas,Acetate,C2H4O2,[M+H]+,61.0284,Acetate-(+),1.2
+//! scfas,Acetate,C2H4O2,[M-H]-,59.0139,Acetate-(-),1.2
+//! ```
+//!
+//! Columns are separated by tabs in `.tsv` files and by commas in `.csv` files, or by
+//! semicolons if the header has them but no comma, as spreadsheets write CSV in
+//! locales with a decimal comma; numbers may then use a decimal comma too.
+
+use crate::error::{Error, Result};
+use crate::ion_lists::{IonList, IonLists};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The columns written by [`write_ion_table`], in order
+const TABLE_HEADER: [&str; 7] = ["list", "compound", "formula", "adduct", "m/z", "label", "rt"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Column {
+    List,
+    Compound,
+    Formula,
+    Adduct,
+    Mz,
+    Label,
+    Rt,
+}
+
+impl Column {
+    fn from_header(header: &str) -> Option<Self> {
+        match header.trim().to_lowercase().as_str() {
+            "list" | "ion list" | "ion_list" => Some(Column::List),
+            "compound" | "name" => Some(Column::Compound),
+            "formula" => Some(Column::Formula),
+            "adduct" => Some(Column::Adduct),
+            "m/z" | "mz" | "mass" => Some(Column::Mz),
+            "label" | "info" => Some(Column::Label),
+            "rt" | "retention time" | "rt (min)" => Some(Column::Rt),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `path` is read as a target table rather than an ion lists JSON file
+pub fn is_table(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv") || ext.eq_ignore_ascii_case("tsv"))
+}
+
+/// Read the target table at `path`.
+///
+/// Rows without a `list` column go to the list `list`, or to a list named after the
+/// file if it is not given.
+pub fn read_ion_table<P: AsRef<Path>>(path: P, list: Option<&str>) -> Result<IonLists> {
+    let path = path.as_ref();
+    let display = path.display().to_string();
+    let text = fs::read_to_string(path).map_err(|e| Error::io(&display, e))?;
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    parse_ion_table(&display, &text, list.unwrap_or(&stem))
+}
+
+/// Parse a target table, naming it after `source` in messages
+pub fn parse_ion_table(source: &str, text: &str, default_list: &str) -> Result<IonLists> {
+    let table_error = |line: usize, message: String| Error::IonTable {
+        path: source.to_string(),
+        line,
+        message,
+    };
+    let mut lines = text
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let Some((header_line, header)) = lines.next() else {
+        return Err(table_error(1, "the table is empty".to_string()));
+    };
+    let delimiter = if header.contains('\t') {
+        '\t'
+    } else if header.contains(';') && !header.contains(',') {
+        ';'
+    } else {
+        ','
+    };
+    let columns: Vec<Option<Column>> = split_record(header, delimiter)
+        .map_err(|message| table_error(header_line, message))?
+        .iter()
+        .map(|header| Column::from_header(header))
+        .collect();
+    for required in [Column::Compound, Column::Mz] {
+        if !columns.contains(&Some(required)) {
+            let name = if required == Column::Mz { "m/z" } else { "compound" };
+            return Err(table_error(header_line, format!("there is no {} column", name)));
+        }
+    }
+
+    let mut lists: BTreeMap<String, IonList> = BTreeMap::new();
+    for (line, record) in lines {
+        let fields = split_record(record, delimiter).map_err(|message| table_error(line, message))?;
+        let field = |column: Column| {
+            columns
+                .iter()
+                .position(|c| *c == Some(column))
+                .and_then(|i| fields.get(i))
+                .map(|field| field.trim())
+                .filter(|field| !field.is_empty())
+        };
+        let number = |column: Column| -> Result<Option<f64>> {
+            field(column)
+                .map(|value| {
+                    let value = if delimiter == ';' { value.replace(',', ".") } else { value.to_string() };
+                    value
+                        .parse()
+                        .map_err(|_| table_error(line, format!("{} is not a number", value)))
+                })
+                .transpose()
+        };
+
+        let Some(compound) = field(Column::Compound) else {
+            return Err(table_error(line, "the compound is missing".to_string()));
+        };
+        let list = field(Column::List).unwrap_or(default_list);
+        let entry = lists
+            .entry(list.to_string())
+            .or_default()
+            .entry(compound.to_string())
+            .or_default();
+
+        if let Some(formula) = field(Column::Formula) {
+            if entry.formula.as_deref().is_some_and(|f| f != formula) {
+                return Err(table_error(line, format!("{} has more than one formula", compound)));
+            }
+            entry.formula = Some(formula.to_string());
+        }
+        if let Some(rt) = number(Column::Rt)? {
+            if entry.rt.is_some_and(|r| r != rt) {
+                return Err(table_error(line, format!("{} has more than one retention time", compound)));
+            }
+            entry.rt = Some(rt);
+        }
+        if let Some(mz) = number(Column::Mz)? {
+            entry.ions.push(mz);
+            entry.info.push(field(Column::Label).unwrap_or_default().to_string());
+            entry.adducts.push(field(Column::Adduct).unwrap_or_default().to_string());
+        }
+    }
+
+    // Labels and adducts are left out of the ion list when none is given
+    for entry in lists.values_mut().flat_map(|list| list.values_mut()) {
+        if entry.info.iter().all(String::is_empty) {
+            entry.info.clear();
+        }
+        if entry.adducts.iter().all(String::is_empty) {
+            entry.adducts.clear();
+        }
+    }
+    Ok(IonLists::from_lists(source, lists))
+}
+
+/// Write ion lists as a target table with one row per ion, separating columns with
+/// `delimiter`
+pub fn write_ion_table<W: Write>(mut writer: W, lists: &[(&str, &IonList)], delimiter: char) -> io::Result<()> {
+    let separator = delimiter.to_string();
+    writeln!(writer, "{}", TABLE_HEADER.join(&separator))?;
+    for (list, compounds) in lists {
+        for (compound, entry) in compounds.iter() {
+            let mut write_row = |mz: Option<f64>, label: &str, adduct: &str| {
+                let fields = [
+                    list.to_string(),
+                    compound.clone(),
+                    entry.formula.clone().unwrap_or_default(),
+                    adduct.to_string(),
+                    mz.map(|mz| mz.to_string()).unwrap_or_default(),
+                    label.to_string(),
+                    entry.rt.map(|rt| rt.to_string()).unwrap_or_default(),
+                ];
+                let fields: Vec<String> = fields.iter().map(|field| escape(field, delimiter)).collect();
+                writeln!(writer, "{}", fields.join(&separator))
+            };
+            if entry.ions.is_empty() {
+                write_row(None, "", "")?;
+            }
+            for (i, mz) in entry.ions.iter().enumerate() {
+                let label = entry.info.get(i).map(String::as_str).unwrap_or_default();
+                let adduct = entry.adducts.get(i).map(String::as_str).unwrap_or_default();
+                write_row(Some(*mz), label, adduct)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Split a table row into its fields, unquoting quoted ones
+fn split_record(record: &str, delimiter: char) -> std::result::Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = record.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("a quoted field is not closed".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Quote a field if it contains the delimiter, quotes or line breaks
+fn escape(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_round_trip() {
+        let table = "\u{feff}Compound;m/z;Label;RT;Formula\n\
+                     Acetate;59,0139;Acetate-(-);1,2;C2H4O2\n\
+                     \"1,2,4-trimethylbenzene\";105;;;\n\
+                     Acetate;61,0284;Acetate-(+);;\n\
+                     Rutin;;;;\n";
+        let ion_lists = parse_ion_table("targets.csv", table, "targets").unwrap();
+        let list = ion_lists.get("targets").unwrap();
+        let acetate = &list["Acetate"];
+        assert_eq!(acetate.ions, [59.0139, 61.0284]);
+        assert_eq!(acetate.info, ["Acetate-(-)", "Acetate-(+)"]);
+        assert_eq!(acetate.rt, Some(1.2));
+        assert!(acetate.adducts.is_empty());
+        assert!(list["1,2,4-trimethylbenzene"].info.is_empty());
+        assert!(list["Rutin"].ions.is_empty());
+        assert_eq!(ion_lists.compounds("targets").unwrap().len(), 3);
+
+        // Table -> JSON -> table gives back the same ion lists
+        let json = IonLists::from_json("targets.json", &ion_lists.to_json::<&str>(&[]).unwrap()).unwrap();
+        assert_eq!(json.get("targets").unwrap(), list);
+        let mut written = Vec::new();
+        write_ion_table(&mut written, &[("targets", json.get("targets").unwrap())], ',').unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(written.lines().count(), 1 + list.values().map(|entry| entry.ions.len().max(1)).sum::<usize>());
+        assert_eq!(parse_ion_table("targets.csv", &written, "other").unwrap().get("targets").unwrap(), list);
+
+        let conflicting = "compound,m/z,rt\nAcetate,59.0139,1.2\nAcetate,61.0284,1.5\n";
+        assert!(matches!(parse_ion_table("t.csv", conflicting, "t"), Err(Error::IonTable { line: 3, .. })));
+        assert!(parse_ion_table("t.csv", "compound,mass\nAcetate,abc\n", "t").is_err());
+        assert!(parse_ion_table("t.csv", "compound,label\nAcetate,x\n", "t").is_err());
+    }
+}
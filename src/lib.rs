@@ -0,0 +1,111 @@
+//! LCMSpector backend: loading of LC-MS measurements, extraction of ion
+//! chromatograms (XICs), quantification against calibration standards and export
+//! of the results.
+//!
+//! The API follows the stages of a typical analysis:
+//!
+//! - **load**: [`detect_format`], [`open_spectra`] and [`load_selected_ms_scans`]
+//!   read mzML, mzXML and MGF files, gzip-compressed or not; [`ScanCache`] keeps
+//!   decoded spectra on disk between runs. Profile spectra are reduced to peaks as set
+//!   by [`Centroiding`]; [`centroid_spectrum`] does the same for a single spectrum.
+//! - **ion lists**: [`IonLists`] reads, merges, combines and validates the ion lists
+//!   naming the compounds to extract, from JSON files or CSV/TSV target tables
+//!   ([`read_ion_table`]); [`load_ion_lists`] loads a single list.
+//! - **extract**: [`process_file_streaming`] and [`process_files_in_parallel`] build
+//!   the XICs of an ion list while streaming spectra, [`XicBuilder`] does the same
+//!   for spectra coming from elsewhere. Streaming also computes the TIC and BPC of the
+//!   MS1 scans; [`compute_chromatograms`] and [`ChromatogramBuilder`] compute them
+//!   per polarity and m/z range.
+//! - **quantify**: [`calibrate`] fits a [`CalibrationCurve`] per compound from
+//!   measured standards and [`quantify`] applies them to a sample.
+//! - **export**: [`write_json`], [`write_csv`] and [`export_results`] write the
+//!   per-ion results with the [`Provenance`] of the run, [`write_chromatograms_csv`]
+//!   the chromatograms; [`write_quantities_csv`], [`write_info_table`] and
+//!   [`write_tic_csv`] write the tables of the `quant`, `info` and `tic` commands.
+//! - **spectra**: [`spectrum_at`] takes the scan closest to a retention time from a
+//!   measurement, [`combined_spectrum`] averages or sums the scans of an RT range with
+//!   background subtraction; [`export_spectrum`] writes them as CSV or MGF.
+//! - **inspect**: [`summarize_file`] summarizes the instrument, scans and ranges of a
+//!   file before it is processed; [`read_file_header`] reads only its header.
+//!
+//! Loading and processing report their progress as [`ProgressEvent`]s to a
+//! [`ProgressReporter`]; pass [`NoProgress`] to ignore them. A [`CancellationToken`]
+//! stops them between spectra, from another thread or a signal handler. Failures are
+//! returned as an [`Error`]; batch functions return a [`FileResult`] per file, so one
+//! broken file does not fail the others.
+//!
+//! ```no_run
+//! use lcmspector_backend::{
+//!     calibrate, load_ion_lists, process_file_streaming, quantify, CancellationToken, Centroiding,
+//!     NoProgress,
+//! };
+//!
+//! # fn main() -> lcmspector_backend::Result<()> {
+//! let mut ion_list = load_ion_lists("scfas")?;
+//! let cancel = CancellationToken::new();
+//! let centroiding = Centroiding::default();
+//! let standard = process_file_streaming("std_10uM.mzML", &ion_list, 0.0001, centroiding, false, None, &NoProgress, &cancel)?;
+//! let blank = process_file_streaming("std_0uM.mzML", &ion_list, 0.0001, centroiding, false, None, &NoProgress, &cancel)?;
+//! calibrate(&mut ion_list, &[(0.0, &blank), (10.0, &standard)]);
+//!
+//! let sample = process_file_streaming("sample.mzML", &ion_list, 0.0001, centroiding, false, None, &NoProgress, &cancel)?;
+//! for quantity in quantify(&sample, &ion_list) {
+//!     println!("{}: {:?}", quantity.compound, quantity.concentration);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod batch;
+pub mod cache;
+pub mod cancel;
+pub mod centroiding;
+pub mod config;
+pub mod error;
+pub mod export;
+pub mod http;
+pub mod ion_lists;
+pub mod ion_table;
+pub mod loading;
+pub mod measurements;
+pub mod metadata;
+pub mod mzxml;
+pub mod processing;
+pub mod progress;
+pub mod provenance;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod quantification;
+pub mod server;
+pub mod spectra;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use cache::ScanCache;
+pub use cancel::CancellationToken;
+pub use centroiding::{centroid_peaks, centroid_spectrum, CentroidMethod, Centroiding};
+pub use config::Config;
+pub use error::{Error, Result};
+pub use export::{
+    export_results, export_spectrum, results_to_json, write_chromatograms_csv, write_csv, write_info_table, write_json,
+    write_mgf, write_quantities_csv, write_spectrum_csv, write_tic_csv,
+};
+pub use ion_lists::{IonListIssue, IonLists};
+pub use ion_table::{read_ion_table, write_ion_table};
+pub use loading::{
+    detect_format, load_ion_lists, load_ion_lists_from, load_ms_scans, load_selected_ms_scans,
+    open_spectra, process_file_streaming, process_files_in_parallel, FileResult, MSFileFormat,
+    ScanSelection, SpectrumStream,
+};
+pub use measurements::{Compound, Ion, MSMeasurement, Polarity};
+pub use metadata::{read_file_header, summarize_file, summarize_files, FileHeader, FileSummary};
+pub use processing::{
+    compute_chromatograms, construct_xics, integrate_xics, Chromatogram, ChromatogramBuilder, ChromatogramFilter, XicBuilder,
+};
+pub use progress::{
+    CallbackProgress, JsonLinesProgress, NoProgress, ProgressEvent, ProgressReporter, TerminalProgress,
+};
+pub use provenance::Provenance;
+pub use quantification::{calibrate, quantify, CalibrationCurve, Quantity};
+pub use spectra::{combine_scans, combined_spectrum, nearest_scan, spectrum_at, Combine, CombineOptions, ExtractedSpectrum};
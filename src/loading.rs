@@ -1,16 +1,199 @@
+use crate::cache::{CacheWriter, ScanCache};
+use crate::cancel::CancellationToken;
+use crate::centroiding::{centroid_spectrum, Centroiding};
+use crate::error::{Error, Result};
+use crate::ion_lists::IonLists;
 use crate::measurements::Compound;
 use crate::measurements::MSMeasurement;
-use crate::processing::construct_xics;
+use crate::mzxml::MzXMLReader;
+use crate::processing::{ChromatogramBuilder, XicBuilder};
+use crate::progress::{NoProgress, ProgressEvent, ProgressReporter};
+use flate2::bufread::MultiGzDecoder;
+use mzdata::io::{DetailLevel, RandomAccessSpectrumIterator, SpectrumSource};
+use mzdata::meta::MSDataFileMetadata;
 use mzdata::spectrum::{MultiLayerSpectrum, SpectrumLike};
-use mzdata::MzMLReader;
+use mzdata::{MGFReader, MzMLReader};
 use rayon::iter::{ParallelIterator};
 use rayon::prelude::*;
-use serde_json::Value;
+use serde::Serialize;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::sync::Arc;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
+/// Mass spectrometry file formats accepted by `load_ms_scans`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MSFileFormat {
+    #[serde(rename = "mzML")]
+    MzML,
+    #[serde(rename = "mzXML")]
+    MzXML,
+    MGF,
+}
+
+impl fmt::Display for MSFileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MSFileFormat::MzML => write!(f, "mzML"),
+            MSFileFormat::MzXML => write!(f, "mzXML"),
+            MSFileFormat::MGF => write!(f, "MGF"),
+        }
+    }
+}
+
+/// Magic bytes at the start of every gzip member
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Shared slot for the first I/O error hit while streaming a file
+type ReadErrorSlot = Arc<Mutex<Option<io::Error>>>;
+
+/// An iterator over all spectra of a file, whatever format it is stored in
+/// and whether or not it is gzip-compressed.
+///
+/// The underlying readers stop iterating when they hit an I/O or parse error, so the
+/// error is kept aside and can be retrieved with [`SpectrumStream::take_error`] once
+/// the iterator is exhausted. A file that ends before all the spectra its header lists,
+/// or that yields no spectra at all, is reported the same way.
+pub struct SpectrumStream {
+    spectra: Box<dyn Iterator<Item = MultiLayerSpectrum> + Send>,
+    read_error: ReadErrorSlot,
+    /// The format of the whole file being read, for which running out of spectra
+    /// without reading any means it is damaged; `None` for partial reads and caches
+    format: Option<MSFileFormat>,
+    /// How many spectra the file says it holds, if it says so
+    expected: Option<u64>,
+    read: u64,
+    exhausted: bool,
+}
+
+impl SpectrumStream {
+    /// A stream that is not checked for completeness, e.g. over a window of a file
+    fn partial(spectra: Box<dyn Iterator<Item = MultiLayerSpectrum> + Send>) -> Self {
+        SpectrumStream {
+            spectra,
+            read_error: ReadErrorSlot::default(),
+            format: None,
+            expected: None,
+            read: 0,
+            exhausted: false,
+        }
+    }
+
+    /// The I/O or parse error that ended the stream early, if any
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.read_error.lock().unwrap().take()
+    }
+
+    /// Record why the stream ended early if the readers stopped without an error
+    fn check_complete(&self) {
+        let mut slot = self.read_error.lock().unwrap();
+        if slot.is_some() {
+            return;
+        }
+        if let Some(expected) = self.expected.filter(|expected| self.read < *expected) {
+            *slot = Some(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("the file is truncated or damaged: only {} of the {expected} spectra it lists could be read", self.read),
+            ));
+        } else if let Some(format) = self.format.filter(|_| self.read == 0) {
+            *slot = Some(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no spectra could be read, the file is damaged or not a valid {format} file"),
+            ));
+        }
+    }
+}
+
+impl Iterator for SpectrumStream {
+    type Item = MultiLayerSpectrum;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+        let spectrum = self.spectra.next();
+        match spectrum {
+            Some(_) => self.read += 1,
+            None => {
+                self.exhausted = true;
+                self.check_complete();
+            }
+        }
+        spectrum
+    }
+}
+
+/// Wraps a byte source and records the first read error
+struct ErrorRecordingReader<R: Read> {
+    inner: R,
+    compressed: bool,
+    read_error: ReadErrorSlot,
+}
+
+impl<R: Read> Read for ErrorRecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).inspect_err(|e| {
+            let message = if self.compressed && e.kind() == io::ErrorKind::UnexpectedEof {
+                "the file is truncated: the gzip stream ended unexpectedly".to_string()
+            } else if self.compressed {
+                format!("not a valid gzip file: {e}")
+            } else {
+                e.to_string()
+            };
+            let mut slot = self.read_error.lock().unwrap();
+            if slot.is_none() {
+                *slot = Some(io::Error::new(e.kind(), message));
+            }
+        })
+    }
+}
+
+/// Which spectra to keep when loading a file.
+///
+/// All criteria are combined; an empty selection keeps every MS1 and MS2 scan.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanSelection {
+    /// Retention time window in minutes, inclusive
+    pub rt_range: Option<(f64, f64)>,
+    /// Range of spectrum positions in the file (0-based), inclusive
+    pub scan_range: Option<(usize, usize)>,
+    /// MS levels to keep, all if `None`
+    pub ms_levels: Option<Vec<u8>>,
+}
+
+impl ScanSelection {
+    /// Whether a spectrum falls inside the selection
+    pub fn contains(&self, spectrum: &MultiLayerSpectrum) -> bool {
+        let rt_ok = self.rt_range.is_none_or(|(start, end)| {
+            let rt = spectrum.start_time();
+            rt >= start && rt <= end
+        });
+        let index_ok = self.scan_range.is_none_or(|(start, end)| {
+            spectrum.index() >= start && spectrum.index() <= end
+        });
+        let level_ok = self
+            .ms_levels
+            .as_ref()
+            .is_none_or(|levels| levels.contains(&spectrum.ms_level()));
+        rt_ok && index_ok && level_ok
+    }
+
+    /// Whether no later spectrum in the file can be selected any more.
+    ///
+    /// Spectra are stored in acquisition order, so once we are past the end of
+    /// the RT or scan window we can stop reading.
+    fn is_past_end(&self, spectrum: &MultiLayerSpectrum) -> bool {
+        self.rt_range.is_some_and(|(_, end)| spectrum.start_time() > end)
+            || self.scan_range.is_some_and(|(_, end)| spectrum.index() > end)
+    }
+
+    fn is_windowed(&self) -> bool {
+        self.rt_range.is_some() || self.scan_range.is_some()
+    }
+}
+
 /// Read file paths from a text file
 pub fn read_file_paths(file_path: &str) -> Result<Vec<String>, std::io::Error> {
     let file = File::open(file_path)?;
@@ -23,107 +206,868 @@ pub fn read_file_paths(file_path: &str) -> Result<Vec<String>, std::io::Error> {
         .collect()
 }
 
-pub fn load_ms_scans(file_path: &str) -> (Vec<MultiLayerSpectrum>, Vec<MultiLayerSpectrum>) {
+/// Check for the gzip magic bytes at the start of the file
+pub fn is_gzipped(file_path: &str) -> io::Result<bool> {
+    let mut magic = Vec::with_capacity(GZIP_MAGIC.len());
+    File::open(file_path)?
+        .take(GZIP_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    Ok(magic == GZIP_MAGIC)
+}
+
+/// Guess the file format from the file extension alone, looking through a `.gz` suffix
+fn format_from_extension(file_path: &str) -> Option<MSFileFormat> {
+    let path = Path::new(file_path);
+    let path = match path.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("gz") => Path::new(path.file_stem()?),
+        _ => path,
+    };
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "mzml" => Some(MSFileFormat::MzML),
+        "mzxml" => Some(MSFileFormat::MzXML),
+        "mgf" => Some(MSFileFormat::MGF),
+        _ => None,
+    }
+}
+
+/// Guess the file format from the first bytes of the file
+fn format_from_header(header: &[u8]) -> Option<MSFileFormat> {
+    let contains = |needle: &[u8]| header.windows(needle.len()).any(|w| w == needle);
+    if contains(b"<mzML") || contains(b"<indexedmzML") {
+        Some(MSFileFormat::MzML)
+    } else if contains(b"<mzXML") || contains(b"<msRun") {
+        Some(MSFileFormat::MzXML)
+    } else if contains(b"BEGIN IONS") {
+        Some(MSFileFormat::MGF)
+    } else {
+        None
+    }
+}
+
+/// Determine the format of an MS data file.
+///
+/// The extension is trusted when it is one we know; otherwise the start of the file is
+/// inspected (after decompression for gzipped files), so files with unusual names
+/// (e.g. `sample_01.xml.gz`) still load.
+pub fn detect_format(file_path: &str) -> Result<MSFileFormat> {
+    if let Some(format) = format_from_extension(file_path) {
+        return Ok(format);
+    }
+    let mut header = Vec::with_capacity(4096);
+    let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+    let read = if is_gzipped(file_path).map_err(|e| Error::io(file_path, e))? {
+        MultiGzDecoder::new(BufReader::new(file))
+            .take(4096)
+            .read_to_end(&mut header)
+    } else {
+        file.take(4096).read_to_end(&mut header)
+    };
+    // A short or damaged file may still have enough of a header to be recognised
+    if let Err(e) = read {
+        if header.is_empty() {
+            return Err(Error::io(file_path, e));
+        }
+    }
+    format_from_header(&header).ok_or_else(|| Error::UnknownFormat {
+        path: file_path.to_string(),
+    })
+}
+
+/// Open an MS data file with the reader matching its format.
+///
+/// Gzip-compressed files are recognised by their magic bytes and decompressed
+/// while streaming, so they never need to be unpacked on disk.
+pub fn open_spectra(file_path: &str) -> Result<SpectrumStream> {
+    let format = detect_format(file_path)?;
+    let (source, compressed) = open_source(file_path)?;
+
+    let read_error = ReadErrorSlot::default();
+    let source = ErrorRecordingReader {
+        inner: source,
+        compressed,
+        read_error: Arc::clone(&read_error),
+    };
+    let mut expected = None;
+    let spectra: Box<dyn Iterator<Item = MultiLayerSpectrum> + Send> = match format {
+        MSFileFormat::MzML => {
+            let reader = MzMLReader::new(source);
+            expected = reader.spectrum_count_hint();
+            Box::new(reader)
+        }
+        MSFileFormat::MzXML => {
+            Box::new(MzXMLReader::new(BufReader::new(source)).with_error_slot(Arc::clone(&read_error)))
+        }
+        MSFileFormat::MGF => Box::new(MGFReader::new(source)),
+    };
+    Ok(SpectrumStream {
+        spectra,
+        read_error,
+        format: Some(format),
+        expected,
+        read: 0,
+        exhausted: false,
+    })
+}
+
+/// Open the bytes of a file, decompressing it while reading if it is gzipped, and
+/// tell whether it was
+pub(crate) fn open_source(file_path: &str) -> Result<(Box<dyn Read + Send>, bool)> {
+    let compressed = is_gzipped(file_path).map_err(|e| Error::io(file_path, e))?;
+    let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+    let source: Box<dyn Read + Send> = if compressed {
+        Box::new(MultiGzDecoder::new(BufReader::new(file)))
+    } else {
+        Box::new(file)
+    };
+    Ok((source, compressed))
+}
+
+/// Position of the first spectrum acquired at or after `rt`, found by binary search
+/// over the offset index reading only spectrum metadata
+fn first_index_at_or_after(reader: &mut MzMLReader<File>, rt: f64) -> usize {
+    let detail_level = *reader.detail_level();
+    reader.set_detail_level(DetailLevel::MetadataOnly);
+    let (mut lo, mut hi) = (0, reader.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        match reader.get_spectrum_by_index(mid) {
+            Some(scan) if scan.start_time() < rt => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+    reader.set_detail_level(detail_level);
+    lo
+}
+
+/// Open an uncompressed mzML file through its offset index and seek to the start
+/// of the selected window.
+///
+/// The `indexedmzML` offsets at the end of the file are used when present, otherwise
+/// mzdata scans the file once to build the index on the fly.
+fn open_indexed_mzml(file_path: &str, selection: &ScanSelection) -> Result<SpectrumStream> {
+    let mut reader = MzMLReader::new_indexed(File::open(file_path).map_err(|e| Error::io(file_path, e))?);
+    if reader.is_empty() {
+        return open_spectra(file_path);
+    }
+
+    let mut start = selection.scan_range.map(|(start, _)| start).unwrap_or(0);
+    if let Some((rt_start, _)) = selection.rt_range {
+        start = start.max(first_index_at_or_after(&mut reader, rt_start));
+    }
+    if start >= reader.len() {
+        return Ok(SpectrumStream::partial(Box::new(std::iter::empty())));
+    }
+    reader
+        .start_from_index(start)
+        .map_err(|e| Error::io(file_path, io::Error::other(format!("could not seek to spectrum {start}: {e}"))))?;
+    Ok(SpectrumStream::partial(Box::new(reader)))
+}
+
+/// Load all spectra from an mzML, mzXML or MGF file, optionally gzip-compressed,
+/// split into MS1 and MS2 scans, with profile spectra centroided
+pub fn load_ms_scans(file_path: &str) -> Result<(Vec<MultiLayerSpectrum>, Vec<MultiLayerSpectrum>)> {
+    load_selected_ms_scans(file_path, &ScanSelection::default(), Centroiding::default(), &NoProgress, &CancellationToken::new())
+}
+
+/// Load the spectra matching `selection`, split into MS1 and MS2 scans and centroided
+/// according to `centroiding` if they hold profile data.
+///
+/// For uncompressed mzML files with an RT or scan window only the selected part of
+/// the file is parsed; other formats are streamed and filtered.
+///
+/// Fails if the file cannot be opened, or with [`Error::Cancelled`] if `cancel` is
+/// triggered before the file is fully read. A file that cannot be read to its end,
+/// e.g. a truncated gzip file, a damaged mzML file or one that holds no spectra at
+/// all, fails with [`Error::Io`] rather than returning the scans read so far.
+pub fn load_selected_ms_scans(
+    file_path: &str,
+    selection: &ScanSelection,
+    centroiding: Centroiding,
+    progress: &dyn ProgressReporter,
+    cancel: &CancellationToken,
+) -> Result<(Vec<MultiLayerSpectrum>, Vec<MultiLayerSpectrum>)> {
     let start_time = Instant::now();
+    progress.report(ProgressEvent::FileStarted {
+        file: file_path.to_string(),
+    });
 
-    let file = match File::open(file_path) {
-        Ok(f) => f,
-        Err(_) => return (Vec::new(), Vec::new()),
+    let random_access = selection.is_windowed()
+        && matches!(detect_format(file_path), Ok(MSFileFormat::MzML))
+        && matches!(is_gzipped(file_path), Ok(false));
+    let opened = if random_access {
+        open_indexed_mzml(file_path, selection)
+    } else {
+        open_spectra(file_path)
+    };
+    let mut spectra = match opened {
+        Ok(spectra) => spectra,
+        Err(e) => return Err(report_failed(progress, file_path, start_time, e)),
     };
-    let mzml_reader = MzMLReader::new(file);
 
     let mut ms1_scans = Vec::new();
     let mut ms2_scans = Vec::new();
-    for scan in mzml_reader {
+    for mut scan in spectra.by_ref() {
+        if cancel.is_cancelled() {
+            return Err(report_failed(progress, file_path, start_time, Error::Cancelled));
+        }
+        if selection.is_past_end(&scan) {
+            break;
+        }
+        if !selection.contains(&scan) {
+            continue;
+        }
+        if let Err(e) = centroid_spectrum(&mut scan, &centroiding) {
+            return Err(report_failed(progress, file_path, start_time, e));
+        }
         match scan.ms_level() {
             1 => ms1_scans.push(scan),
             2 => ms2_scans.push(scan),
             _ => (),
         }
     }
+    if let Some(e) = spectra.take_error() {
+        return Err(report_failed(progress, file_path, start_time, Error::io(file_path, e)));
+    }
+
+    progress.report(ProgressEvent::ScansLoaded {
+        file: file_path.to_string(),
+        ms1_scans: ms1_scans.len(),
+        ms2_scans: ms2_scans.len(),
+        seconds: start_time.elapsed().as_secs_f64(),
+    });
+    report_finished(progress, file_path, start_time);
+
+    Ok((ms1_scans, ms2_scans))
+}
+
+fn report_error(progress: &dyn ProgressReporter, file_path: &str, message: String) {
+    progress.report(ProgressEvent::Error {
+        file: file_path.to_string(),
+        message,
+    });
+}
+
+/// Report that a file failed and is done, handing the error back to be returned
+fn report_failed(progress: &dyn ProgressReporter, file_path: &str, start_time: Instant, error: Error) -> Error {
+    report_error(progress, file_path, error.to_string());
+    report_finished(progress, file_path, start_time);
+    error
+}
+
+fn report_finished(progress: &dyn ProgressReporter, file_path: &str, start_time: Instant) {
+    progress.report(ProgressEvent::FileFinished {
+        file: file_path.to_string(),
+        seconds: start_time.elapsed().as_secs_f64(),
+    });
+}
+
+/// Open the spectra of a file from the scan cache if it holds an up-to-date copy.
+///
+/// Otherwise the file itself is opened and a [`CacheWriter`] is returned alongside, to
+/// be fed every spectrum read so the next run can use the cache.
+pub fn open_cached_spectra(
+    file_path: &str,
+    cache: &ScanCache,
+    progress: &dyn ProgressReporter,
+) -> Result<(SpectrumStream, Option<CacheWriter>)> {
+    match cache.open(file_path) {
+        Ok(Some(cached)) => {
+            let spectra = SpectrumStream::partial(Box::new(cached));
+            return Ok((spectra, None));
+        }
+        Ok(None) => (),
+        Err(e) => report_error(progress, file_path, format!("Could not read the scan cache: {e}")),
+    }
+    let spectra = open_spectra(file_path)?;
+    let writer = cache
+        .writer(file_path)
+        .inspect_err(|e| report_error(progress, file_path, format!("Could not create the scan cache: {e}")))
+        .ok();
+    Ok((spectra, writer))
+}
+
+/// Load a file and build its XICs in a single pass over its spectra.
+///
+/// Every spectrum is centroided according to `centroiding` if it holds profile data,
+/// matched against all ions of the ion list as it is read and then dropped, unless
+/// `retain_scans` is set, in which case the MS1 and MS2 scans are kept in the returned
+/// measurement as well. With a `cache`, decoded spectra are read from it when up to
+/// date and written to it otherwise; the cache holds them as stored, before centroiding.
+///
+/// Fails if the file cannot be opened, the ion list is invalid or a spectrum cannot be
+/// decoded, or with [`Error::Cancelled`] if `cancel` is triggered before the file is
+/// fully processed. Like [`load_selected_ms_scans`], a file that cannot be read to its
+/// end fails, and is not cached.
+#[allow(clippy::too_many_arguments)]
+pub fn process_file_streaming(
+    file_path: &str,
+    ion_list: &[Compound],
+    mass_accuracy: f64,
+    centroiding: Centroiding,
+    retain_scans: bool,
+    cache: Option<&ScanCache>,
+    progress: &dyn ProgressReporter,
+    cancel: &CancellationToken,
+) -> Result<MSMeasurement> {
+    let start_time = Instant::now();
+    progress.report(ProgressEvent::FileStarted {
+        file: file_path.to_string(),
+    });
+    let measurement = stream_measurement(file_path, ion_list, mass_accuracy, centroiding, retain_scans, cache, progress, cancel)
+        .map_err(|e| report_failed(progress, file_path, start_time, e))?;
+    report_finished(progress, file_path, start_time);
+    Ok(measurement)
+}
+
+/// The body of [`process_file_streaming`], between its start and finish events
+#[allow(clippy::too_many_arguments)]
+fn stream_measurement(
+    file_path: &str,
+    ion_list: &[Compound],
+    mass_accuracy: f64,
+    centroiding: Centroiding,
+    retain_scans: bool,
+    cache: Option<&ScanCache>,
+    progress: &dyn ProgressReporter,
+    cancel: &CancellationToken,
+) -> Result<MSMeasurement> {
+    let start_time = Instant::now();
+    let mut xic_builder = XicBuilder::new(ion_list, mass_accuracy)?;
+    let mut chromatogram_builder = ChromatogramBuilder::for_run();
+    let mut ms1_scans = Vec::new();
+    let mut ms2_scans = Vec::new();
+    let (mut ms1_count, mut ms2_count) = (0, 0);
+
+    let (mut spectra, mut cache_writer) = match cache {
+        Some(cache) => open_cached_spectra(file_path, cache, progress)?,
+        None => (open_spectra(file_path)?, None),
+    };
+    for mut scan in spectra.by_ref() {
+        // The cache keeps the spectra as stored, so it serves any centroiding
+        if let Some(writer) = cache_writer.as_mut() {
+            if let Err(e) = writer.write(&scan) {
+                report_error(progress, file_path, format!("Could not write the scan cache: {e}"));
+                if let Some(writer) = cache_writer.take() {
+                    writer.abandon();
+                }
+            }
+        }
+        let matched = if cancel.is_cancelled() {
+            Err(Error::Cancelled)
+        } else if scan.ms_level() == 1 {
+            centroid_spectrum(&mut scan, &centroiding)
+                .and_then(|_| xic_builder.add_spectrum(&scan))
+                .and_then(|_| chromatogram_builder.add_spectrum(&scan))
+        } else if retain_scans {
+            centroid_spectrum(&mut scan, &centroiding).map(drop)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = matched {
+            // A cache of part of the file would be taken for the whole file next time
+            if let Some(writer) = cache_writer {
+                writer.abandon();
+            }
+            return Err(e);
+        }
+        match scan.ms_level() {
+            1 => {
+                ms1_count += 1;
+                if retain_scans {
+                    ms1_scans.push(scan);
+                }
+            }
+            2 => {
+                ms2_count += 1;
+                if retain_scans {
+                    ms2_scans.push(scan);
+                }
+            }
+            _ => (),
+        }
+    }
+    if let Some(e) = spectra.take_error() {
+        if let Some(writer) = cache_writer {
+            writer.abandon();
+        }
+        return Err(Error::io(file_path, e));
+    }
+    if let Some(Err(e)) = cache_writer.map(CacheWriter::finish) {
+        report_error(progress, file_path, format!("Could not write the scan cache: {e}"));
+    }
 
-    println!(
-        "Loaded {} MS1 scans and {} MS2 scans in {:.2?} seconds from {}.",
-        ms1_scans.len(),
-        ms2_scans.len(),
-        start_time.elapsed(),
-        file_path
-    );
+    progress.report(ProgressEvent::ScansLoaded {
+        file: file_path.to_string(),
+        ms1_scans: ms1_count,
+        ms2_scans: ms2_count,
+        seconds: start_time.elapsed().as_secs_f64(),
+    });
 
-    (ms1_scans, ms2_scans)
+    let xics = xic_builder.finish();
+    progress.report(ProgressEvent::XicsBuilt {
+        file: file_path.to_string(),
+        compounds: xics.len(),
+        seconds: start_time.elapsed().as_secs_f64(),
+    });
+
+    Ok(MSMeasurement::from_data(ms1_scans, ms2_scans, xics, mass_accuracy as f32)
+        .with_chromatograms(chromatogram_builder.finish_run())
+        .with_source(file_path, 0))
+}
+
+/// The outcome of processing one file of a batch
+#[derive(Debug)]
+pub struct FileResult {
+    /// The path the file was given as
+    pub file: String,
+    pub result: Result<MSMeasurement>,
+}
+
+impl FileResult {
+    /// The result of the file at `sample_index` of a batch, recording the index in the
+    /// measurement
+    pub(crate) fn new(sample_index: usize, file: String, result: Result<MSMeasurement>) -> Self {
+        let result = result.map(|measurement| MSMeasurement { sample_index, ..measurement });
+        FileResult { file, result }
+    }
 }
 
-/// Processes multiple MS files in parallel, returning a vector of results
-/// where each result contains the MS1 scans, MS2 scans and processed compounds from each file
+/// Processes multiple MS files in parallel, returning one result per file, in the
+/// order of `file_paths`. Each successful result contains the processed compounds of
+/// the file, and also the MS1 and MS2 scans if `retain_scans` is set; a failed one
+/// holds the reason, so a broken file does not stop or hide behind the others.
+/// Decoded spectra are read from and written to `cache` when one is given.
+///
+/// Once `cancel` is triggered no further files are started, and the files that were
+/// not completed fail with [`Error::Cancelled`].
+#[allow(clippy::too_many_arguments)]
 pub fn process_files_in_parallel(
     file_paths: &[String],
-    ion_list_name: &str,
+    ion_list: &[Compound],
     mass_accuracy: f64,
-) -> Vec<MSMeasurement> {
+    centroiding: Centroiding,
+    retain_scans: bool,
+    cache: Option<&ScanCache>,
+    progress: &dyn ProgressReporter,
+    cancel: &CancellationToken,
+) -> Vec<FileResult> {
     let start_time = Instant::now();
-    println!("Starting parallel processing of {} files...", file_paths.len());
+    progress.report(ProgressEvent::BatchStarted {
+        files: file_paths.len(),
+    });
     
-    // Load ion list once - it will be shared across all file processing tasks
-    let ion_list = Arc::new(load_ion_lists(ion_list_name));
-    
-    let results: Vec<MSMeasurement> = file_paths
+    let results: Vec<FileResult> = file_paths
         .par_iter()
-        .map(|file_path| {
-            let (ms1_scans, ms2_scans) = load_ms_scans(file_path);
-            let result = construct_xics(
-                &ms1_scans, 
-                &ion_list, 
-                mass_accuracy
-            );
-            MSMeasurement::from_data(
-                ms1_scans, 
-                ms2_scans, 
-                result, 
-                mass_accuracy as f32
-            )
+        .enumerate()
+        .map(|(index, file_path)| {
+            let result = if cancel.is_cancelled() {
+                Err(Error::Cancelled)
+            } else {
+                process_file_streaming(file_path, ion_list, mass_accuracy, centroiding, retain_scans, cache, progress, cancel)
+            };
+            FileResult::new(index, file_path.clone(), result)
         })
         .collect();
 
-    println!("Parallel processing completed in {:.2?} seconds.", start_time.elapsed());
+    progress.report(ProgressEvent::BatchFinished {
+        files: results.iter().filter(|r| r.result.is_ok()).count(),
+        seconds: start_time.elapsed().as_secs_f64(),
+    });
 
     results
 }
 
-pub fn load_ion_lists(ion_list_name: &str) -> Vec<Compound> {
-    // Load ion lists
-    let ion_lists_file = File::open("ion_lists.json").expect("Could not open ion lists file");
-    let ion_lists_reader = BufReader::new(ion_lists_file);
-    let ion_lists: Value =
-        serde_json::from_reader(ion_lists_reader).expect("Failed to parse ion lists JSON");
-
-    // Create Compounds from the specified ion list
-    let compounds: Vec<Compound> = if let Some(list) = ion_lists.get(ion_list_name) {
-        list.as_object()
-            .map(|compounds_map| {
-                compounds_map
-                    .iter()
-                    .map(|(name, compound_data)| {
-                        let ions = compound_data["ions"]
-                            .as_array()
-                            .map(|v| v.iter().filter_map(|x| x.as_f64()).collect())
-                            .unwrap_or_default();
-
-                        let ion_info = compound_data["info"]
-                            .as_array()
-                            .map(|v| {
-                                v.iter()
-                                    .filter_map(|x| x.as_str().map(String::from))
-                                    .collect()
-                            })
-                            .unwrap_or_default();
-
-                        Compound::from_ion_list_entry(name.clone(), &ions, &ion_info)
-                    })
-                    .collect()
-            })
-            .unwrap_or_default()
-    } else {
-        Vec::new()
-    };
-    compounds
+/// Load the compounds of the ion list named `ion_list_name` from the ion lists file
+/// in the working or configuration directory, or from the built-in ion lists if
+/// there is none (see [`IonLists::locate`])
+pub fn load_ion_lists(ion_list_name: &str) -> Result<Vec<Compound>> {
+    IonLists::locate()?.compounds(ion_list_name)
+}
+
+/// Load the compounds of the ion list named `ion_list_name` from the ion lists file
+/// at `path`.
+///
+/// Fails if the file cannot be read or parsed, or has no list of that name.
+pub fn load_ion_lists_from<P: AsRef<Path>>(path: P, ion_list_name: &str) -> Result<Vec<Compound>> {
+    IonLists::from_file(path)?.compounds(ion_list_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::CallbackProgress;
+    use crate::test_utils::{encode_peaks, synthetic_run, synthetic_spectrum, temp_path, write_mzml};
+    use mzdata::spectrum::SignalContinuity;
+
+    #[test]
+    fn test_detect_format_from_content() {
+        let path = temp_path("unknown.xml");
+        std::fs::write(&path, "<?xml version=\"1.0\"?>\n<mzXML xmlns=\"http://sashimi.sourceforge.net/schema_revision/mzXML_3.2\">").unwrap();
+        assert_eq!(detect_format(path.to_str().unwrap()).unwrap(), MSFileFormat::MzXML);
+
+        std::fs::write(&path, "BEGIN IONS\nPEPMASS=100\nEND IONS\n").unwrap();
+        assert_eq!(detect_format(path.to_str().unwrap()).unwrap(), MSFileFormat::MGF);
+
+        std::fs::write(&path, "not a spectrum file").unwrap();
+        assert!(detect_format(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_mixed_formats() {
+        let mzml_path = write_mzml("run.mzML", &synthetic_run());
+
+        let mzxml_path = temp_path("run.mzXML");
+        let mzxml = format!(
+            r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+<mzXML>
+  <msRun scanCount="3">
+    <scan num="1" msLevel="1" peaksCount="2" polarity="-" retentionTime="PT6S" centroided="1">
+      <peaks precision="32" byteOrder="network" contentType="m/z-int">{}</peaks>
+      <scan num="2" msLevel="2" peaksCount="1" polarity="-" retentionTime="PT6.5S" centroided="0">
+        <precursorMz precursorIntensity="100" precursorCharge="1">59.0139</precursorMz>
+        <peaks precision="32" byteOrder="network" contentType="m/z-int">{}</peaks>
+      </scan>
+    </scan>
+    <scan num="3" msLevel="1" peaksCount="1" polarity="-" retentionTime="PT12S">
+      <peaks precision="32" byteOrder="network" contentType="m/z-int">{}</peaks>
+    </scan>
+  </msRun>
+</mzXML>"#,
+            encode_peaks(&[(59.0139, 500.0), (73.0295, 20.0)]),
+            encode_peaks(&[(41.0, 3.0)]),
+            encode_peaks(&[(59.0139, 800.0)]),
+        );
+        std::fs::write(&mzxml_path, mzxml).unwrap();
+
+        let mgf_path = temp_path("run.mgf");
+        std::fs::write(
+            &mgf_path,
+            "BEGIN IONS\nTITLE=scan=2\nPEPMASS=59.0139\nCHARGE=1-\nRTINSECONDS=6.5\n41.0 3.0\n59.0 7.0\nEND IONS\n",
+        )
+        .unwrap();
+
+        let (ms1, ms2) = load_ms_scans(mzml_path.to_str().unwrap()).unwrap();
+        assert_eq!((ms1.len(), ms2.len()), (10, 10));
+
+        let (ms1, ms2) = load_ms_scans(mzxml_path.to_str().unwrap()).unwrap();
+        assert_eq!((ms1.len(), ms2.len()), (2, 1));
+        assert_eq!(ms1[0].description.index, 0);
+        assert_eq!(ms2[0].description.index, 1);
+        assert!((ms1[1].start_time() - 0.2).abs() < 1e-9);
+        let mzs = ms1[0].arrays.as_ref().unwrap().mzs().unwrap();
+        assert!((mzs[0] - 59.0139).abs() < 1e-4);
+        let precursor = ms2[0].precursor().unwrap();
+        assert!((precursor.ions[0].mz - 59.0139).abs() < 1e-9);
+        // Without a centroided attribute the data itself decides whether to centroid
+        let continuities: Vec<SignalContinuity> =
+            open_spectra(mzxml_path.to_str().unwrap()).unwrap().map(|s| s.signal_continuity()).collect();
+        assert_eq!(continuities, [SignalContinuity::Centroid, SignalContinuity::Profile, SignalContinuity::Unknown]);
+
+        let (ms1, ms2) = load_ms_scans(mgf_path.to_str().unwrap()).unwrap();
+        assert_eq!((ms1.len(), ms2.len()), (0, 1));
+
+        for path in [mzml_path, mzxml_path, mgf_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_damaged_files_fail() {
+        let mzml_path = write_mzml("run.mzML", &synthetic_run());
+        let mzml = std::fs::read(&mzml_path).unwrap();
+        let random: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let cut = mzml.windows(10).position(|window| window == b"<spectrum ").unwrap() + 2000;
+        let ion_list = vec![Compound::new("Acetate".to_string(), vec![59.0139], vec!["Acetate-neg".to_string()])];
+
+        let files: Vec<String> = [
+            ("garbage.mzML", b"this is not an mzML file".to_vec()),
+            ("random.mzML", random),
+            ("truncated.mzML", mzml[..cut].to_vec()),
+            ("header.mzML", mzml[..cut / 2].to_vec()),
+            ("empty.mgf", b"# no spectra\n".to_vec()),
+        ]
+        .into_iter()
+        .map(|(name, bytes)| {
+            let path = temp_path(name);
+            std::fs::write(&path, bytes).unwrap();
+            path.to_str().unwrap().to_string()
+        })
+        .collect();
+        let results = process_files_in_parallel(&files, &ion_list, 0.0001, Centroiding::default(), false, None, &NoProgress, &CancellationToken::new());
+        for result in &results {
+            assert!(matches!(result.result, Err(Error::Io { .. })), "{} did not fail", result.file);
+        }
+        let truncated = results[2].result.as_ref().unwrap_err().to_string();
+        assert!(truncated.contains("of the 20 spectra"), "{truncated}");
+        for path in files {
+            std::fs::remove_file(path).unwrap();
+        }
+        std::fs::remove_file(mzml_path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_mzxml_reports_error() {
+        let scan = format!(
+            r#"<scan num="1" msLevel="1" peaksCount="1" polarity="-" retentionTime="PT6S">
+      <peaks precision="32" byteOrder="network" contentType="m/z-int">{}</peaks>
+    </scan>"#,
+            encode_peaks(&[(59.0139, 500.0)])
+        );
+        let path = temp_path("truncated.mzXML");
+        // Cut off between two scans, and inside a scan
+        for document in [
+            format!("<mzXML>\n  <msRun scanCount=\"2\">\n    {scan}\n"),
+            format!("<mzXML>\n  <msRun scanCount=\"2\">\n    {scan}\n    <scan num=\"2\" msLevel=\"1\">"),
+        ] {
+            std::fs::write(&path, document).unwrap();
+            let mut spectra = open_spectra(path.to_str().unwrap()).unwrap();
+            assert_eq!(spectra.by_ref().count(), 1);
+            let error = spectra.take_error().expect("truncation should be reported");
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_malformed_mzxml_scans_report_error() {
+        let peaks = format!(r#"<peaks precision="32" byteOrder="network" contentType="m/z-int">{}</peaks>"#, encode_peaks(&[(41.0, 3.0)]));
+        let path = temp_path("malformed.mzXML");
+        for (scan, message) in [
+            (format!(r#"<scan msLevel="two" num="7" retentionTime="PT6S">{peaks}</scan>"#), "Invalid msLevel \"two\" in scan 7"),
+            (
+                format!(r#"<scan num="8" msLevel="2" retentionTime="PT6S"><precursorMz>59,0139</precursorMz>{peaks}</scan>"#),
+                "Invalid precursorMz \"59,0139\" in scan 8",
+            ),
+        ] {
+            std::fs::write(&path, format!("<mzXML>\n  <msRun scanCount=\"1\">\n    {scan}\n  </msRun>\n</mzXML>")).unwrap();
+            let mut spectra = open_spectra(path.to_str().unwrap()).unwrap();
+            assert_eq!(spectra.by_ref().count(), 0);
+            let error = spectra.take_error().expect("the malformed scan should be reported");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), message);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn gzip_file(path: &std::path::Path, name: &str) -> std::path::PathBuf {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let gz_path = temp_path(name);
+        let mut encoder = GzEncoder::new(File::create(&gz_path).unwrap(), flate2::Compression::default());
+        encoder.write_all(&std::fs::read(path).unwrap()).unwrap();
+        encoder.finish().unwrap();
+        gz_path
+    }
+
+    #[test]
+    fn test_load_gzipped_mzml() {
+        let mzml_path = write_mzml("run.mzML", &synthetic_run());
+        let gz_path = gzip_file(&mzml_path, "run.mzML.gz");
+        // No recognisable extension: both compression and format come from the content
+        let renamed_path = gzip_file(&mzml_path, "run.dat");
+
+        let plain = load_ms_scans(mzml_path.to_str().unwrap()).unwrap();
+        assert!(is_gzipped(gz_path.to_str().unwrap()).unwrap());
+        assert!(!is_gzipped(mzml_path.to_str().unwrap()).unwrap());
+        for path in [&gz_path, &renamed_path] {
+            assert_eq!(detect_format(path.to_str().unwrap()).unwrap(), MSFileFormat::MzML);
+            let (ms1, ms2) = load_ms_scans(path.to_str().unwrap()).unwrap();
+            assert_eq!((ms1.len(), ms2.len()), (plain.0.len(), plain.1.len()));
+            assert_eq!(
+                ms1[4].arrays.as_ref().unwrap().intensities().unwrap(),
+                plain.0[4].arrays.as_ref().unwrap().intensities().unwrap()
+            );
+        }
+
+        for path in [mzml_path, gz_path, renamed_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_truncated_gzip_reports_error() {
+        let mzml_path = write_mzml("run.mzML", &synthetic_run());
+        let gz_path = gzip_file(&mzml_path, "truncated.mzML.gz");
+        let bytes = std::fs::read(&gz_path).unwrap();
+        std::fs::write(&gz_path, &bytes[..bytes.len() / 2]).unwrap();
+
+        let mut spectra = open_spectra(gz_path.to_str().unwrap()).unwrap();
+        let loaded = spectra.by_ref().count();
+        assert!(loaded < 20);
+        let error = spectra.take_error().expect("truncation should be reported");
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(error.to_string().contains("is truncated"));
+
+        // Loading fails rather than returning the scans read so far
+        let events = Mutex::new(Vec::new());
+        let progress = CallbackProgress(|event| events.lock().unwrap().push(event));
+        let result =
+            load_selected_ms_scans(gz_path.to_str().unwrap(), &ScanSelection::default(), Centroiding::default(), &progress, &CancellationToken::new());
+        assert!(matches!(result, Err(Error::Io { .. })));
+        let events = events.into_inner().unwrap();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, ProgressEvent::Error { message, .. } if message.contains("is truncated"))));
+        assert!(matches!(events.last(), Some(ProgressEvent::FileFinished { .. })));
+
+        for path in [mzml_path, gz_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_selected_scans() {
+        let indexed_path = write_mzml("run.mzML", &synthetic_run());
+        // Drop the offset index so it has to be rebuilt while opening
+        let content = std::fs::read_to_string(&indexed_path).unwrap();
+        let unindexed = match content.find("<indexList") {
+            Some(pos) => format!("{}</indexedmzML>\n", &content[..pos]),
+            None => content.clone(),
+        };
+        let unindexed_path = temp_path("unindexed.mzML");
+        std::fs::write(&unindexed_path, unindexed).unwrap();
+        let gz_path = gzip_file(&indexed_path, "run.mzML.gz");
+
+        let cancel = CancellationToken::new();
+        for path in [&indexed_path, &unindexed_path, &gz_path] {
+            let path = path.to_str().unwrap();
+
+            let selection = ScanSelection {
+                rt_range: Some((0.28, 0.62)),
+                ..Default::default()
+            };
+            let (ms1, ms2) = load_selected_ms_scans(path, &selection, Centroiding::default(), &NoProgress, &cancel).unwrap();
+            assert_eq!((ms1.len(), ms2.len()), (4, 3), "{path}");
+            assert!((ms1[0].start_time() - 0.3).abs() < 1e-9);
+
+            let selection = ScanSelection {
+                scan_range: Some((4, 7)),
+                ms_levels: Some(vec![1]),
+                ..Default::default()
+            };
+            let (ms1, ms2) = load_selected_ms_scans(path, &selection, Centroiding::default(), &NoProgress, &cancel).unwrap();
+            assert_eq!(ms2.len(), 0);
+            let indices: Vec<usize> = ms1.iter().map(|s| s.index()).collect();
+            assert_eq!(indices, vec![4, 6]);
+
+            let selection = ScanSelection {
+                rt_range: Some((5.0, 6.0)),
+                ..Default::default()
+            };
+            let (ms1, ms2) = load_selected_ms_scans(path, &selection, Centroiding::default(), &NoProgress, &cancel).unwrap();
+            assert!(ms1.is_empty() && ms2.is_empty());
+        }
+
+        for path in [indexed_path, unindexed_path, gz_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_process_file_streaming_retention() {
+        let path = write_mzml("run.mzML", &synthetic_run());
+        let ion_list = vec![Compound::new("Acetate".to_string(), vec![59.0139], vec!["Acetate-neg".to_string()])];
+
+        let cancel = CancellationToken::new();
+        let streamed = process_file_streaming(path.to_str().unwrap(), &ion_list, 0.0001, Centroiding::default(), false, None, &NoProgress, &cancel).unwrap();
+        assert!(streamed.ms1_scans.is_empty() && streamed.ms2_scans.is_empty());
+        let retained = process_file_streaming(path.to_str().unwrap(), &ion_list, 0.0001, Centroiding::default(), true, None, &NoProgress, &cancel).unwrap();
+        assert_eq!((retained.ms1_scans.len(), retained.ms2_scans.len()), (10, 10));
+
+        assert_eq!(streamed.xics[0].ions, retained.xics[0].ions);
+        assert!(streamed.xics[0].ions[0].ms_intensity.is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_profile_spectra_are_centroided() {
+        // The same Gaussian peak sampled at two densities
+        let profile_run = |name: &str, spacing: f64| {
+            let mzs: Vec<f64> = (0..=(0.03 / spacing).round() as usize).map(|i| 59.0 + i as f64 * spacing).collect();
+            let spectra: Vec<MultiLayerSpectrum> = (0..5)
+                .map(|i| {
+                    let height = 1000.0 * (3.0 - (i as f64 - 2.0).abs());
+                    let intensities: Vec<f32> = mzs
+                        .iter()
+                        .map(|mz| (height * (-(mz - 59.0139).powi(2) / (2.0 * 0.002 * 0.002)).exp()) as f32)
+                        .collect();
+                    let mut spectrum = synthetic_spectrum(i, 1, i as f64 * 0.1, &mzs, &intensities);
+                    spectrum.description.signal_continuity = SignalContinuity::Profile;
+                    spectrum
+                })
+                .collect();
+            write_mzml(name, &spectra)
+        };
+        let ion_list = vec![Compound::new("Acetate".to_string(), vec![59.0139], vec!["Acetate-neg".to_string()])];
+        let cancel = CancellationToken::new();
+        let intensity = |path: &std::path::Path, centroiding: Centroiding| {
+            let measurement =
+                process_file_streaming(path.to_str().unwrap(), &ion_list, 0.001, centroiding, true, None, &NoProgress, &cancel)
+                    .unwrap();
+            (measurement.xics[0].ions[0].ms_intensity.unwrap(), measurement)
+        };
+
+        let (dense, sparse) = (profile_run("dense.mzML", 0.0005), profile_run("sparse.mzML", 0.001));
+        let (dense_intensity, measurement) = intensity(&dense, Centroiding::default());
+        let (sparse_intensity, _) = intensity(&sparse, Centroiding::default());
+        assert!((dense_intensity - sparse_intensity).abs() < 0.01 * dense_intensity);
+        assert!(measurement.ms1_scans.iter().all(|scan| scan.signal_continuity() == SignalContinuity::Centroid));
+
+        // Uncentroided, every sampled point within the tolerance is added up
+        let (dense_profile, _) = intensity(&dense, Centroiding::off());
+        let (sparse_profile, _) = intensity(&sparse, Centroiding::off());
+        assert!(dense_profile > 1.5 * sparse_profile);
+        for path in [dense, sparse] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cancelled_processing() {
+        let path = write_mzml("run.mzML", &synthetic_run());
+        let file_path = path.to_str().unwrap();
+        let ion_list = vec![Compound::new("Acetate".to_string(), vec![59.0139], vec!["Acetate-neg".to_string()])];
+        let cache = ScanCache::new(temp_path("cache"));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let events = Mutex::new(Vec::new());
+        let progress = CallbackProgress(|event| events.lock().unwrap().push(event));
+        let result = process_file_streaming(file_path, &ion_list, 0.0001, Centroiding::default(), false, Some(&cache), &progress, &cancel);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        let result = load_selected_ms_scans(file_path, &ScanSelection::default(), Centroiding::default(), &NoProgress, &cancel);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        // The cache of an unfinished file is never kept
+        assert!(!cache.cache_path(file_path).unwrap().exists());
+        assert!(matches!(events.lock().unwrap().last(), Some(ProgressEvent::FileFinished { .. })));
+
+        let files = vec![file_path.to_string()];
+        let results = process_files_in_parallel(&files, &ion_list, 0.0001, Centroiding::default(), false, None, &NoProgress, &cancel);
+        assert!(matches!(results[0].result, Err(Error::Cancelled)));
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_dir_all(cache.dir()).ok();
+    }
+
+    #[test]
+    fn test_batch_reports_failed_files() {
+        let path = write_mzml("run.mzML", &synthetic_run());
+        let missing = temp_path("missing.mzML");
+        let files = vec![missing.to_str().unwrap().to_string(), path.to_str().unwrap().to_string()];
+
+        assert!(matches!(load_ms_scans(&files[0]), Err(Error::Io { .. })));
+        let ion_list = load_ion_lists("scfas").unwrap();
+        let results = process_files_in_parallel(&files, &ion_list, 0.0001, Centroiding::default(), false, None, &NoProgress, &CancellationToken::new());
+        // One result per file, in input order, with the reason for the failed one
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].file, files[0]);
+        assert!(matches!(&results[0].result, Err(Error::Io { path, .. }) if *path == files[0]));
+        assert_eq!(results[1].result.as_ref().unwrap().xics.len(), 4);
+
+        let unknown = load_ion_lists("no_such_list");
+        assert!(matches!(unknown, Err(Error::UnknownIonList { name, .. }) if name == "no_such_list"));
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -1,160 +1,751 @@
-pub mod loading;
-pub mod measurements;
-pub mod processing;
-
-use std::env;
+use clap::{Args, Parser, Subcommand};
+use lcmspector_backend::batch::process_batch;
+use lcmspector_backend::config::DEFAULT_CONFIG_FILE;
+use lcmspector_backend::progress::{JsonLinesProgress, NoProgress, ProgressReporter, TerminalProgress};
+use lcmspector_backend::{
+    cache, calibrate, centroid_spectrum, export_results, export_spectrum, http, loading, quantify, server, spectra,
+    summarize_files, write_spectrum_csv, CancellationToken, CentroidMethod, Centroiding, ChromatogramBuilder, ChromatogramFilter, Combine,
+    CombineOptions, Compound, Config, Error, FileResult, IonLists, MSMeasurement, Polarity, Provenance, Quantity,
+    ScanSelection, read_ion_table, write_info_table, write_ion_table, write_quantities_csv, write_tic_csv,
+};
+use mzdata::prelude::*;
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::Arc;
 use tokio::runtime;
-use futures::future::join_all;
-use std::path::Path;
-use indicatif::{ProgressBar, ProgressStyle};
+
+/// Something went wrong: bad input files, configuration or ion lists
+const EXIT_ERROR: i32 = 1;
+// clap exits with 2 on invalid usage
+/// Processing finished, but some files could not be processed
+const EXIT_FILES_FAILED: i32 = 3;
+/// Stopped with Ctrl-C
+const EXIT_CANCELLED: i32 = 130;
+
+/// Targeted LC-MS data extraction and quantitation
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Configuration file [default: lcmspector.toml if present]
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Extract the XICs of an ion list from a batch of files
+    Extract(ExtractArgs),
+    /// Summarize the instrument, scans and ranges of files as one table
+    Info {
+        /// The mzML/mzXML/MGF files, optionally gzip-compressed
+        #[arg(required_unless_present = "file_list")]
+        files: Vec<String>,
+        /// Read the files to summarize from this file, one path per line
+        #[arg(short, long, value_name = "PATH")]
+        file_list: Option<String>,
+        /// Where to write the table, as CSV if it ends in .csv [default: tab-separated on stdout]
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Write the summaries as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Export the total ion and base peak chromatograms of a file as CSV
+    Tic {
+        file: String,
+        /// Only scans of this polarity (positive or negative)
+        #[arg(long, value_parser = parse_polarity)]
+        polarity: Option<Polarity>,
+        /// Only peaks within this m/z range
+        #[arg(long, value_name = "START,END", value_parser = parse_range)]
+        mz_range: Option<(f64, f64)>,
+        /// Where to write the CSV [default: stdout]
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Dump the spectrum closest to a retention time, or the scans of an RT range
+    /// combined, as CSV or MGF
+    Spectrum(SpectrumArgs),
+    /// List, show or validate the ion lists
+    IonLists {
+        /// Ion lists file, overriding the configuration; repeat to merge several files
+        #[arg(long, value_name = "PATH")]
+        ion_lists: Vec<PathBuf>,
+
+        #[command(subcommand)]
+        command: IonListsCommand,
+    },
+    /// Calibrate with standards and quantify the compounds of an ion list in samples
+    Quant(QuantArgs),
+    /// Answer JSON-RPC requests on stdin/stdout
+    Serve,
+    /// Run the HTTP API
+    ServeHttp {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+        /// How many jobs may wait in the queue
+        #[arg(long, default_value_t = http::DEFAULT_QUEUE_CAPACITY)]
+        queue: usize,
+        /// How many finished jobs are kept with their results
+        #[arg(long, default_value_t = http::DEFAULT_RETAINED_JOBS)]
+        keep_jobs: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum IonListsCommand {
+    /// List the names of all ion lists and the files they are defined in
+    List,
+    /// Show the compounds and ions of an ion list
+    Show { name: String },
+    /// Check ion lists for mismatched labels, duplicate ions and invalid masses
+    Validate {
+        /// The lists to check [default: all]
+        names: Vec<String>,
+    },
+    /// Convert a CSV/TSV target table to the ion lists JSON format
+    Import {
+        table: PathBuf,
+        /// The list of rows without a list column [default: the file name]
+        #[arg(long)]
+        list: Option<String>,
+        /// Where to write the JSON [default: stdout]
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Write ion lists as a target table, tab-separated if the output ends in .tsv
+    Export {
+        /// The lists to export [default: all]
+        names: Vec<String>,
+        /// Where to write the table [default: stdout]
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    /// Name of the ion list to extract, or several names separated by commas
+    ion_list: String,
+    /// The files to process
+    #[arg(required_unless_present = "file_list")]
+    files: Vec<String>,
+    /// Read the files to process from this file, one path per line
+    #[arg(short, long, value_name = "PATH")]
+    file_list: Option<String>,
+
+    #[command(flatten)]
+    options: ProcessingOptions,
+}
+
+#[derive(Args)]
+struct SpectrumArgs {
+    file: String,
+    /// Retention time in minutes
+    #[arg(long, required_unless_present = "rt_range")]
+    rt: Option<f64>,
+    /// Combine the scans of this retention time window instead, in minutes
+    #[arg(long, value_name = "START,END", value_parser = parse_range, conflicts_with = "rt")]
+    rt_range: Option<(f64, f64)>,
+    #[arg(long, default_value_t = 1)]
+    ms_level: u8,
+    /// How far from the retention time to look for a scan, in minutes
+    #[arg(long, default_value_t = 1.0)]
+    window: f64,
+    /// Subtract the mean of the scans of this window, e.g. next to a peak; repeat
+    /// for several windows
+    #[arg(long, value_name = "START,END", value_parser = parse_range, requires = "rt_range")]
+    background: Vec<(f64, f64)>,
+    /// Width of the m/z bins the combined scans are merged in
+    #[arg(long, value_name = "M/Z", default_value_t = spectra::DEFAULT_BIN_WIDTH)]
+    bin_width: f64,
+    /// Sum the combined scans instead of averaging them
+    #[arg(long)]
+    sum: bool,
+    /// Where to write the spectrum, as MGF if it ends in .mgf [default: CSV on stdout]
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct QuantArgs {
+    /// Name of the ion list to quantify, or several names separated by commas
+    ion_list: String,
+    /// A calibration standard as <concentration>=<file>, given once per standard
+    #[arg(short, long = "standard", value_name = "CONC=FILE", value_parser = parse_standard, required = true)]
+    standards: Vec<(f64, String)>,
+    /// The sample files to quantify
+    #[arg(required = true)]
+    samples: Vec<String>,
+
+    #[command(flatten)]
+    options: ProcessingOptions,
+}
+
+/// Options overriding the configuration file
+#[derive(Args)]
+struct ProcessingOptions {
+    /// m/z tolerance
+    #[arg(long, value_name = "M/Z")]
+    mass_accuracy: Option<f64>,
+    /// Ignore matched peaks below this intensity
+    #[arg(long, value_name = "VALUE")]
+    min_intensity: Option<f64>,
+    /// Only integrate this retention time window, in minutes
+    #[arg(long, value_name = "START,END", value_parser = parse_range)]
+    rt_range: Option<(f64, f64)>,
+    /// How profile spectra are centroided: off, weighted-average or gaussian
+    #[arg(long, value_name = "METHOD", value_parser = parse_centroid_method)]
+    centroid: Option<CentroidMethod>,
+    /// Centroid spectra the file marks as centroided if they look like profile data
+    #[arg(long)]
+    no_vendor_centroids: bool,
+    /// Ion lists file; repeat to merge several files
+    #[arg(long, value_name = "PATH")]
+    ion_lists: Vec<PathBuf>,
+    /// Split batches with more files than this into Tokio tasks
+    #[arg(long, value_name = "FILES")]
+    batch_threshold: Option<usize>,
+    /// Tokio worker threads
+    #[arg(long, value_name = "THREADS")]
+    workers: Option<usize>,
+    /// Threads processing files, 0 for one per core
+    #[arg(long, value_name = "THREADS")]
+    threads: Option<usize>,
+    /// Write the results as CSV if the path ends in .csv, as JSON otherwise
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Keep the raw scans of every file in memory
+    #[arg(long)]
+    keep_scans: bool,
+    /// Cache decoded spectra on disk
+    #[arg(long)]
+    cache: bool,
+    /// Report progress as JSON lines on stderr
+    #[arg(long)]
+    json_progress: bool,
+}
+
+impl ProcessingOptions {
+    fn apply(&self, config: &mut Config) {
+        if let Some(mass_accuracy) = self.mass_accuracy {
+            config.tolerances.mass_accuracy = mass_accuracy;
+        }
+        if let Some(min_intensity) = self.min_intensity {
+            config.peak_picking.min_intensity = min_intensity;
+        }
+        if self.rt_range.is_some() {
+            config.integration.rt_range = self.rt_range;
+        }
+        if let Some(method) = self.centroid {
+            config.centroiding.method = method;
+        }
+        config.centroiding.vendor_centroids &= !self.no_vendor_centroids;
+        if !self.ion_lists.is_empty() {
+            config.ion_lists.paths = self.ion_lists.clone();
+        }
+        if let Some(threshold) = self.batch_threshold {
+            config.parallelism.batch_threshold = threshold;
+        }
+        if let Some(workers) = self.workers {
+            config.parallelism.worker_threads = workers;
+        }
+        if let Some(threads) = self.threads {
+            config.parallelism.threads = threads;
+        }
+        if self.output.is_some() {
+            config.output.path = self.output.clone();
+        }
+        config.output.keep_scans |= self.keep_scans;
+        config.output.cache |= self.cache;
+        config.output.json_progress |= self.json_progress;
+    }
+}
+
+fn parse_range(value: &str) -> Result<(f64, f64), String> {
+    let (start, end) = value.split_once(',').ok_or("expected <start>,<end>")?;
+    let start = start.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    let end = end.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    Ok((start, end))
+}
+
+fn parse_polarity(value: &str) -> Result<Polarity, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "pos" | "positive" | "+" => Ok(Polarity::Positive),
+        "neg" | "negative" | "-" => Ok(Polarity::Negative),
+        _ => Err("expected positive or negative".to_string()),
+    }
+}
+
+fn parse_centroid_method(value: &str) -> Result<CentroidMethod, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "off" | "none" => Ok(CentroidMethod::Off),
+        "weighted-average" | "average" => Ok(CentroidMethod::WeightedAverage),
+        "gaussian" => Ok(CentroidMethod::Gaussian),
+        _ => Err("expected off, weighted-average or gaussian".to_string()),
+    }
+}
+
+fn parse_standard(value: &str) -> Result<(f64, String), String> {
+    let (concentration, file) = value.split_once('=').ok_or("expected <concentration>=<file>")?;
+    let concentration = concentration.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    Ok((concentration, file.to_string()))
+}
 
 fn main() {
-    println!(
-        "Running LCMSpector backend version {}",
-        env!("CARGO_PKG_VERSION")
-    );
-    let args: Vec<String> = env::args().collect();
+    let cli = Cli::parse();
+    let config = load_config(cli.config.as_deref());
 
-    // Require 3 arguments: program name, ion list name, and file list path
-    if args.len() != 3 {
-        eprintln!("Usage: {} <ion_list_name> <file_list_path>", args[0]);
-        process::exit(1);
+    match cli.command {
+        Command::Extract(args) => extract(config, args),
+        Command::Info { files, file_list, output, json } => info(files, file_list.as_deref(), output.as_deref(), json),
+        Command::Tic { file, polarity, mz_range, output } => {
+            tic(&file, ChromatogramFilter { polarity, mz_range }, &config.centroiding, output.as_deref())
+        }
+        Command::Spectrum(args) => spectrum(&config, args),
+        Command::IonLists { ion_lists, command } => {
+            let paths = if ion_lists.is_empty() { config.ion_lists.paths } else { ion_lists };
+            ion_lists_command(&paths, command)
+        }
+        Command::Quant(args) => quant(config, args),
+        Command::Serve => serve(&config),
+        Command::ServeHttp { addr, queue, keep_jobs } => serve_http(&config, &addr, queue, keep_jobs),
     }
+}
 
-    let ion_list_name = &args[1];
-    let file_list_path = &args[2];
+/// Print an error and exit
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("Error: {}", message);
+    process::exit(EXIT_ERROR);
+}
 
-    // Read file paths from the specified file
-    let file_paths = match loading::read_file_paths(file_list_path) {
-        Ok(paths) => paths,
-        Err(e) => {
-            eprintln!("Error reading file list: {}", e);
-            process::exit(1);
+/// Read the configuration given with `--config`, or the default configuration file
+/// if there is one in the working directory
+fn load_config(path: Option<&Path>) -> Config {
+    let loaded = match path {
+        Some(path) => Config::load(path),
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::load(DEFAULT_CONFIG_FILE),
+        None => Ok(Config::default()),
+    };
+    loaded.unwrap_or_else(|e| fail(e))
+}
+
+/// Open `path` for writing, or stdout if it is not given
+fn output_writer(path: Option<&Path>) -> Box<dyn Write> {
+    match path {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => fail(format!("Could not create {}: {}", path.display(), e)),
+        },
+        None => Box::new(BufWriter::new(io::stdout())),
+    }
+}
+
+fn print_banner() {
+    eprintln!("Running LCMSpector backend version {}", env!("CARGO_PKG_VERSION"));
+}
+
+fn extract(mut config: Config, args: ExtractArgs) {
+    args.options.apply(&mut config);
+    print_banner();
+    // Echo the effective configuration, as a valid configuration file, so runs can be reproduced
+    eprintln!("# Effective configuration");
+    eprintln!("{}", config.to_toml());
+
+    let mut file_paths = args.files;
+    if let Some(file_list) = &args.file_list {
+        match loading::read_file_paths(file_list) {
+            Ok(paths) => file_paths.extend(paths),
+            Err(e) => fail(format!("Could not read the file list {}: {}", file_list, e)),
         }
+    }
+    let ion_list = load_compounds(&config, &args.ion_list);
+    let mut provenance = Provenance::start(&config, &ion_list_names(&args.ion_list), &ion_list);
+
+    eprintln!("Processing {} files with ion list: {}", file_paths.len(), args.ion_list);
+    let (results, cancelled) = run_batch(&config, &file_paths, ion_list);
+    provenance.finish(&file_paths);
+    let failed_count = report_failures(&results);
+    let measurements: Vec<MSMeasurement> = results.into_iter().filter_map(|r| r.result.ok()).collect();
+    eprintln!("Processed {} files", measurements.len());
+
+    if let Some(output) = &config.output.path {
+        if let Err(e) = export_results(output, &measurements, &provenance) {
+            fail(format!("Could not write {}: {}", output.display(), e));
+        }
+        eprintln!("Results written to {}", output.display());
+    }
+    if cancelled {
+        eprintln!("Cancelled, {} of {} files were completed", measurements.len(), file_paths.len());
+        process::exit(EXIT_CANCELLED);
+    }
+    process::exit(if failed_count > 0 { EXIT_FILES_FAILED } else { 0 });
+}
+
+/// Process a batch of files with the configured parallelism and integration settings.
+///
+/// The first Ctrl-C stops the batch: the files in progress are abandoned and the rest
+/// fail as cancelled, so the caller can still export the completed ones. Returns the
+/// results and whether the batch was cancelled. A second Ctrl-C exits immediately.
+fn run_batch(config: &Config, file_paths: &[String], ion_list: Vec<Compound>) -> (Vec<FileResult>, bool) {
+    // Progress is shown as a bar, or as JSON lines on stderr for other programs to parse
+    let progress: Arc<dyn ProgressReporter> = if config.output.json_progress {
+        Arc::new(JsonLinesProgress)
+    } else {
+        Arc::new(TerminalProgress::new())
     };
-    
-    println!("Processing {} files with ion list: {}", file_paths.len(), ion_list_name);
-    
-    // Create a multi-threaded runtime for async IO operations
-    let rt = runtime::Builder::new_multi_thread()
-        .worker_threads(4) // Optimized for IO-bound operations
-        .enable_all()
-        .build()
-        .expect("Failed to create Tokio runtime");
-    
-    let mut results = Vec::new();
-
-    rt.block_on(async {
-        if file_paths.len() > 25 {
-            // For large batches, use hybrid approach (Tokio + Rayon)
-            results = process_large_batch(&file_paths, ion_list_name, 0.0001).await;
-        } else {
-            // For smaller batches, use standard Rayon approach
-            results = loading::process_files_in_parallel(&file_paths, ion_list_name, 0.0001);
-            println!("Processed {} files using standard parallel approach", results.len());
+    // Decoded spectra are cached on disk when requested
+    let cache = config.output.cache.then(cache::ScanCache::default_location);
+
+    if config.parallelism.threads > 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(config.parallelism.threads)
+            .build_global()
+            .expect("Failed to create the thread pool");
+    }
+    let rt = build_runtime(config.parallelism.worker_threads);
+
+    let cancel = CancellationToken::new();
+    let ctrl_c_cancel = cancel.clone();
+    rt.spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Cancelling, press Ctrl-C again to exit immediately...");
+            ctrl_c_cancel.cancel();
+            if tokio::signal::ctrl_c().await.is_ok() {
+                process::exit(EXIT_CANCELLED);
+            }
         }
     });
-    process::exit(0);
-}
-
-async fn process_large_batch(
-    file_paths: &[String],
-    ion_list_name: &str,
-    mass_accuracy: f64,
-) -> Vec<measurements::MSMeasurement> {
-    let start = std::time::Instant::now();
-    println!("Starting optimized large-batch processing for {} files...", file_paths.len());
-    
-    // Load ion list once and share it across all tasks
-    let ion_list = Arc::new(loading::load_ion_lists(ion_list_name));
-    
-    // Determine optimal batch size based on available cores
-    let num_physical_cores = num_cpus::get_physical();
-    let batch_size = std::cmp::max(5, file_paths.len() / (num_physical_cores * 2));
-    println!("Splitting into batches of approximately {} files each", batch_size);
-    
-    // Create a progress bar for overall progress
-    let progress_bar = ProgressBar::new(file_paths.len() as u64);
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {wide_bar:.cyan/blue} {pos}/{len} files ({percent}%, ETA: {eta})")
-            .expect("Failed to create progress style")
-    );
-    
-    // Split files into batches for processing
-    let batches: Vec<Vec<String>> = file_paths
-        .chunks(batch_size)
-        .map(|chunk| chunk.to_vec())
+
+    let results = rt.block_on(process_batch(file_paths, ion_list, config, cache, progress, cancel.clone()));
+    (results, cancel.is_cancelled())
+}
+
+/// List the files that could not be processed on stderr and return how many there were.
+/// Files that were not completed because the batch was cancelled are not counted.
+fn report_failures(results: &[FileResult]) -> usize {
+    let failed: Vec<&FileResult> = results
+        .iter()
+        .filter(|r| r.result.as_ref().is_err_and(|e| !matches!(e, Error::Cancelled)))
         .collect();
-    
-    // Create shared progress bar
-    let progress_bar = Arc::new(progress_bar);
-    
-    // Process each batch asynchronously
-    let batch_futures = batches.into_iter().map(|batch| {
-        let ion_list_clone = Arc::clone(&ion_list);
-        let progress_bar_clone = Arc::clone(&progress_bar);
-        
-        tokio::spawn(async move {
-            // Process this batch of files
-            process_file_batch(batch, ion_list_clone, mass_accuracy as f32, progress_bar_clone).await
+    if !failed.is_empty() {
+        eprintln!("{} of {} files failed:", failed.len(), results.len());
+        for FileResult { file, result } in &failed {
+            if let Err(e) = result {
+                eprintln!("  {}: {}", file, e);
+            }
+        }
+    }
+    failed.len()
+}
+
+fn info(mut files: Vec<String>, file_list: Option<&str>, output: Option<&Path>, json: bool) {
+    if let Some(file_list) = file_list {
+        match loading::read_file_paths(file_list) {
+            Ok(paths) => files.extend(paths),
+            Err(e) => fail(format!("Could not read the file list {}: {}", file_list, e)),
+        }
+    }
+    let mut summaries = Vec::new();
+    for (file, summary) in files.iter().zip(summarize_files(&files)) {
+        match summary {
+            Ok(summary) => summaries.push(summary),
+            Err(e) => eprintln!("{}: {}", file, e),
+        }
+    }
+
+    let mut writer = output_writer(output);
+    let written = if json {
+        serde_json::to_writer_pretty(&mut writer, &summaries)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(writer))
+    } else {
+        let is_csv = output.and_then(Path::extension).is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+        write_info_table(&mut writer, &summaries, if is_csv { ',' } else { '\t' })
+    };
+    if let Err(e) = written.and_then(|_| writer.flush()) {
+        fail(e);
+    }
+
+    let failed = files.len() - summaries.len();
+    if failed > 0 {
+        process::exit(if summaries.is_empty() { EXIT_ERROR } else { EXIT_FILES_FAILED });
+    }
+}
+
+fn tic(file: &str, filter: ChromatogramFilter, centroiding: &Centroiding, output: Option<&Path>) {
+    let mut builder = ChromatogramBuilder::new(&[filter]);
+    let mut spectra = loading::open_spectra(file).unwrap_or_else(|e| fail(e));
+    for mut scan in spectra.by_ref().filter(|scan| scan.ms_level() == 1) {
+        centroid_spectrum(&mut scan, centroiding).unwrap_or_else(|e| fail(e));
+        builder.add_spectrum(&scan).unwrap_or_else(|e| fail(e));
+    }
+    if let Some(e) = spectra.take_error() {
+        fail(format!("{}: {}", file, e));
+    }
+    let chromatogram = builder.finish().remove(0);
+
+    let mut writer = output_writer(output);
+    if let Err(e) = write_tic_csv(&mut writer, &chromatogram).and_then(|_| writer.flush()) {
+        fail(e);
+    }
+}
+
+fn spectrum(config: &Config, args: SpectrumArgs) {
+    let file = args.file.as_str();
+    // Only the scans around the spectrum are loaded
+    let rt_range = match (args.rt, args.rt_range) {
+        (_, Some((start, end))) => args
+            .background
+            .iter()
+            .fold((start, end), |(start, end), background| (start.min(background.0), end.max(background.1))),
+        (Some(rt), None) => (rt - args.window, rt + args.window),
+        (None, None) => unreachable!("clap requires --rt or --rt-range"),
+    };
+    let selection = ScanSelection {
+        rt_range: Some(rt_range),
+        ms_levels: Some(vec![args.ms_level]),
+        ..ScanSelection::default()
+    };
+    let (ms1_scans, ms2_scans) =
+        loading::load_selected_ms_scans(file, &selection, config.centroiding, &NoProgress, &CancellationToken::new())
+            .unwrap_or_else(|e| fail(e));
+    let measurement = MSMeasurement::from_data(ms1_scans, ms2_scans, Vec::new(), 0.0).with_source(file, 0);
+
+    let extracted = match (args.rt, args.rt_range) {
+        (_, Some(range)) => {
+            let options = CombineOptions {
+                mode: if args.sum { Combine::Sum } else { Combine::Mean },
+                bin_width: args.bin_width,
+                background: args.background.clone(),
+            };
+            spectra::combined_spectrum(&measurement, range, args.ms_level, &options)
+        }
+        (Some(rt), None) => spectra::spectrum_at(&measurement, rt, args.ms_level),
+        (None, None) => unreachable!("clap requires --rt or --rt-range"),
+    };
+    let spectrum = extracted
+        .unwrap_or_else(|e| fail(e))
+        .unwrap_or_else(|| fail(format!("No MS{} scan within {} to {} min in {}", args.ms_level, rt_range.0, rt_range.1, file)));
+    match spectrum.scans {
+        1 => eprintln!("Scan at {} min", spectrum.rt_range.0),
+        scans => eprintln!(
+            "{} scans from {} to {} min, {} background scans",
+            scans, spectrum.rt_range.0, spectrum.rt_range.1, spectrum.background_scans
+        ),
+    }
+
+    let written = match &args.output {
+        Some(path) => export_spectrum(path, &spectrum),
+        None => {
+            let mut writer = output_writer(None);
+            write_spectrum_csv(&mut writer, &spectrum).and_then(|_| writer.flush())
+        }
+    };
+    if let Err(e) = written {
+        fail(e);
+    }
+}
+
+/// The compounds of the comma-separated ion list `names`, from the configured ion lists files
+fn load_compounds(config: &Config, names: &str) -> Vec<Compound> {
+    IonLists::from_files(&config.ion_lists.paths)
+        .and_then(|ion_lists| ion_lists.combined(&ion_list_names(names)))
+        .unwrap_or_else(|e| fail(e))
+}
+
+/// The ion list names given as one argument, separated by commas
+fn ion_list_names(names: &str) -> Vec<&str> {
+    names.split(',').map(str::trim).collect()
+}
+
+/// Convert a target table to the ion lists JSON format
+fn import_ion_table(table: &Path, list: Option<&str>, output: Option<&Path>) {
+    let imported = read_ion_table(table, list).unwrap_or_else(|e| fail(e));
+    let json = imported.to_json::<&str>(&[]).unwrap_or_else(|e| fail(e));
+    let mut writer = output_writer(output);
+    if let Err(e) = writeln!(writer, "{}", json).and_then(|_| writer.flush()) {
+        fail(e);
+    }
+}
+
+fn ion_lists_command(paths: &[PathBuf], command: IonListsCommand) {
+    let load = || IonLists::from_files(paths).unwrap_or_else(|e| fail(e));
+    // The given list names, or all of them
+    let select = |ion_lists: &IonLists, names: Vec<String>| -> Vec<String> {
+        if names.is_empty() {
+            ion_lists.names().map(String::from).collect()
+        } else {
+            names
+        }
+    };
+    match command {
+        IonListsCommand::List => {
+            let ion_lists = load();
+            for name in ion_lists.names() {
+                println!("{}\t{}", name, ion_lists.source(name).unwrap_or_default());
+            }
+        }
+        IonListsCommand::Show { name } => {
+            let compounds = load().compounds(&name).unwrap_or_else(|e| fail(e));
+            for compound in compounds {
+                println!("{}", compound.name);
+                for ion in &compound.ions {
+                    let polarity = match ion.polarity {
+                        Some(Polarity::Positive) => "+",
+                        Some(Polarity::Negative) => "-",
+                        None => "",
+                    };
+                    let label = ion.label.as_deref().unwrap_or_default();
+                    let adduct = ion.adduct.as_deref().unwrap_or_default();
+                    println!("  {}\t{}\t{}\t{}", ion.name(), polarity, adduct, label);
+                }
+            }
+        }
+        IonListsCommand::Validate { names } => {
+            let ion_lists = load();
+            let selected = select(&ion_lists, names);
+            let issues = ion_lists.validate();
+            let mut error_count = 0;
+            for name in &selected {
+                let list = ion_lists.get(name).unwrap_or_else(|e| fail(e));
+                let list_issues: Vec<_> = issues.iter().filter(|issue| issue.list == *name).collect();
+                let errors = list_issues.iter().filter(|issue| issue.problem.is_error()).count();
+                let warnings = list_issues.len() - errors;
+                if list_issues.is_empty() {
+                    println!("{}: {} compounds, ok", name, list.len());
+                } else {
+                    println!("{}: {} compounds, {} errors, {} warnings", name, list.len(), errors, warnings);
+                }
+                for issue in list_issues {
+                    println!("  {}", issue);
+                }
+                error_count += errors;
+            }
+            if error_count > 0 {
+                eprintln!("Found {} errors", error_count);
+                process::exit(EXIT_ERROR);
+            }
+        }
+        IonListsCommand::Export { names, output } => {
+            let ion_lists = load();
+            let selected = select(&ion_lists, names);
+            let lists: Vec<_> = selected
+                .iter()
+                .map(|name| ion_lists.get(name).map(|list| (name.as_str(), list)))
+                .collect::<Result<_, _>>()
+                .unwrap_or_else(|e| fail(e));
+            let is_tsv = output
+                .as_deref()
+                .and_then(Path::extension)
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("tsv"));
+            let mut writer = output_writer(output.as_deref());
+            let written = write_ion_table(&mut writer, &lists, if is_tsv { '\t' } else { ',' })
+                .and_then(|_| writer.flush());
+            if let Err(e) = written {
+                fail(e);
+            }
+        }
+        IonListsCommand::Import { table, list, output } => {
+            import_ion_table(&table, list.as_deref(), output.as_deref())
+        }
+    }
+}
+
+fn quant(mut config: Config, args: QuantArgs) {
+    args.options.apply(&mut config);
+    print_banner();
+    let mut ion_list = load_compounds(&config, &args.ion_list);
+
+    let standard_count = args.standards.len();
+    let file_paths: Vec<String> = args
+        .standards
+        .iter()
+        .map(|(_, file)| file.clone())
+        .chain(args.samples.iter().cloned())
+        .collect();
+    eprintln!(
+        "Processing {} standards and {} samples with ion list: {}",
+        standard_count,
+        args.samples.len(),
+        args.ion_list
+    );
+    let parameters = json!({"config": config, "standards": args.standards});
+    let mut provenance = Provenance::start(&parameters, &ion_list_names(&args.ion_list), &ion_list);
+    let (mut results, cancelled) = run_batch(&config, &file_paths, ion_list.clone());
+    provenance.finish(&file_paths);
+    let sample_results = results.split_off(standard_count);
+
+    // Every standard is needed for the calibration, so a failed one is fatal; after
+    // Ctrl-C the samples are quantified with the standards that were completed
+    let mut standards = Vec::new();
+    for ((concentration, _), FileResult { file, result }) in args.standards.iter().zip(results) {
+        match result {
+            Ok(measurement) => standards.push((*concentration, measurement)),
+            Err(Error::Cancelled) if cancelled => (),
+            Err(e) => fail(format!("Standard {} failed: {}", file, e)),
+        }
+    }
+    let standards: Vec<(f64, &MSMeasurement)> = standards.iter().map(|(c, m)| (*c, m)).collect();
+    calibrate(&mut ion_list, &standards);
+
+    let failed_count = report_failures(&sample_results);
+    let quantities: Vec<(&str, Vec<Quantity>)> = sample_results
+        .iter()
+        .filter_map(|FileResult { file, result }| {
+            result.as_ref().ok().map(|measurement| (file.as_str(), quantify(measurement, &ion_list)))
         })
+        .collect();
+
+    let mut writer = output_writer(config.output.path.as_deref());
+    if let Err(e) = write_quantities_csv(&mut writer, &quantities, &provenance).and_then(|_| writer.flush()) {
+        fail(e);
+    }
+    if let Some(output) = &config.output.path {
+        eprintln!("Results written to {}", output.display());
+    }
+    if cancelled {
+        let completed = sample_results.iter().filter(|r| r.result.is_ok()).count();
+        eprintln!("Cancelled, {} of {} samples were completed", completed, sample_results.len());
+        process::exit(EXIT_CANCELLED);
+    }
+    process::exit(if failed_count > 0 { EXIT_FILES_FAILED } else { 0 });
+}
+
+/// Answer JSON-RPC requests until stdin is closed
+fn serve(config: &Config) {
+    // In server mode stdout carries the JSON-RPC messages, so nothing else may be printed there
+    eprintln!("Running LCMSpector backend version {} in server mode", env!("CARGO_PKG_VERSION"));
+    if let Err(e) = server::serve_stdio(config) {
+        fail(format!("Server error: {}", e));
+    }
+}
+
+/// Create a multi-threaded runtime for async IO operations
+fn build_runtime(worker_threads: usize) -> runtime::Runtime {
+    runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads.max(1))
+        .enable_all()
+        .build()
+        .expect("Failed to create Tokio runtime")
+}
+
+/// Run the HTTP API until the process is killed
+fn serve_http(config: &Config, addr: &str, queue_capacity: usize, retained_jobs: usize) {
+    print_banner();
+    let rt = build_runtime(config.parallelism.worker_threads);
+    let result = rt.block_on(async {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        eprintln!("Listening on http://{} with a queue of {} jobs", listener.local_addr()?, queue_capacity);
+        http::serve_http(listener, queue_capacity, retained_jobs, config.clone()).await
     });
-    
-    // Wait for all batches to complete
-    let batch_results = join_all(batch_futures).await;
-    
-    // Count total processed files
-    let total_processed = batch_results.iter()
-        .filter_map(|r| r.as_ref().ok())
-        .map(|v| v.len())
-        .sum::<usize>();
-    
-    progress_bar.finish_with_message(format!("Processed {} files in {:.2?}", 
-        total_processed, start.elapsed()));
-    
-    println!("Large-batch processing completed in {:.2?} seconds", start.elapsed());
-    
-    // Flatten and collect the results
-    batch_results.into_iter()
-        .filter_map(|r| r.ok())
-        .flatten()
-        .collect()
-
-}
-
-/// Process a batch of files asynchronously
-///
-/// This function handles a smaller subset of files within the large batch processing
-async fn process_file_batch(
-    batch: Vec<String>,
-    ion_list: Arc<Vec<measurements::Compound>>,
-    mass_accuracy: f32,
-    progress_bar: Arc<ProgressBar>
-) -> Vec<measurements::MSMeasurement> {
-    let mut results = Vec::with_capacity(batch.len());
-    
-    for file_path in batch {
-        // File operations could be made async for further optimization
-        // But keeping synchronous for compatibility with existing code
-    let _file_name = Path::new(&file_path).file_name()
-        .map(|f| f.to_string_lossy().to_string())
-        .unwrap_or_else(|| file_path.clone());
-        
-        // Process the file
-        let (ms1_scans, ms2_scans) = loading::load_ms_scans(&file_path);
-        let compounds = processing::construct_xics(&ms1_scans, &ion_list, mass_accuracy as f64);
-        
-        // Store the result
-        results.push(measurements::MSMeasurement::from_data(
-            ms1_scans,  ms2_scans, compounds, mass_accuracy
-        ));
-        
-        // Update progress
-        progress_bar.inc(1);
-    }
-    
-    results
+    if let Err(e) = result {
+        fail(format!("HTTP server error: {}", e));
+    }
 }
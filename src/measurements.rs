@@ -1,76 +1,230 @@
-use mzdata::spectrum::MultiLayerSpectrum;
-use std::collections::HashMap;
+use crate::ion_lists::CompoundEntry;
+use crate::processing::Chromatogram;
+use indexmap::IndexMap;
+use mzdata::spectrum::{MultiLayerSpectrum, ScanPolarity};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct MSMeasurement {
+    /// The path of the file the measurement was read from
+    pub path: String,
+    /// The position of the file in the batch it was processed in
+    pub sample_index: usize,
     pub mass_accuracy: f32,
     pub ms1_scans: Vec<MultiLayerSpectrum>,
     pub ms2_scans: Vec<MultiLayerSpectrum>,
     pub xics: Vec<Compound>,
+    /// TIC and BPC of the MS1 scans, see [`ChromatogramBuilder::for_run`](crate::processing::ChromatogramBuilder::for_run)
+    pub chromatograms: Vec<Chromatogram>,
 }
 
 pub struct LCMeasurement {
     pub lc_scans: Vec<u32>,
 }
 
+/// The ionization mode an ion is detected in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Polarity {
+    Positive,
+    Negative,
+}
+
+impl Polarity {
+    /// The polarity given by an adduct such as `[M+H]+`, or by a label naming the
+    /// mode, such as `Acetate-(-)` or `Adenine-I-pos`
+    pub fn infer(label: Option<&str>, adduct: Option<&str>) -> Option<Polarity> {
+        match adduct.map(str::trim) {
+            Some(adduct) if adduct.ends_with('+') => return Some(Polarity::Positive),
+            Some(adduct) if adduct.ends_with('-') => return Some(Polarity::Negative),
+            _ => (),
+        }
+        let label = label?;
+        if label.contains("(+)") {
+            return Some(Polarity::Positive);
+        }
+        if label.contains("(-)") {
+            return Some(Polarity::Negative);
+        }
+        label
+            .split(['-', ' ', '_'])
+            .find_map(|token| match token.to_lowercase().as_str() {
+                "pos" | "positive" => Some(Polarity::Positive),
+                "neg" | "negative" => Some(Polarity::Negative),
+                _ => None,
+            })
+    }
+
+    /// The polarity a scan was acquired in, `None` if the file does not say
+    pub fn of_scan(polarity: ScanPolarity) -> Option<Polarity> {
+        match polarity {
+            ScanPolarity::Positive => Some(Polarity::Positive),
+            ScanPolarity::Negative => Some(Polarity::Negative),
+            ScanPolarity::Unknown => None,
+        }
+    }
+
+    /// `positive` or `negative`, as serialized
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Polarity::Positive => "positive",
+            Polarity::Negative => "negative",
+        }
+    }
+}
+
+/// An ion of a compound and the results measured for it.
+///
+/// Serializes as the per-ion object of the results JSON, `{"m/z", "RT", "MS Intensity",
+/// "LC Intensity"}`; the label is part of the compound's `ion_info`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ion {
+    #[serde(rename = "m/z")]
+    pub mz: f64,
+    /// Label of the ion from the `info` of the ion list
+    #[serde(skip)]
+    pub label: Option<String>,
+    #[serde(skip)]
+    pub polarity: Option<Polarity>,
+    /// Adduct, e.g. `[M-H]-`
+    #[serde(skip)]
+    pub adduct: Option<String>,
+    /// Retention time of the most intense point of the XIC, in minutes
+    #[serde(rename = "RT")]
+    pub rt: Option<f64>,
+    /// Total intensity of the XIC
+    #[serde(rename = "MS Intensity")]
+    pub ms_intensity: Option<f64>,
+    #[serde(rename = "LC Intensity")]
+    pub lc_intensity: Option<f64>,
+}
+
+impl Ion {
+    /// An ion without label or results
+    pub fn new(mz: f64) -> Self {
+        Ion {
+            mz,
+            label: None,
+            polarity: None,
+            adduct: None,
+            rt: None,
+            ms_intensity: None,
+            lc_intensity: None,
+        }
+    }
+
+    /// An ion labelled from the ion list, with the polarity inferred from the label
+    /// or adduct
+    pub fn labelled(mz: f64, label: Option<String>, adduct: Option<String>) -> Self {
+        let polarity = Polarity::infer(label.as_deref(), adduct.as_deref());
+        Ion {
+            label,
+            polarity,
+            adduct,
+            ..Ion::new(mz)
+        }
+    }
+
+    /// The name of the ion in results and XICs: its m/z as written in the ion list
+    pub fn name(&self) -> String {
+        format!("{}", self.mz)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Compound {
     /// The name of the compound
     pub name: String,
 
-    /// A dictionary of ions with their associated information
-    pub ions: HashMap<String, HashMap<String, Option<f64>>>,
+    /// The ions of the compound, in ion list order
+    pub ions: Vec<Ion>,
 
     /// List of MS2 data (type not specified in original, so using a generic Vec)
     pub ms2: Vec<()>,
 
-    /// Additional ion information
-    pub ion_info: Vec<String>,
+    /// Calibration curve data, sorted by key so it serializes the same on every run
+    pub calibration_curve: BTreeMap<String, f64>,
 
-    /// Calibration curve data
-    pub calibration_curve: HashMap<String, f64>,
+    /// Extracted ion chromatogram of each ion by [`Ion::name`], as scan times and intensities
+    pub xics: HashMap<String, (Vec<f64>, Vec<f64>)>,
 }
 
 impl Compound {
+    /// A compound with the ions `ions`, labelled by position from `ion_info`
     pub fn new(name: String, ions: Vec<f64>, ion_info: Vec<String>) -> Self {
+        let mut labels = ion_info.into_iter();
+        let ions = ions
+            .into_iter()
+            .map(|mz| Ion::labelled(mz, labels.next(), None))
+            .collect();
+        Self::from_ions(name, ions)
+    }
+
+    pub fn from_ions(name: String, ions: Vec<Ion>) -> Self {
         Compound {
-            name: name.clone(),
-            ions: ions
-                .into_iter()
-                .map(|ion| {
-                    let ion_name = format!("{ion}");
-                    (
-                        ion_name,
-                        HashMap::from([
-                            ("m/z".to_string(), Some(ion)),
-                            ("RT".to_string(), None),
-                            ("MS Intensity".to_string(), None),
-                            ("LC Intensity".to_string(), None),
-                        ]),
-                    )
-                })
-                .collect(),
+            name,
+            ions,
             ms2: Vec::new(),
-            ion_info,
-            calibration_curve: HashMap::new(),
+            calibration_curve: BTreeMap::new(),
+            xics: HashMap::new(),
         }
     }
 
-    /// Create a Compound from a JSON entry in the ion lists
-    pub fn from_ion_list_entry(name: String, ions: &Vec<f64>, ion_info: &Vec<String>) -> Self {
-        Self::new(name, ions.clone(), ion_info.clone())
+    /// Create a Compound from an entry in the ion lists
+    pub fn from_ion_list_entry(name: String, entry: &CompoundEntry) -> Self {
+        let label = |labels: &[String], i: usize| labels.get(i).filter(|label| !label.is_empty()).cloned();
+        let ions = entry
+            .ions
+            .iter()
+            .enumerate()
+            .map(|(i, mz)| Ion::labelled(*mz, label(&entry.info, i), label(&entry.adducts, i)))
+            .collect();
+        Self::from_ions(name, ions)
+    }
+
+    /// The ion with the m/z `mz`
+    pub fn ion(&self, mz: f64) -> Option<&Ion> {
+        self.ions.iter().find(|ion| ion.mz == mz)
+    }
+
+    /// The labels of the ions, in ion order, empty for unlabelled ions
+    pub fn ion_info(&self) -> Vec<String> {
+        self.ions
+            .iter()
+            .map(|ion| ion.label.clone().unwrap_or_default())
+            .collect()
+    }
+}
+
+/// Serializes as the compound object of the results JSON, with the ions keyed by name
+/// in ion list order
+impl Serialize for Compound {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ions: IndexMap<String, &Ion> = self.ions.iter().map(|ion| (ion.name(), ion)).collect();
+        let mut compound = serializer.serialize_struct("Compound", 5)?;
+        compound.serialize_field("name", &self.name)?;
+        compound.serialize_field("ions", &ions)?;
+        compound.serialize_field("ms2", &self.ms2)?;
+        compound.serialize_field("ion_info", &self.ion_info())?;
+        compound.serialize_field("calibration_curve", &self.calibration_curve)?;
+        compound.end()
     }
 }
 
-// Optional: Implement Display trait for string representation
 impl fmt::Display for Compound {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Compound: {}, ions: {:?}, ion info: {:?}",
-            self.name, self.ions, self.ion_info
-        )
+        let ions: Vec<String> = self
+            .ions
+            .iter()
+            .map(|ion| match &ion.label {
+                Some(label) => format!("{} ({})", ion.name(), label),
+                None => ion.name(),
+            })
+            .collect();
+        write!(f, "Compound: {}, ions: {}", self.name, ions.join(", "))
     }
 }
 
@@ -84,10 +238,51 @@ impl MSMeasurement {
     ) -> Self {
 
         MSMeasurement {
+            path: String::new(),
+            sample_index: 0,
             ms1_scans,
             ms2_scans,
             xics,
+            chromatograms: Vec::new(),
             mass_accuracy,
         }
     }
-}
\ No newline at end of file
+
+    /// Attach the TIC and BPC of the MS1 scans
+    pub fn with_chromatograms(mut self, chromatograms: Vec<Chromatogram>) -> Self {
+        self.chromatograms = chromatograms;
+        self
+    }
+
+    /// Record the file the measurement was read from and its position in the batch
+    pub fn with_source(mut self, path: &str, sample_index: usize) -> Self {
+        self.path = path.to_string();
+        self.sample_index = sample_index;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ions_keep_ion_list_order_and_labels() {
+        let entry = CompoundEntry {
+            ions: vec![61.0284, 59.0139, 119.0350],
+            info: vec!["Acetate-(+)".to_string(), "Acetate-(-)".to_string(), String::new()],
+            adducts: vec![String::new(), String::new(), "[2M-H]-".to_string()],
+            ..CompoundEntry::default()
+        };
+        let compound = Compound::from_ion_list_entry("Acetate".to_string(), &entry);
+        let mzs: Vec<f64> = compound.ions.iter().map(|ion| ion.mz).collect();
+        assert_eq!(mzs, entry.ions);
+        let polarities: Vec<_> = compound.ions.iter().map(|ion| ion.polarity).collect();
+        assert_eq!(polarities, [Some(Polarity::Positive), Some(Polarity::Negative), Some(Polarity::Negative)]);
+        assert_eq!(compound.ions[2].label, None);
+        assert_eq!(compound.ions[2].adduct.as_deref(), Some("[2M-H]-"));
+        assert_eq!(Polarity::infer(Some("Adenine-I-pos"), None), Some(Polarity::Positive));
+        assert_eq!(Polarity::infer(Some("Formate dimer (+)"), None), Some(Polarity::Positive));
+        assert_eq!(Polarity::infer(Some("Lactate"), None), None);
+    }
+}
@@ -0,0 +1,274 @@
+//! What MS data files record about how they were acquired.
+//!
+//! mzML files describe their instruments, the software that wrote them and when the
+//! run was started in a header before the first spectrum. mzXML and MGF files are read
+//! without one, so their header is empty.
+//!
+//! [`summarize_file`] adds what the spectra themselves show, reading them with the same
+//! readers as [`load_ms_scans`](crate::load_ms_scans): polarities, MS levels, RT and
+//! m/z ranges, centroid or profile data and activation types.
+
+use crate::error::{Error, Result};
+use crate::loading::{detect_format, is_gzipped, open_source, open_spectra, MSFileFormat};
+use crate::measurements::Polarity;
+use mzdata::meta::{ComponentType, MSDataFileMetadata};
+use mzdata::spectrum::{SignalContinuity, SpectrumLike};
+use mzdata::MzMLReader;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The acquisition details in the header of a file
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FileHeader {
+    /// Model of every instrument configuration
+    pub instruments: Vec<String>,
+    /// Ion sources of the instrument configurations
+    pub ion_sources: Vec<String>,
+    /// Mass analyzers of the instrument configurations
+    pub analyzers: Vec<String>,
+    /// Software that acquired or processed the data, with its version
+    pub software: Vec<String>,
+    /// When the run was started, in RFC 3339 format
+    pub acquired_at: Option<String>,
+}
+
+/// Read the header of a file without reading its spectra
+pub fn read_file_header(file_path: &str) -> Result<FileHeader> {
+    if detect_format(file_path)? != MSFileFormat::MzML {
+        return Ok(FileHeader::default());
+    }
+    let (source, _) = open_source(file_path)?;
+    let reader = MzMLReader::new(source);
+
+    let mut header = FileHeader::default();
+    let mut configurations: Vec<_> = reader.instrument_configurations().values().collect();
+    configurations.sort_by_key(|configuration| configuration.id);
+    for configuration in configurations {
+        if let Some(model) = configuration.params.first() {
+            push_unique(&mut header.instruments, &model.name);
+        }
+        for component in &configuration.components {
+            let names = match component.component_type {
+                ComponentType::IonSource => &mut header.ion_sources,
+                ComponentType::Analyzer => &mut header.analyzers,
+                _ => continue,
+            };
+            if let Some(name) = component.name() {
+                push_unique(names, name);
+            }
+        }
+    }
+    for software in reader.softwares() {
+        let name = software.params.first().map_or(software.id.as_str(), |param| param.name.as_str());
+        match software.version.is_empty() {
+            true => push_unique(&mut header.software, name),
+            false => push_unique(&mut header.software, &format!("{} {}", name, software.version)),
+        }
+    }
+    header.acquired_at = reader
+        .run_description()
+        .and_then(|run| run.start_time)
+        .map(|start| start.to_rfc3339());
+    Ok(header)
+}
+
+/// What a file holds, from its header and a pass over its spectra
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileSummary {
+    pub path: String,
+    pub format: MSFileFormat,
+    /// Whether the file is gzip-compressed
+    pub gzip: bool,
+    #[serde(flatten)]
+    pub header: FileHeader,
+    /// The polarities scans were acquired in, in order of appearance
+    pub polarities: Vec<Polarity>,
+    /// Number of scans of every MS level
+    pub ms_levels: BTreeMap<u8, usize>,
+    /// First and last retention time in minutes
+    pub rt_range: Option<(f64, f64)>,
+    /// Lowest and highest m/z of all data points
+    pub mz_range: Option<(f64, f64)>,
+    /// Number of centroided scans
+    pub centroid_scans: usize,
+    /// Number of profile scans
+    pub profile_scans: usize,
+    /// Activation methods of the MSn scans, in order of appearance
+    pub activations: Vec<String>,
+}
+
+impl FileSummary {
+    /// Number of scans of `ms_level`
+    pub fn scans(&self, ms_level: u8) -> usize {
+        self.ms_levels.get(&ms_level).copied().unwrap_or(0)
+    }
+
+    /// `centroid`, `profile` or `mixed`, or `None` if the file has no scans
+    pub fn spectrum_mode(&self) -> Option<&'static str> {
+        match (self.centroid_scans, self.profile_scans) {
+            (0, 0) => None,
+            (_, 0) => Some("centroid"),
+            (0, _) => Some("profile"),
+            _ => Some("mixed"),
+        }
+    }
+}
+
+/// Summarize a file, reading its header and all of its spectra.
+///
+/// Fails like [`open_spectra`] if the file cannot be opened, and if it breaks off
+/// before its last spectrum.
+pub fn summarize_file(file_path: &str) -> Result<FileSummary> {
+    let mut summary = FileSummary {
+        path: file_path.to_string(),
+        format: detect_format(file_path)?,
+        gzip: is_gzipped(file_path).map_err(|e| Error::io(file_path, e))?,
+        header: read_file_header(file_path)?,
+        polarities: Vec::new(),
+        ms_levels: BTreeMap::new(),
+        rt_range: None,
+        mz_range: None,
+        centroid_scans: 0,
+        profile_scans: 0,
+        activations: Vec::new(),
+    };
+    let mut spectra = open_spectra(file_path)?;
+    for spectrum in spectra.by_ref() {
+        *summary.ms_levels.entry(spectrum.ms_level()).or_default() += 1;
+        let polarity = Polarity::of_scan(spectrum.polarity());
+        if let Some(polarity) = polarity.filter(|p| !summary.polarities.contains(p)) {
+            summary.polarities.push(polarity);
+        }
+        match spectrum.signal_continuity() {
+            SignalContinuity::Profile => summary.profile_scans += 1,
+            SignalContinuity::Centroid => summary.centroid_scans += 1,
+            SignalContinuity::Unknown => (),
+        }
+        if let Some(precursor) = spectrum.precursor() {
+            for method in precursor.activation.methods() {
+                push_unique(&mut summary.activations, method.name());
+            }
+        }
+        summary.rt_range = Some(widen(summary.rt_range, spectrum.start_time(), spectrum.start_time()));
+        let mzs = spectrum.arrays.as_ref().and_then(|arrays| arrays.mzs().ok());
+        if let Some((&first, &last)) = mzs.as_ref().and_then(|mzs| mzs.first().zip(mzs.last())) {
+            // Data points are sorted by m/z
+            summary.mz_range = Some(widen(summary.mz_range, first, last));
+        }
+    }
+    if let Some(e) = spectra.take_error() {
+        return Err(Error::io(file_path, e));
+    }
+    Ok(summary)
+}
+
+/// Summarize several files in parallel, returning one result per file in input order
+pub fn summarize_files<S: AsRef<str> + Sync>(file_paths: &[S]) -> Vec<Result<FileSummary>> {
+    file_paths.par_iter().map(|path| summarize_file(path.as_ref())).collect()
+}
+
+/// `range` extended to include `low` and `high`
+fn widen(range: Option<(f64, f64)>, low: f64, high: f64) -> (f64, f64) {
+    range.map_or((low, high), |(start, end)| (start.min(low), end.max(high)))
+}
+
+fn push_unique(values: &mut Vec<String>, value: &str) {
+    if !values.iter().any(|v| v == value) {
+        values.push(value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{synthetic_run, temp_path, write_mzml};
+    use mzdata::meta::DissociationMethodTerm;
+    use mzdata::spectrum::{Precursor, SelectedIon};
+
+    /// The header of a Q Exactive run converted by msconvert, without its spectra
+    const QEXACTIVE_HEADER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<mzML xmlns="http://psi.hupo.org/ms/mzml" version="1.1.0">
+  <cvList count="1">
+    <cv id="MS" fullName="Proteomics Standards Initiative Mass Spectrometry Ontology" version="4.1.30" URI="https://raw.githubusercontent.com/HUPO-PSI/psi-ms-CV/master/psi-ms.obo"/>
+  </cvList>
+  <fileDescription>
+    <fileContent>
+      <cvParam cvRef="MS" accession="MS:1000579" name="MS1 spectrum" value=""/>
+    </fileContent>
+  </fileDescription>
+  <softwareList count="2">
+    <software id="Xcalibur" version="4.1.31.9">
+      <cvParam cvRef="MS" accession="MS:1000532" name="Xcalibur" value=""/>
+    </software>
+    <software id="pwiz" version="3.0.21193">
+      <cvParam cvRef="MS" accession="MS:1000615" name="ProteoWizard software" value=""/>
+    </software>
+  </softwareList>
+  <instrumentConfigurationList count="1">
+    <instrumentConfiguration id="IC1">
+      <cvParam cvRef="MS" accession="MS:1001911" name="Q Exactive" value=""/>
+      <componentList count="3">
+        <source order="1">
+          <cvParam cvRef="MS" accession="MS:1000073" name="electrospray ionization" value=""/>
+        </source>
+        <analyzer order="2">
+          <cvParam cvRef="MS" accession="MS:1000484" name="orbitrap" value=""/>
+        </analyzer>
+        <detector order="3">
+          <cvParam cvRef="MS" accession="MS:1000624" name="inductive detector" value=""/>
+        </detector>
+      </componentList>
+    </instrumentConfiguration>
+  </instrumentConfigurationList>
+  <dataProcessingList count="1">
+    <dataProcessing id="pwiz_Reader_Thermo_conversion">
+      <processingMethod order="0" softwareRef="pwiz">
+        <cvParam cvRef="MS" accession="MS:1000544" name="Conversion to mzML" value=""/>
+      </processingMethod>
+    </dataProcessing>
+  </dataProcessingList>
+  <run id="STMIX_BIG_2.5mM_pos" defaultInstrumentConfigurationRef="IC1" startTimeStamp="2024-03-05T10:15:00Z">
+    <spectrumList count="0" defaultDataProcessingRef="pwiz_Reader_Thermo_conversion">
+    </spectrumList>
+  </run>
+</mzML>
+"#;
+
+    #[test]
+    fn test_read_file_header() {
+        let path = temp_path("header.mzML");
+        std::fs::write(&path, QEXACTIVE_HEADER).unwrap();
+        let header = read_file_header(path.to_str().unwrap()).unwrap();
+        assert_eq!(header.instruments, ["Q Exactive"]);
+        assert_eq!(header.ion_sources, ["electrospray ionization"]);
+        assert_eq!(header.analyzers, ["orbitrap"]);
+        assert_eq!(header.software, ["Xcalibur 4.1.31.9", "ProteoWizard software 3.0.21193"]);
+        assert_eq!(header.acquired_at.as_deref(), Some("2024-03-05T10:15:00+00:00"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_summarize_file() {
+        let mut spectra = synthetic_run();
+        let mut precursor = Precursor::default();
+        precursor.ions.push(SelectedIon { mz: 59.0139, ..SelectedIon::default() });
+        precursor.activation.methods_mut().push(DissociationMethodTerm::BeamTypeCollisionInducedDissociation);
+        spectra[1].description.precursor = Some(precursor);
+        spectra[0].description.signal_continuity = SignalContinuity::Profile;
+        let path = write_mzml("summary.mzML", &spectra);
+
+        let summary = summarize_file(path.to_str().unwrap()).unwrap();
+        assert_eq!((summary.format, summary.gzip), (MSFileFormat::MzML, false));
+        assert_eq!((summary.scans(1), summary.scans(2), summary.scans(3)), (10, 10, 0));
+        assert_eq!(summary.polarities, [Polarity::Negative]);
+        assert_eq!(summary.rt_range, Some((0.0, 0.9 + 0.05)));
+        assert_eq!(summary.mz_range, Some((41.0, 100.0)));
+        assert_eq!(summary.spectrum_mode(), Some("mixed"));
+        assert_eq!(summary.activations, ["beam-type collision-induced dissociation"]);
+
+        let summaries = summarize_files(&[path.to_str().unwrap(), "missing.mzML"]);
+        assert!(matches!(summaries[..], [Ok(_), Err(_)]));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+use base64_simd::STANDARD as BASE64;
+use flate2::read::ZlibDecoder;
+use mzdata::params::ParamList;
+use mzdata::spectrum::bindata::{ArrayType, BinaryArrayMap, BinaryDataArrayType, DataArray};
+use mzdata::spectrum::{
+    Acquisition, MultiLayerSpectrum, Precursor, ScanEvent, ScanPolarity, SelectedIon,
+    SignalContinuity, SpectrumDescription,
+};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+use std::sync::{Arc, Mutex};
+
+/// A minimal streaming reader for the legacy mzXML format.
+///
+/// mzdata does not ship an mzXML reader, so this covers the subset of the schema
+/// our instruments produce: `scan` elements (possibly nested), their `precursorMz`
+/// and the base64-encoded `peaks` payload with optional zlib compression. Spectra are
+/// yielded in document order as [`MultiLayerSpectrum`] so they can be handled exactly
+/// like spectra coming from `MzMLReader`.
+///
+/// Iteration stops at the first malformed or truncated element; the error is kept
+/// and can be retrieved with [`MzXMLReader::take_error`].
+pub struct MzXMLReader<R: BufRead> {
+    reader: Reader<R>,
+    buffer: Vec<u8>,
+    /// Scans that are currently open, innermost last
+    open_scans: Vec<ScanBuilder>,
+    /// Spectra that are complete but have not been handed out yet
+    ready: VecDeque<MultiLayerSpectrum>,
+    /// Which element's text content we are currently collecting
+    text_target: TextTarget,
+    index: usize,
+    done: bool,
+    /// Whether `</msRun>` was reached, so the document was read to its end
+    run_closed: bool,
+    /// The error that ended parsing, shared with whoever needs to report it
+    read_error: Arc<Mutex<Option<io::Error>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextTarget {
+    None,
+    PrecursorMz,
+    Peaks,
+}
+
+#[derive(Debug, Default)]
+struct ScanBuilder {
+    num: String,
+    ms_level: u8,
+    polarity: ScanPolarity,
+    /// From the `centroided` attribute; `Unknown` when it is missing
+    continuity: SignalContinuity,
+    retention_time: f64,
+    precision: usize,
+    zlib: bool,
+    little_endian: bool,
+    precursor: Option<SelectedIon>,
+    precursor_text: String,
+    peaks_text: String,
+    emitted: bool,
+}
+
+impl<R: BufRead> MzXMLReader<R> {
+    pub fn new(source: R) -> Self {
+        let mut reader = Reader::from_reader(source);
+        reader.trim_text(true);
+        MzXMLReader {
+            reader,
+            buffer: Vec::new(),
+            open_scans: Vec::new(),
+            ready: VecDeque::new(),
+            text_target: TextTarget::None,
+            index: 0,
+            done: false,
+            run_closed: false,
+            read_error: Arc::default(),
+        }
+    }
+
+    /// Keep the error that ends parsing in `slot`, so it can still be retrieved once
+    /// the reader has been moved into an iterator chain. An error already in the slot,
+    /// e.g. from the underlying reader, is not replaced.
+    pub fn with_error_slot(mut self, slot: Arc<Mutex<Option<io::Error>>>) -> Self {
+        self.read_error = slot;
+        self
+    }
+
+    /// The error that ended iteration early, if any
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.read_error.lock().unwrap().take()
+    }
+
+    /// Parse events until at least one spectrum is ready or the document ends
+    fn fill(&mut self) -> io::Result<()> {
+        while self.ready.is_empty() && !self.done {
+            self.buffer.clear();
+            let event = self
+                .reader
+                .read_event_into(&mut self.buffer)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            match event {
+                Event::Start(ref e) => {
+                    let start = e.to_owned();
+                    self.handle_start(&start)?;
+                }
+                Event::Empty(ref e) => {
+                    let start = e.to_owned();
+                    self.handle_start(&start)?;
+                    self.handle_end(start.name().as_ref())?;
+                }
+                Event::Text(ref t) => {
+                    let text = t
+                        .unescape()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    if let Some(scan) = self.open_scans.last_mut() {
+                        match self.text_target {
+                            TextTarget::PrecursorMz => scan.precursor_text.push_str(&text),
+                            TextTarget::Peaks => scan.peaks_text.push_str(&text),
+                            TextTarget::None => (),
+                        }
+                    }
+                }
+                Event::End(ref e) => {
+                    let name = e.name().as_ref().to_vec();
+                    self.handle_end(&name)?;
+                }
+                Event::Eof => {
+                    if !self.open_scans.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "mzXML document ended inside a <scan> element",
+                        ));
+                    }
+                    if !self.run_closed {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "mzXML document ended before </msRun>",
+                        ));
+                    }
+                    self.done = true;
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_start(&mut self, element: &BytesStart) -> io::Result<()> {
+        match element.name().as_ref() {
+            b"scan" => {
+                // mzXML nests MSn scans inside their parent scan, after the parent's peaks.
+                // Emit the parent first so spectra keep their acquisition order.
+                self.emit_innermost()?;
+                let mut scan = ScanBuilder {
+                    ms_level: 1,
+                    precision: 32,
+                    ..Default::default()
+                };
+                let mut ms_level = None;
+                for attr in element.attributes().flatten() {
+                    let value = attr
+                        .unescape_value()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    match attr.key.as_ref() {
+                        b"num" => scan.num = value.to_string(),
+                        b"msLevel" => ms_level = Some(value.to_string()),
+                        b"polarity" => {
+                            scan.polarity = match value.as_ref() {
+                                "+" => ScanPolarity::Positive,
+                                "-" => ScanPolarity::Negative,
+                                _ => ScanPolarity::Unknown,
+                            }
+                        }
+                        b"centroided" => {
+                            scan.continuity = match value.as_ref() {
+                                "1" => SignalContinuity::Centroid,
+                                "0" => SignalContinuity::Profile,
+                                _ => SignalContinuity::Unknown,
+                            }
+                        }
+                        b"retentionTime" => scan.retention_time = parse_duration_minutes(&value),
+                        _ => (),
+                    }
+                }
+                if let Some(ms_level) = ms_level {
+                    scan.ms_level = ms_level.parse().map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Invalid msLevel {ms_level:?} in scan {}", scan.num),
+                        )
+                    })?;
+                }
+                self.open_scans.push(scan);
+            }
+            b"precursorMz" => {
+                if let Some(scan) = self.open_scans.last_mut() {
+                    let mut ion = SelectedIon::default();
+                    for attr in element.attributes().flatten() {
+                        let value = attr.unescape_value().unwrap_or_default();
+                        match attr.key.as_ref() {
+                            b"precursorIntensity" => ion.intensity = value.parse().unwrap_or(0.0),
+                            b"precursorCharge" => ion.charge = value.parse().ok(),
+                            _ => (),
+                        }
+                    }
+                    scan.precursor = Some(ion);
+                    scan.precursor_text.clear();
+                    self.text_target = TextTarget::PrecursorMz;
+                }
+            }
+            b"peaks" => {
+                if let Some(scan) = self.open_scans.last_mut() {
+                    for attr in element.attributes().flatten() {
+                        let value = attr.unescape_value().unwrap_or_default();
+                        match attr.key.as_ref() {
+                            b"precision" => scan.precision = value.parse().unwrap_or(32),
+                            b"compressionType" => scan.zlib = value == "zlib",
+                            b"byteOrder" => scan.little_endian = value == "little",
+                            _ => (),
+                        }
+                    }
+                    scan.peaks_text.clear();
+                    self.text_target = TextTarget::Peaks;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn handle_end(&mut self, name: &[u8]) -> io::Result<()> {
+        match name {
+            b"scan" => {
+                self.emit_innermost()?;
+                self.open_scans.pop();
+            }
+            b"precursorMz" | b"peaks" => self.text_target = TextTarget::None,
+            b"msRun" => self.run_closed = true,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Turn the innermost open scan into a spectrum, if it hasn't been already
+    fn emit_innermost(&mut self) -> io::Result<()> {
+        let index = self.index;
+        let Some(scan) = self.open_scans.last_mut() else {
+            return Ok(());
+        };
+        if scan.emitted {
+            return Ok(());
+        }
+        scan.emitted = true;
+        let spectrum = scan.build(index)?;
+        self.index += 1;
+        self.ready.push_back(spectrum);
+        Ok(())
+    }
+}
+
+impl ScanBuilder {
+    fn build(&self, index: usize) -> io::Result<MultiLayerSpectrum> {
+        let (mzs, intensities) = decode_peaks(
+            self.peaks_text.trim(),
+            self.precision,
+            self.zlib,
+            self.little_endian,
+        )?;
+
+        let mut mz_array = DataArray::from_name_and_type(&ArrayType::MZArray, BinaryDataArrayType::Float64);
+        let mut intensity_array =
+            DataArray::from_name_and_type(&ArrayType::IntensityArray, BinaryDataArrayType::Float32);
+        mz_array
+            .update_buffer(&mzs)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+        intensity_array
+            .update_buffer(&intensities)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+        let mut arrays = BinaryArrayMap::new();
+        arrays.add(mz_array);
+        arrays.add(intensity_array);
+
+        let precursor = match self.precursor.clone() {
+            Some(mut ion) => {
+                let text = self.precursor_text.trim();
+                ion.mz = text.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid precursorMz {text:?} in scan {}", self.num),
+                    )
+                })?;
+                Some(Precursor {
+                    ions: vec![ion],
+                    ..Default::default()
+                })
+            }
+            None => None,
+        };
+
+        let acquisition = Acquisition {
+            scans: vec![ScanEvent {
+                start_time: self.retention_time,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let description = SpectrumDescription::new(
+            format!("scan={}", self.num),
+            index,
+            self.ms_level,
+            self.polarity,
+            self.continuity,
+            ParamList::new(),
+            acquisition,
+            precursor,
+        );
+
+        Ok(MultiLayerSpectrum::from_arrays_and_description(arrays, description))
+    }
+}
+
+impl<R: BufRead> Iterator for MzXMLReader<R> {
+    type Item = MultiLayerSpectrum;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            self.read_error.lock().unwrap().get_or_insert(e);
+            self.done = true;
+        }
+        self.ready.pop_front()
+    }
+}
+
+/// Decode an mzXML `peaks` payload into separate m/z and intensity arrays.
+///
+/// The payload is interleaved m/z-intensity pairs, in network byte order unless stated otherwise.
+fn decode_peaks(
+    encoded: &str,
+    precision: usize,
+    zlib: bool,
+    little_endian: bool,
+) -> io::Result<(Vec<f64>, Vec<f32>)> {
+    if encoded.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let raw = BASE64
+        .decode_to_vec(encoded.as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid base64 in <peaks>: {e}")))?;
+    let bytes = if zlib {
+        let mut decompressed = Vec::new();
+        ZlibDecoder::new(raw.as_slice()).read_to_end(&mut decompressed)?;
+        decompressed
+    } else {
+        raw
+    };
+
+    let width = precision / 8;
+    if width != 4 && width != 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported <peaks> precision: {precision}"),
+        ));
+    }
+    if bytes.len() % (2 * width) != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Truncated <peaks> payload",
+        ));
+    }
+
+    let values: Vec<f64> = bytes
+        .chunks_exact(width)
+        .map(|chunk| match (width, little_endian) {
+            (4, false) => f32::from_be_bytes(chunk.try_into().unwrap()) as f64,
+            (4, true) => f32::from_le_bytes(chunk.try_into().unwrap()) as f64,
+            (_, false) => f64::from_be_bytes(chunk.try_into().unwrap()),
+            (_, true) => f64::from_le_bytes(chunk.try_into().unwrap()),
+        })
+        .collect();
+
+    let mzs = values.iter().step_by(2).copied().collect();
+    let intensities = values.iter().skip(1).step_by(2).map(|v| *v as f32).collect();
+    Ok((mzs, intensities))
+}
+
+/// Convert an `xs:duration` such as `PT123.45S` or `PT2M3.5S` to minutes
+fn parse_duration_minutes(value: &str) -> f64 {
+    let Some(time) = value.trim().strip_prefix("PT") else {
+        return value.parse::<f64>().map(|s| s / 60.0).unwrap_or(0.0);
+    };
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    for c in time.chars() {
+        match c {
+            'H' => {
+                seconds += number.parse::<f64>().unwrap_or(0.0) * 3600.0;
+                number.clear();
+            }
+            'M' => {
+                seconds += number.parse::<f64>().unwrap_or(0.0) * 60.0;
+                number.clear();
+            }
+            'S' => {
+                seconds += number.parse::<f64>().unwrap_or(0.0);
+                number.clear();
+            }
+            _ => number.push(c),
+        }
+    }
+    seconds / 60.0
+}
@@ -1,129 +1,400 @@
-use crate::measurements::Compound;
-use mzdata::spectrum::{MultiLayerSpectrum};
+use crate::cancel::CancellationToken;
+use crate::error::{Error, Result};
+use crate::measurements::{Compound, Polarity};
+use mzdata::spectrum::{MultiLayerSpectrum, SpectrumLike};
 use ndarray::Array2;
 use rayon::prelude::*;
-use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use std::borrow::{Borrow, Cow};
 
 /// Optimized function to construct extracted ion chromatograms (XICs) from MS data
 /// 
-/// This implementation uses Rayon for parallel processing of compounds and ions,
-/// designed to be both thread-safe and efficient, making it suitable for both
-/// single and multi-process environments.
-pub fn construct_xics<'a>(
-    data: &'a [MultiLayerSpectrum],
-    ion_list: &'a [Compound],
+/// All ions of the ion list are matched against each spectrum in a single merge pass
+/// (see [`XicBuilder`]). Spectra are split into chunks that are matched in parallel
+/// with Rayon and the partial traces are concatenated in acquisition order, making it
+/// suitable for both single and multi-process environments.
+///
+/// Fails with [`Error::Cancelled`] if `cancel` is triggered before all spectra are matched.
+pub fn construct_xics<S: Borrow<MultiLayerSpectrum> + Sync>(
+    data: &[S],
+    ion_list: &[Compound],
     mass_accuracy: f64,
-) -> Vec<Compound> {
-    let start_time = Instant::now();
-    println!("Starting to construct XICs...");
-    
-    // Process compounds in parallel using Rayon
-    let compounds: Vec<Compound> = ion_list
-        .par_iter()
-        .map(|compound| {
-            // Clone the compound for mutation
-            let mut compound_clone = compound.clone();
-            
-            // Extract all ion names first to avoid borrowing issues
-            let ion_names: Vec<String> = compound_clone.ions.keys().cloned().collect();
-            
-            // Process each ion and collect results
-            let ion_results: Vec<(String, Option<f64>, Option<f64>)> = ion_names
-                .par_iter()
-                .map(|ion_name| {
-                    // Process each ion independently
-                    process_ion(ion_name, data, mass_accuracy)
-                })
-                .collect();
-            
-            // Update the compound with results
-            for (ion_name, ms_intensity, rt) in ion_results {
-                if let Some(ion_data) = compound_clone.ions.get_mut(&ion_name) {
-                    ion_data.insert("MS Intensity".to_string(), ms_intensity);
-                    if let Some(rt_value) = rt {
-                        ion_data.insert("RT".to_string(), Some(rt_value));
+    cancel: &CancellationToken,
+) -> Result<Vec<Compound>> {
+    // Validates the ion list once, before any work is split up
+    let mut builder = XicBuilder::new(ion_list, mass_accuracy)?;
+
+    let chunk_size = data.len().div_ceil(rayon::current_num_threads()).max(1);
+    let partial_builders: Vec<XicBuilder> = data
+        .par_chunks(chunk_size)
+        .map(|chunk| {
+            let mut builder = XicBuilder::new(ion_list, mass_accuracy)?;
+            for spectrum in chunk {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                builder.add_spectrum(spectrum.borrow())?;
+            }
+            Ok(builder)
+        })
+        .collect::<Result<_>>()?;
+    if cancel.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    for partial in partial_builders {
+        builder.append(partial);
+    }
+    Ok(builder.finish())
+}
+
+/// Builds XICs for a whole ion list from spectra that are fed in one at a time.
+///
+/// Unlike keeping all scans around, the spectra do not have to stay in memory: each one
+/// is matched against every ion of the ion list and can be dropped right after, so
+/// memory use is bounded by the size of the XICs rather than by the raw data.
+///
+/// Matching relies on the m/z arrays being sorted. The ion windows are sorted once by
+/// their lower bound and merged against the peak array, so a spectrum costs one binary
+/// search per ion plus the matched peaks, instead of a scan over all peaks per ion.
+pub struct XicBuilder {
+    compounds: Vec<Compound>,
+    /// Compound index, ion index and m/z window of every ion in the ion list
+    targets: Vec<(usize, usize, (f64, f64))>,
+    /// Indices into `targets`, ordered by the lower bound of their m/z window
+    merge_order: Vec<usize>,
+    /// Matched scan times and intensities, one trace per target
+    traces: Vec<(Vec<f64>, Vec<f64>)>,
+}
+
+impl XicBuilder {
+    /// Fails with [`Error::InvalidIon`] if an ion of the ion list is not an m/z value
+    pub fn new(ion_list: &[Compound], mass_accuracy: f64) -> Result<Self> {
+        let mut targets: Vec<(usize, usize, (f64, f64))> = Vec::new();
+        for (compound_index, compound) in ion_list.iter().enumerate() {
+            for (ion_index, ion) in compound.ions.iter().enumerate() {
+                let mass_range = ion_mass_range(ion.mz, mass_accuracy).ok_or_else(|| Error::InvalidIon {
+                    compound: compound.name.clone(),
+                    ion: ion.name(),
+                })?;
+                targets.push((compound_index, ion_index, mass_range));
+            }
+        }
+        let mut merge_order: Vec<usize> = (0..targets.len()).collect();
+        merge_order.sort_by(|a, b| targets[*a].2 .0.total_cmp(&targets[*b].2 .0));
+        let traces = vec![(Vec::new(), Vec::new()); targets.len()];
+        Ok(XicBuilder {
+            compounds: ion_list.to_vec(),
+            targets,
+            merge_order,
+            traces,
+        })
+    }
+
+    /// Match one MS1 spectrum against all ions of the ion list.
+    ///
+    /// Spectra without peak arrays are skipped; fails with [`Error::Spectrum`] if the
+    /// arrays are there but cannot be decoded.
+    pub fn add_spectrum(&mut self, spectrum: &MultiLayerSpectrum) -> Result<()> {
+        let Some((mzs, intensity_values)) = peak_arrays(spectrum)? else {
+            return Ok(());
+        };
+        let scan_time = spectrum.description.acquisition.start_time();
+
+        // Windows are visited by increasing lower bound, so the first candidate peak
+        // of the next window is never before the first candidate of the current one
+        let mut cursor = 0;
+        for &target in &self.merge_order {
+            let mass_range = self.targets[target].2;
+            cursor += mzs[cursor..].partition_point(|mz| *mz < mass_range.0);
+            let (scan_times, intensities) = &mut self.traces[target];
+            for (mz, intensity) in mzs[cursor..].iter().zip(&intensity_values[cursor..]) {
+                if *mz > mass_range.1 {
+                    break;
+                }
+                intensities.push(*intensity as f64);
+                scan_times.push(scan_time);
+            }
+        }
+        Ok(())
+    }
+
+    /// Append the traces of a builder that was fed the spectra following ours
+    pub fn append(&mut self, other: XicBuilder) {
+        for ((scan_times, intensities), (other_times, other_intensities)) in
+            self.traces.iter_mut().zip(other.traces)
+        {
+            scan_times.extend(other_times);
+            intensities.extend(other_intensities);
+        }
+    }
+
+    /// Summarise the accumulated XICs into the compounds of the ion list.
+    ///
+    /// The traces themselves are moved into [`Compound::xics`].
+    pub fn finish(mut self) -> Vec<Compound> {
+        for ((compound_index, ion_index, _), (scan_times, intensities)) in
+            self.targets.into_iter().zip(self.traces)
+        {
+            let compound = &mut self.compounds[compound_index];
+            let ion = &mut compound.ions[ion_index];
+            let (ms_intensity, rt) = summarize_xic(&scan_times, &intensities);
+            ion.ms_intensity = ms_intensity;
+            if rt.is_some() {
+                ion.rt = rt;
+            }
+            compound.xics.insert(ion.name(), (scan_times, intensities));
+        }
+        self.compounds
+    }
+}
+
+/// Which MS1 scans and peaks a chromatogram is computed over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChromatogramFilter {
+    /// Only scans acquired in this polarity, all scans if `None`
+    pub polarity: Option<Polarity>,
+    /// Only peaks within this m/z range, all peaks if `None`
+    pub mz_range: Option<(f64, f64)>,
+}
+
+impl ChromatogramFilter {
+    /// Whether the scans of the filter include `spectrum`
+    fn includes(&self, spectrum: &MultiLayerSpectrum) -> bool {
+        self.polarity.is_none_or(|polarity| Polarity::of_scan(spectrum.polarity()) == Some(polarity))
+    }
+}
+
+/// The total ion chromatogram (TIC) and base peak chromatogram (BPC) of the MS1 scans
+/// selected by a [`ChromatogramFilter`], one point per scan
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Chromatogram {
+    #[serde(flatten)]
+    pub filter: ChromatogramFilter,
+    pub scan_times: Vec<f64>,
+    /// Summed intensity of every scan
+    pub tic: Vec<f64>,
+    /// Intensity of the most intense peak of every scan
+    pub bpc: Vec<f64>,
+    /// m/z of the most intense peak of every scan, `None` for scans without peaks
+    pub bpc_mz: Vec<Option<f64>>,
+}
+
+/// Builds the TIC and BPC of several [`ChromatogramFilter`]s from MS1 spectra that are
+/// fed in one at a time, like [`XicBuilder`] does for XICs
+pub struct ChromatogramBuilder {
+    chromatograms: Vec<Chromatogram>,
+}
+
+impl ChromatogramBuilder {
+    pub fn new(filters: &[ChromatogramFilter]) -> Self {
+        let chromatograms = filters
+            .iter()
+            .map(|filter| Chromatogram {
+                filter: *filter,
+                ..Chromatogram::default()
+            })
+            .collect();
+        ChromatogramBuilder { chromatograms }
+    }
+
+    /// The chromatograms kept with every measurement: one over all MS1 scans, and one
+    /// per polarity, kept by [`finish_run`](Self::finish_run) for runs that switch polarity
+    pub fn for_run() -> Self {
+        Self::new(&[
+            ChromatogramFilter::default(),
+            ChromatogramFilter { polarity: Some(Polarity::Positive), mz_range: None },
+            ChromatogramFilter { polarity: Some(Polarity::Negative), mz_range: None },
+        ])
+    }
+
+    /// Add one point per chromatogram for an MS1 spectrum.
+    ///
+    /// Spectra without peak arrays count as empty scans; fails with [`Error::Spectrum`]
+    /// if the arrays are there but cannot be decoded.
+    pub fn add_spectrum(&mut self, spectrum: &MultiLayerSpectrum) -> Result<()> {
+        let arrays = peak_arrays(spectrum)?;
+        let scan_time = spectrum.description.acquisition.start_time();
+        for chromatogram in self.chromatograms.iter_mut().filter(|c| c.filter.includes(spectrum)) {
+            let (mut tic, mut base_peak) = (0.0, None::<(f64, f64)>);
+            if let Some((mzs, intensities)) = &arrays {
+                let (first, last) = match chromatogram.filter.mz_range {
+                    Some((start, end)) => (mzs.partition_point(|mz| *mz < start), mzs.partition_point(|mz| *mz <= end)),
+                    None => (0, mzs.len()),
+                };
+                for (mz, intensity) in mzs[first..last].iter().zip(&intensities[first..last]) {
+                    let intensity = *intensity as f64;
+                    tic += intensity;
+                    if base_peak.is_none_or(|(_, highest)| intensity > highest) {
+                        base_peak = Some((*mz, intensity));
                     }
                 }
             }
-            
-            compound_clone
+            chromatogram.scan_times.push(scan_time);
+            chromatogram.tic.push(tic);
+            chromatogram.bpc.push(base_peak.map_or(0.0, |(_, intensity)| intensity));
+            chromatogram.bpc_mz.push(base_peak.map(|(mz, _)| mz));
+        }
+        Ok(())
+    }
+
+    /// Append the points of a builder that was fed the spectra following ours
+    pub fn append(&mut self, other: ChromatogramBuilder) {
+        for (chromatogram, other) in self.chromatograms.iter_mut().zip(other.chromatograms) {
+            chromatogram.scan_times.extend(other.scan_times);
+            chromatogram.tic.extend(other.tic);
+            chromatogram.bpc.extend(other.bpc);
+            chromatogram.bpc_mz.extend(other.bpc_mz);
+        }
+    }
+
+    /// The chromatograms, in the order of the filters
+    pub fn finish(self) -> Vec<Chromatogram> {
+        self.chromatograms
+    }
+
+    /// The chromatograms of a builder made with [`for_run`](Self::for_run): the one
+    /// over all scans, followed by the per-polarity ones if the run has scans of both
+    pub fn finish_run(self) -> Vec<Chromatogram> {
+        let mut chromatograms = self.finish();
+        if chromatograms[1..].iter().any(|chromatogram| chromatogram.scan_times.is_empty()) {
+            chromatograms.truncate(1);
+        }
+        chromatograms
+    }
+}
+
+/// Compute the TIC and BPC of every filter over MS1 spectra, matching chunks of
+/// spectra in parallel like [`construct_xics`]
+pub fn compute_chromatograms<S: Borrow<MultiLayerSpectrum> + Sync>(
+    data: &[S],
+    filters: &[ChromatogramFilter],
+    cancel: &CancellationToken,
+) -> Result<Vec<Chromatogram>> {
+    let chunk_size = data.len().div_ceil(rayon::current_num_threads()).max(1);
+    let partial_builders: Vec<ChromatogramBuilder> = data
+        .par_chunks(chunk_size)
+        .map(|chunk| {
+            let mut builder = ChromatogramBuilder::new(filters);
+            for spectrum in chunk {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                builder.add_spectrum(spectrum.borrow())?;
+            }
+            Ok(builder)
         })
-        .collect();
-    
-    println!("Finished constructing XICs in {:.2?} seconds.", start_time.elapsed());
-    compounds
+        .collect::<Result<_>>()?;
+    if cancel.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    let mut builder = ChromatogramBuilder::new(filters);
+    for partial in partial_builders {
+        builder.append(partial);
+    }
+    Ok(builder.finish())
 }
 
-/// Process a single ion 
-fn process_ion(
-    ion_name: &str, 
-    data: &[MultiLayerSpectrum],
-    mass_accuracy: f64
-) -> (String, Option<f64>, Option<f64>) {
-    // Calculate mass range
-    let mass = ion_name
-        .parse::<f64>()
-        .unwrap_or_else(|e| panic!("Failed to parse mass: {e}"));
+/// Recompute the intensity and RT of every ion from its XIC trace.
+///
+/// Only trace points of at least `min_intensity` and within `rt_range` (the whole run
+/// if `None`) are counted, so results can be re-integrated with other settings without
+/// reading the files again.
+pub fn integrate_xics(compounds: &mut [Compound], min_intensity: f64, rt_range: Option<(f64, f64)>) {
+    for compound in compounds {
+        for ion in compound.ions.iter_mut() {
+            let Some((scan_times, intensities)) = compound.xics.get(&ion.name()) else {
+                continue;
+            };
+            let (scan_times, intensities): (Vec<f64>, Vec<f64>) = scan_times
+                .iter()
+                .zip(intensities)
+                .filter(|(rt, intensity)| {
+                    **intensity >= min_intensity && rt_range.is_none_or(|(start, end)| **rt >= start && **rt <= end)
+                })
+                .unzip();
+            (ion.ms_intensity, ion.rt) = summarize_xic(&scan_times, &intensities);
+        }
+    }
+}
+
+/// The m/z window searched for an ion, +/- 3 times the mass accuracy, or `None` if
+/// the m/z is not a finite number
+fn ion_mass_range(mass: f64, mass_accuracy: f64) -> Option<(f64, f64)> {
+    if !mass.is_finite() {
+        return None;
+    }
     let mass_range = (mass - 3.0 * mass_accuracy, mass + 3.0 * mass_accuracy);
 
     // Safeguard for mass range
-    let mass_range = if mass_range.0 < 0.0 {
-        (0.0, mass_range.1)
+    if mass_range.0 < 0.0 {
+        Some((0.0, mass_range.1))
     } else {
-        mass_range
-    };
+        Some(mass_range)
+    }
+}
+
+/// Decoded m/z and intensity arrays of a spectrum
+pub(crate) type PeakArrays<'a> = (Cow<'a, [f64]>, Cow<'a, [f32]>);
 
-    // Collect intensity data for this ion efficiently
-    let (intensities, scan_times) = find_matching_intensities(data, mass_range);
+/// The m/z and intensity arrays of a spectrum, `None` if it has no peak data.
+///
+/// Fails with [`Error::Spectrum`] if they cannot be decoded or differ in length.
+pub(crate) fn peak_arrays(spectrum: &MultiLayerSpectrum) -> Result<Option<PeakArrays<'_>>> {
+    let Some(arrays) = spectrum.arrays.as_ref() else {
+        return Ok(None);
+    };
+    let decode_error = |e: mzdata::spectrum::bindata::ArrayRetrievalError| Error::Spectrum {
+        index: spectrum.index(),
+        message: e.to_string(),
+    };
+    let mzs = arrays.mzs().map_err(decode_error)?;
+    let intensities = arrays.intensities().map_err(decode_error)?;
+    if mzs.len() != intensities.len() {
+        return Err(Error::Spectrum {
+            index: spectrum.index(),
+            message: format!("{} m/z values but {} intensities", mzs.len(), intensities.len()),
+        });
+    }
+    Ok(Some((mzs, intensities)))
+}
 
+/// Summarise an XIC as its total intensity and the retention time of its most intense point
+fn summarize_xic(scan_times: &[f64], intensities: &[f64]) -> (Option<f64>, Option<f64>) {
     // Skip processing if no intensities found
     if intensities.is_empty() {
-        return (ion_name.to_string(), None, None);
+        return (None, None);
     }
 
-    // Create XIC array
+    // Create XIC array: scan times in the first row, intensities in the second
     let xic_array = Array2::from_shape_vec(
         (2, intensities.len()), 
         [scan_times, intensities].concat()
     ).expect("Failed to create XIC array");
 
     // Get the total intensity
-    let total_intensity = if xic_array.is_empty() {
-        None
-    } else {
-        Some(xic_array.sum())
-    };
+    let total_intensity = Some(xic_array.row(1).sum());
 
     // Find max intensity index and get corresponding scan time
-    let rt = if !xic_array.is_empty() && xic_array.shape()[1] >= 2 {
-        let max_index = xic_array
-            .column(1)
-            .iter()
-            .enumerate()
-            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-            .map(|(index, _)| index);
-
-        if let Some(index) = max_index {
-            if index < data.len() {
-                Some(data[index].description.acquisition.start_time())
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    } else {
-        None
-    };
+    let rt = xic_array
+        .row(1)
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| xic_array[[0, index]]);
 
-    (ion_name.to_string(), total_intensity, rt)
+    (total_intensity, rt)
 }
 
 /// Find all matching intensities for a given mass range efficiently
-fn find_matching_intensities(
+///
+/// Returns the intensities and scan times of every peak within `mass_range`, using a
+/// binary search on each (sorted) m/z array to find the start of the window.
+pub fn find_matching_intensities(
     data: &[MultiLayerSpectrum], 
     mass_range: (f64, f64)
-) -> (Vec<f64>, Vec<f64>) {
+) -> Result<(Vec<f64>, Vec<f64>)> {
     let mut intensities = Vec::new();
     let mut scan_times = Vec::new();
     
@@ -133,32 +404,29 @@ fn find_matching_intensities(
     scan_times.reserve(estimated_matches);
 
     for spectrum in data {
-        if let Some(arrays) = spectrum.arrays.as_ref() {
-            let mzs = arrays.mzs().unwrap();
-            let intensity_values = arrays.intensities().unwrap();
-            
+        if let Some((mzs, intensity_values)) = peak_arrays(spectrum)? {
             let scan_time = spectrum.description.acquisition.start_time();
             
-            // Linear scan for all values in the mass range
-            for i in 0..mzs.len() {
-                // Convert each mz value to f64 before comparison
-                let mz_value = mzs[i] as f64;
-                if mz_value >= mass_range.0 && mz_value <= mass_range.1 {
-                    intensities.push(intensity_values[i] as f64);
-                    scan_times.push(scan_time);
+            let first = mzs.partition_point(|mz| *mz < mass_range.0);
+            for (mz, intensity) in mzs[first..].iter().zip(&intensity_values[first..]) {
+                if *mz > mass_range.1 {
+                    break;
                 }
+                intensities.push(*intensity as f64);
+                scan_times.push(scan_time);
             }
         }
     }
     
-    (intensities, scan_times)
+    Ok((intensities, scan_times))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
+    use crate::measurements::Ion;
     use mzdata::{spectrum::SpectrumLike};
+    use std::time::Instant;
     
     #[test]
     fn test_construct_xics() {
@@ -174,28 +442,28 @@ mod tests {
             .as_object()
             .unwrap()
             .iter()
-            .map(|(name, compound_data)| Compound {
-                name: name.to_string(),
-                ions: HashMap::from_iter(
+            .map(|(name, compound_data)| {
+                Compound::new(
+                    name.to_string(),
                     compound_data["ions"]
                         .as_array()
                         .unwrap()
                         .iter()
-                        .map(|ion| (ion.to_string(), HashMap::new())),
-                ),
-                ion_info: compound_data["info"]
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|info| info.as_str().unwrap().to_string())
-                    .collect(),
-                calibration_curve: HashMap::new(),
-                ms2: Vec::new(),
+                        .map(|ion| ion.as_f64().unwrap())
+                        .collect(),
+                    compound_data["info"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|info| info.as_str().unwrap().to_string())
+                        .collect(),
+                )
             })
             .collect::<Vec<Compound>>();
 
-        // Read data in from tests/ANIL_1mM_1_neg.mzml
-        let file = std::fs::File::open("tests/ANIL_1mM_1_neg.mzml").unwrap();
+        // Read a synthetic negative-mode run with acetate and propionate peaks
+        let path = crate::test_utils::write_mzml("construct_xics.mzML", &crate::test_utils::synthetic_run());
+        let file = std::fs::File::open(&path).unwrap();
         let mut reader = mzdata::MzMLReader::new(file);
         let mut data = Vec::new();
         for scan in &mut reader {
@@ -205,25 +473,284 @@ mod tests {
         }
 
         // Call construct_xics
-        let result = construct_xics(&data, &ion_list, 0.0001);
+        let result = construct_xics(&data, &ion_list, 0.0001, &CancellationToken::new()).unwrap();
 
         // Verify results
         assert_eq!(result.len(), 4);
-        let compound = &result[0];
-        assert_eq!(compound.name, "Acetate");
+        // Compounds keep the order of the ion list
+        assert_eq!(result[0].name, "Formate");
+        let compound = result.iter().find(|compound| compound.name == "Acetate").unwrap();
 
         let negative_mode = [59.0139, 73.0295, 87.04515];
 
         // Check that RT and MS Intensity have been populated
-        for (ion, ion_data) in compound.ions.iter() {
-            if negative_mode
+        for ion in compound.ions.iter() {
+            if negative_mode.contains(&ion.mz) {
+                assert!(ion.rt.is_some());
+                assert!(ion.ms_intensity.is_some());
+            }
+        }
+        let acetate = compound.ion(59.0139).unwrap();
+        assert_eq!((acetate.rt, acetate.ms_intensity), (Some(0.5), Some(25500.0)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_streaming_xics_match_construct_xics() {
+        let ion_list = vec![
+            Compound::new("Acetate".to_string(), vec![59.0139, 73.0295], vec!["Acetate-neg".to_string(), "Propionate-neg".to_string()]),
+            Compound::new("Missing".to_string(), vec![500.0], vec!["Missing".to_string()]),
+        ];
+        let ms1: Vec<MultiLayerSpectrum> = crate::test_utils::synthetic_run()
+            .into_iter()
+            .filter(|s| s.ms_level() == 1)
+            .collect();
+
+        let batch = construct_xics(&ms1, &ion_list, 0.0001, &CancellationToken::new()).unwrap();
+        let mut builder = XicBuilder::new(&ion_list, 0.0001).unwrap();
+        for spectrum in &ms1 {
+            builder.add_spectrum(spectrum).unwrap();
+        }
+        let streamed = builder.finish();
+
+        assert_eq!(batch.len(), streamed.len());
+        for (a, b) in batch.iter().zip(streamed.iter()) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.ions, b.ions);
+            assert_eq!(a.xics, b.xics);
+        }
+        // The traces are kept alongside the summaries, one point per MS1 scan
+        let (scan_times, intensities) = &streamed[0].xics["59.0139"];
+        assert_eq!((scan_times.len(), intensities.len()), (10, 10));
+        assert_eq!(intensities[5], 5000.0);
+        let acetate = streamed[0].ion(59.0139).unwrap();
+        assert_eq!(acetate.ms_intensity, Some(25500.0));
+        assert!((acetate.rt.unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(acetate.label.as_deref(), Some("Acetate-neg"));
+        assert_eq!(streamed[1].ions[0].ms_intensity, None);
+    }
+
+    #[test]
+    fn test_chromatograms() {
+        let mut ms1: Vec<MultiLayerSpectrum> = crate::test_utils::synthetic_run()
+            .into_iter()
+            .filter(|s| s.ms_level() == 1)
+            .collect();
+        ms1[9].description.polarity = mzdata::spectrum::ScanPolarity::Positive;
+        let filters = [
+            ChromatogramFilter::default(),
+            ChromatogramFilter { polarity: Some(Polarity::Negative), mz_range: Some((55.0, 80.0)) },
+        ];
+
+        let chromatograms = compute_chromatograms(&ms1, &filters, &CancellationToken::new()).unwrap();
+        let (all, negative) = (&chromatograms[0], &chromatograms[1]);
+        assert_eq!(all.tic.len(), 10);
+        assert_eq!(all.tic[5], 10.0 + 5000.0 + 20.0 + 5.0);
+        assert_eq!((all.bpc[5], all.bpc_mz[5]), (5000.0, Some(59.0139)));
+        // The positive scan is left out, and so are the peaks outside the m/z range
+        assert_eq!(negative.scan_times.len(), 9);
+        assert_eq!(negative.tic[5], 5000.0 + 20.0);
+        assert_eq!(negative.tic[0], 500.0 + 20.0);
+
+        // Streaming the scans gives the same chromatograms
+        let mut builder = ChromatogramBuilder::new(&filters);
+        for spectrum in &ms1 {
+            builder.add_spectrum(spectrum).unwrap();
+        }
+        assert_eq!(builder.finish(), chromatograms);
+
+        // Per-polarity chromatograms are only kept for runs with scans of both
+        let mut builder = ChromatogramBuilder::for_run();
+        for spectrum in &ms1 {
+            builder.add_spectrum(spectrum).unwrap();
+        }
+        assert_eq!(builder.finish_run().len(), 3);
+        let mut builder = ChromatogramBuilder::for_run();
+        builder.add_spectrum(&ms1[0]).unwrap();
+        assert_eq!(builder.finish_run().len(), 1);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(compute_chromatograms(&ms1, &filters, &cancel), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_integrate_xics() {
+        let ion_list = [Compound::new("Acetate".to_string(), vec![59.0139], Vec::new())];
+        let ms1: Vec<MultiLayerSpectrum> = crate::test_utils::synthetic_run()
+            .into_iter()
+            .filter(|s| s.ms_level() == 1)
+            .collect();
+        let mut compounds = construct_xics(&ms1, &ion_list, 0.0001, &CancellationToken::new()).unwrap();
+        let acetate = |compounds: &[Compound]| (compounds[0].ions[0].ms_intensity, compounds[0].ions[0].rt);
+
+        integrate_xics(&mut compounds, 0.0, None);
+        assert_eq!(acetate(&compounds).0, Some(25500.0));
+        integrate_xics(&mut compounds, 3500.0, Some((0.25, 0.75)));
+        assert_eq!(acetate(&compounds).0, Some(13000.0));
+        assert!((acetate(&compounds).1.unwrap() - 0.5).abs() < 1e-9);
+        integrate_xics(&mut compounds, 0.0, Some((2.0, 3.0)));
+        assert_eq!(acetate(&compounds), (None, None));
+    }
+
+    #[test]
+    fn test_invalid_ion_is_an_error() {
+        let mut compound = Compound::new("Acetate".to_string(), vec![59.0139], Vec::new());
+        compound.ions.push(Ion::new(f64::NAN));
+        let ms1 = crate::test_utils::synthetic_run();
+
+        let result = construct_xics(&ms1, &[compound], 0.0001, &CancellationToken::new());
+        assert!(matches!(result, Err(Error::InvalidIon { ion, .. }) if ion == "NaN"));
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let ion_list = [Compound::new("Acetate".to_string(), vec![59.0139], Vec::new())];
+        assert!(matches!(construct_xics(&ms1, &ion_list, 0.0001, &cancel), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_xic_summary_values() {
+        // The total is the sum of the intensities alone and the RT is the scan time of
+        // the most intense point; the ion is missing from the first scans
+        let ms1: Vec<MultiLayerSpectrum> = [(0.0, 0.0), (0.1, 0.0), (0.2, 100.0), (0.3, 400.0), (0.4, 250.0)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(rt, intensity))| {
+                let (mzs, intensities): (Vec<f64>, Vec<f32>) = match intensity {
+                    0.0 => (vec![45.0], vec![10.0]),
+                    _ => (vec![45.0, 59.0139], vec![10.0, intensity]),
+                };
+                crate::test_utils::synthetic_spectrum(i, 1, rt, &mzs, &intensities)
+            })
+            .collect();
+        let ion_list = [Compound::new("Acetate".to_string(), vec![59.0139], Vec::new())];
+
+        let batch = construct_xics(&ms1, &ion_list, 0.0001, &CancellationToken::new()).unwrap();
+        let mut builder = XicBuilder::new(&ion_list, 0.0001).unwrap();
+        for spectrum in &ms1 {
+            builder.add_spectrum(spectrum).unwrap();
+        }
+        for compounds in [batch, builder.finish()] {
+            let acetate = &compounds[0].ions[0];
+            assert_eq!(acetate.ms_intensity, Some(750.0));
+            assert!((acetate.rt.unwrap() - 0.3).abs() < 1e-9);
+        }
+
+        // A single point has an RT too
+        assert_eq!(summarize_xic(&[0.7], &[20.0]), (Some(20.0), Some(0.7)));
+        assert_eq!(summarize_xic(&[], &[]), (None, None));
+    }
+
+    #[test]
+    fn test_mismatched_arrays_are_an_error() {
+        let spectrum = crate::test_utils::synthetic_spectrum(3, 1, 0.5, &[59.0139, 73.0295, 87.0452], &[500.0]);
+        let ion_list = [Compound::new("Propionate".to_string(), vec![87.0452], Vec::new())];
+        let mut builder = XicBuilder::new(&ion_list, 0.0001).unwrap();
+        assert!(matches!(builder.add_spectrum(&spectrum), Err(Error::Spectrum { index: 3, .. })));
+        let mut chromatograms = ChromatogramBuilder::new(&[ChromatogramFilter { polarity: None, mz_range: Some((80.0, 90.0)) }]);
+        assert!(matches!(chromatograms.add_spectrum(&spectrum), Err(Error::Spectrum { index: 3, .. })));
+    }
+
+    #[test]
+    fn test_merged_matching_agrees_with_linear_scan() {
+        // Dense spectra and overlapping windows, including ions sharing peaks
+        let mzs: Vec<f64> = (0..2000).map(|i| 50.0 + i as f64 * 0.0005).collect();
+        let intensities: Vec<f32> = (0..2000).map(|i| (i % 97) as f32 + 1.0).collect();
+        let data: Vec<MultiLayerSpectrum> = (0..5)
+            .map(|i| crate::test_utils::synthetic_spectrum(i, 1, i as f64, &mzs, &intensities))
+            .collect();
+        let masses = vec![50.2, 50.2004, 50.5, 50.1, 49.0, 52.0];
+        let ion_list = vec![Compound::new("Test".to_string(), masses.clone(), Vec::new())];
+
+        let mut builder = XicBuilder::new(&ion_list, 0.0005).unwrap();
+        for spectrum in &data {
+            builder.add_spectrum(spectrum).unwrap();
+        }
+        let result = builder.finish();
+
+        for mass in masses {
+            let range = ion_mass_range(mass, 0.0005).unwrap();
+            let expected: f64 = data
                 .iter()
-                .any(|negative_mode_ion| ion.parse::<f64>().unwrap() == *negative_mode_ion)
-            {
-                assert!(ion_data.contains_key("RT"));
-                assert!(ion_data.contains_key("MS Intensity"));
-                assert!(ion_data.get("MS Intensity").unwrap().is_some());
+                .flat_map(|_| mzs.iter().zip(&intensities))
+                .filter(|(mz, _)| **mz >= range.0 && **mz <= range.1)
+                .map(|(_, intensity)| *intensity as f64)
+                .sum();
+            let (found, _) = find_matching_intensities(&data, range).unwrap();
+            assert_eq!(found.iter().sum::<f64>(), expected);
+            let stored = result[0].ion(mass).unwrap().ms_intensity;
+            assert_eq!(stored, if expected > 0.0 { Some(expected) } else { None });
+        }
+    }
+
+    /// Benchmark of the merged single-pass matching against the previous approach of
+    /// scanning every peak of every spectrum once per ion.
+    ///
+    /// Run with `cargo test --release bench_xic_matching -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_xic_matching() {
+        let n_peaks = 5000;
+        let step = 950.0 / n_peaks as f64;
+        let data: Vec<MultiLayerSpectrum> = (0..200)
+            .map(|index| {
+                let mzs: Vec<f64> = (0..n_peaks).map(|i| 50.0 + i as f64 * step).collect();
+                let intensities: Vec<f32> =
+                    (0..n_peaks).map(|i| ((i * 31 + index) % 1000) as f32).collect();
+                crate::test_utils::synthetic_spectrum(index, 1, index as f64 * 0.01, &mzs, &intensities)
+            })
+            .collect();
+
+        for n_compounds in [10, 50, 200] {
+            let ion_list: Vec<Compound> = (0..n_compounds)
+                .map(|i| {
+                    let base = 60.0 + i as f64 * 900.0 / n_compounds as f64;
+                    let ions = (0..5).map(|k| base + k as f64 * 1.0034).collect();
+                    Compound::new(format!("Compound {i}"), ions, Vec::new())
+                })
+                .collect();
+
+            let start = Instant::now();
+            let mut linear_total = 0.0;
+            for compound in &ion_list {
+                for ion in &compound.ions {
+                    let range = ion_mass_range(ion.mz, 0.0001).unwrap();
+                    for spectrum in &data {
+                        let arrays = spectrum.arrays.as_ref().unwrap();
+                        let mzs = arrays.mzs().unwrap();
+                        let intensities = arrays.intensities().unwrap();
+                        for (mz, intensity) in mzs.iter().zip(intensities.iter()) {
+                            if *mz >= range.0 && *mz <= range.1 {
+                                linear_total += *intensity as f64;
+                            }
+                        }
+                    }
+                }
             }
+            let linear = start.elapsed();
+
+            let start = Instant::now();
+            let mut builder = XicBuilder::new(&ion_list, 0.0001).unwrap();
+            for spectrum in &data {
+                builder.add_spectrum(spectrum).unwrap();
+            }
+            let result = builder.finish();
+            let merged = start.elapsed();
+
+            let merged_total: f64 = result
+                .iter()
+                .flat_map(|compound| &compound.ions)
+                .filter_map(|ion| ion.ms_intensity)
+                .sum();
+            assert_eq!(linear_total, merged_total);
+            println!(
+                "{} ions: linear scan {:.2?}, merged pass {:.2?} ({:.1}x faster)",
+                n_compounds * 5,
+                linear,
+                merged,
+                linear.as_secs_f64() / merged.as_secs_f64()
+            );
+            assert!(merged < linear);
         }
     }
 }
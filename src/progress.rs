@@ -0,0 +1,151 @@
+//! Progress reporting for loading and processing.
+//!
+//! Every stage reports what it is doing as a [`ProgressEvent`] to a
+//! [`ProgressReporter`]. The CLI shows them as a terminal progress bar or as JSON lines
+//! on stderr, library users can pass a [`CallbackProgress`] to receive them directly.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Something that happened while loading or processing files
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// Processing of a list of files started
+    BatchStarted { files: usize },
+    /// A file was opened
+    FileStarted { file: String },
+    /// All selected spectra of a file were read
+    ScansLoaded {
+        file: String,
+        ms1_scans: usize,
+        ms2_scans: usize,
+        seconds: f64,
+    },
+    /// The XICs of the ion list were built for a file
+    XicsBuilt {
+        file: String,
+        compounds: usize,
+        seconds: f64,
+    },
+    /// A file is done, successfully or not
+    FileFinished { file: String, seconds: f64 },
+    /// Something went wrong with a file: it failed, or e.g. its scan cache could not
+    /// be written while processing went on.
+    Error { file: String, message: String },
+    /// Processing of a list of files is done
+    BatchFinished { files: usize, seconds: f64 },
+}
+
+/// Receives progress events, possibly from several threads at once
+pub trait ProgressReporter: Send + Sync {
+    fn report(&self, event: ProgressEvent);
+}
+
+/// Ignores all events
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn report(&self, _event: ProgressEvent) {}
+}
+
+/// Calls a closure for every event
+pub struct CallbackProgress<F>(pub F);
+
+impl<F: Fn(ProgressEvent) + Send + Sync> ProgressReporter for CallbackProgress<F> {
+    fn report(&self, event: ProgressEvent) {
+        (self.0)(event)
+    }
+}
+
+/// Writes every event as one line of JSON to stderr
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonLinesProgress;
+
+impl ProgressReporter for JsonLinesProgress {
+    fn report(&self, event: ProgressEvent) {
+        let line = serde_json::to_string(&event).expect("Progress events serialize to JSON");
+        // Lock so lines from different threads don't interleave
+        writeln!(io::stderr().lock(), "{line}").ok();
+    }
+}
+
+/// A progress bar over the files of a batch, with a log line per file on stderr
+pub struct TerminalProgress {
+    bar: ProgressBar,
+}
+
+impl TerminalProgress {
+    pub fn new() -> Self {
+        let bar = ProgressBar::new(0);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] {wide_bar:.cyan/blue} {pos}/{len} files ({percent}%, ETA: {eta})")
+                .expect("Failed to create progress style"),
+        );
+        TerminalProgress { bar }
+    }
+
+    /// Print a line without tearing the bar
+    fn log(&self, line: String) {
+        self.bar.suspend(|| eprintln!("{line}"));
+    }
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressReporter for TerminalProgress {
+    fn report(&self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::BatchStarted { files } => {
+                self.bar.set_length(files as u64);
+                self.bar.reset_elapsed();
+            }
+            ProgressEvent::FileStarted { .. } | ProgressEvent::XicsBuilt { .. } => (),
+            ProgressEvent::ScansLoaded {
+                file,
+                ms1_scans,
+                ms2_scans,
+                seconds,
+            } => self.log(format!(
+                "Loaded {ms1_scans} MS1 scans and {ms2_scans} MS2 scans in {:.2?} from {file}.",
+                Duration::from_secs_f64(seconds)
+            )),
+            ProgressEvent::FileFinished { .. } => self.bar.inc(1),
+            ProgressEvent::Error { file, message } => self.log(format!("Error in {file}: {message}")),
+            ProgressEvent::BatchFinished { files, seconds } => {
+                self.bar.finish_and_clear();
+                self.log(format!(
+                    "Processed {files} files in {:.2?}.",
+                    Duration::from_secs_f64(seconds)
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_serialize_as_tagged_json() {
+        let event = ProgressEvent::ScansLoaded {
+            file: "a.mzML".to_string(),
+            ms1_scans: 10,
+            ms2_scans: 5,
+            seconds: 0.5,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"scans_loaded","file":"a.mzML","ms1_scans":10,"ms2_scans":5,"seconds":0.5}"#
+        );
+    }
+}
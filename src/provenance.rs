@@ -0,0 +1,229 @@
+//! Provenance of a result set: which backend produced it, with which parameters and
+//! ion list, from which files, when and where, so results can be traced back and a
+//! run reproduced.
+//!
+//! A [`Provenance`] is started before a batch is processed and finished with the files
+//! of the batch, which are then checksummed and their headers read. Every export
+//! format carries it: the results JSON under `provenance`, CSV tables as `#` comment
+//! lines above the header.
+
+use crate::measurements::Compound;
+use crate::metadata::{read_file_header, FileHeader};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+/// Where, when and from what a result set was produced
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Provenance {
+    /// Version of the backend
+    pub backend_version: String,
+    /// When processing started, in RFC 3339 format
+    pub started_at: String,
+    /// When processing finished, `None` while it is still running
+    pub finished_at: Option<String>,
+    pub host: Host,
+    /// Every parameter of the run, e.g. the effective [`Config`](crate::Config)
+    pub parameters: Value,
+    pub ion_list: IonListProvenance,
+    /// The input files, in input order
+    pub files: Vec<FileProvenance>,
+}
+
+/// The machine a result set was produced on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Host {
+    pub hostname: Option<String>,
+    pub os: String,
+    pub arch: String,
+    pub cpus: usize,
+}
+
+/// The ion list the compounds were extracted with
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IonListProvenance {
+    /// The names of the lists, in the order they were combined
+    pub names: Vec<String>,
+    /// SHA-256 of the compounds and their ions, see [`ion_list_sha256`]
+    pub sha256: String,
+    pub compounds: usize,
+}
+
+/// One input file as it was when processed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileProvenance {
+    pub path: String,
+    /// Size in bytes
+    pub size: Option<u64>,
+    /// SHA-256 of the file as stored, compressed or not
+    pub sha256: Option<String>,
+    /// Last modification time, in RFC 3339 format
+    pub modified: Option<String>,
+    /// Instruments and software from the file header
+    #[serde(flatten)]
+    pub header: FileHeader,
+    /// Why the file could not be checksummed or its header read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Provenance {
+    /// Start recording a run with `parameters`, extracting the compounds of the ion
+    /// lists `ion_lists`
+    pub fn start<P: Serialize, S: AsRef<str>>(parameters: &P, ion_lists: &[S], compounds: &[Compound]) -> Self {
+        Provenance {
+            backend_version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: timestamp(Utc::now()),
+            finished_at: None,
+            host: Host::current(),
+            parameters: serde_json::to_value(parameters).unwrap_or(Value::Null),
+            ion_list: IonListProvenance {
+                names: ion_lists.iter().map(|name| name.as_ref().to_string()).collect(),
+                sha256: ion_list_sha256(compounds),
+                compounds: compounds.len(),
+            },
+            files: Vec::new(),
+        }
+    }
+
+    /// Record the end of the run and the input files `files`, checksumming them and
+    /// reading their headers
+    pub fn finish<S: AsRef<str> + Sync>(&mut self, files: &[S]) {
+        self.files = files.par_iter().map(|file| FileProvenance::read(file.as_ref())).collect();
+        self.finished_at = Some(timestamp(Utc::now()));
+    }
+
+    /// Write the provenance as `# key: value` comment lines, nested values as JSON
+    /// and one `# file:` line per input file
+    pub fn write_comments<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let Value::Object(fields) = serde_json::to_value(self)? else {
+            unreachable!("Provenance serializes to a JSON object");
+        };
+        for (key, value) in fields {
+            match value {
+                Value::Array(files) if key == "files" => {
+                    for file in files {
+                        writeln!(writer, "# file: {}", file)?;
+                    }
+                }
+                Value::String(value) => writeln!(writer, "# {}: {}", key, value)?,
+                value => writeln!(writer, "# {}: {}", key, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Host {
+    /// The machine this process runs on
+    pub fn current() -> Self {
+        Host {
+            hostname: hostname(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            cpus: num_cpus::get(),
+        }
+    }
+}
+
+impl FileProvenance {
+    /// Checksum the file at `path` and read its header
+    pub fn read(path: &str) -> Self {
+        let mut provenance = FileProvenance {
+            path: path.to_string(),
+            size: None,
+            sha256: None,
+            modified: None,
+            header: FileHeader::default(),
+            error: None,
+        };
+        let checksummed = fs::metadata(path).and_then(|metadata| {
+            provenance.size = Some(metadata.len());
+            provenance.modified = metadata.modified().ok().map(|modified| timestamp(modified.into()));
+            let mut hasher = Sha256::new();
+            io::copy(&mut File::open(path)?, &mut hasher)?;
+            provenance.sha256 = Some(hex(&hasher.finalize()));
+            Ok(())
+        });
+        let read = checksummed
+            .map_err(|e| e.to_string())
+            .and_then(|_| read_file_header(path).map_err(|e| e.to_string()));
+        match read {
+            Ok(header) => provenance.header = header,
+            Err(e) => provenance.error = Some(e),
+        }
+        provenance
+    }
+}
+
+/// SHA-256 identifying an ion list by what is extracted with it: the name of every
+/// compound with the m/z value and label of each of its ions, in order
+pub fn ion_list_sha256(compounds: &[Compound]) -> String {
+    let mut hasher = Sha256::new();
+    for compound in compounds {
+        hasher.update(compound.name.as_bytes());
+        hasher.update([0]);
+        for ion in &compound.ions {
+            hasher.update(ion.mz.to_le_bytes());
+            hasher.update(ion.label.as_deref().unwrap_or_default().as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update([0xff]);
+    }
+    hex(&hasher.finalize())
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn hostname() -> Option<String> {
+    ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{synthetic_run, write_mzml};
+
+    #[test]
+    fn test_provenance_records_files_and_ion_list() {
+        let compounds = vec![Compound::new("Acetate".to_string(), vec![59.0139], vec!["Acetate-(-)".to_string()])];
+        let mut provenance = Provenance::start(&serde_json::json!({"mass_accuracy": 0.0001}), &["scfas"], &compounds);
+        assert_eq!(provenance.ion_list.sha256, ion_list_sha256(&compounds));
+        // Relabelling an ion changes the ion list hash
+        let relabelled = vec![Compound::new("Acetate".to_string(), vec![59.0139], vec!["[M-H]-".to_string()])];
+        assert_ne!(ion_list_sha256(&relabelled), provenance.ion_list.sha256);
+
+        let path = write_mzml("provenance.mzML", &synthetic_run());
+        let path = path.to_str().unwrap();
+        provenance.finish(&[path, "missing.mzML"]);
+        let file = &provenance.files[0];
+        assert_eq!(file.size, Some(fs::metadata(path).unwrap().len()));
+        assert_eq!(file.sha256.as_ref().map(String::len), Some(64));
+        assert_eq!(file.error, None);
+        assert!(provenance.files[1].error.is_some());
+        assert!(provenance.finished_at.is_some());
+
+        let mut comments = Vec::new();
+        provenance.write_comments(&mut comments).unwrap();
+        let comments = String::from_utf8(comments).unwrap();
+        assert!(comments.lines().all(|line| line.starts_with("# ")));
+        assert!(comments.contains(&format!("# backend_version: {}\n", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(comments.matches("# file: ").count(), 2);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,674 @@
+//! Python bindings for the LCMSpector GUI.
+//!
+//! Built as the `lcmspector_backend` extension module with maturin when the `python`
+//! feature is enabled. Spectra and XICs are handed to Python as NumPy arrays, and the
+//! heavy lifting runs with the GIL released so the GUI stays responsive.
+
+use crate::cache::ScanCache;
+use crate::cancel::CancellationToken;
+use crate::centroiding::{CentroidMethod, Centroiding};
+use crate::error::Error;
+use crate::export::export_spectrum;
+use crate::loading::{self, FileResult, ScanSelection};
+use crate::measurements::{Compound, MSMeasurement, Polarity};
+use crate::processing::{self, Chromatogram};
+use crate::progress::{CallbackProgress, NoProgress, ProgressEvent};
+use crate::spectra::{self, Combine, CombineOptions, ExtractedSpectrum};
+use pyo3::exceptions::{PyInterruptedError, PyKeyError, PyOSError, PyRuntimeError, PyValueError};
+use mzdata::spectrum::{MultiLayerSpectrum, SpectrumLike};
+use ndarray::Array2;
+use numpy::{PyArray1, PyArray2, ToPyArray};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+type PySpectra = Vec<Py<PySpectrum>>;
+
+/// Cancels a running call from another thread, e.g. a GUI cancel button
+#[pyclass(name = "CancellationToken", module = "lcmspector_backend", frozen)]
+#[derive(Default)]
+pub struct PyCancellationToken {
+    inner: CancellationToken,
+}
+
+#[pymethods]
+impl PyCancellationToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// The token to run with: the caller's, or one that is never cancelled
+fn token(cancel: Option<&Bound<'_, PyCancellationToken>>) -> CancellationToken {
+    cancel.map(|cancel| cancel.get().inner.clone()).unwrap_or_default()
+}
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> Self {
+        let message = error.to_string();
+        match error {
+            Error::Io { .. } => PyOSError::new_err(message),
+            Error::UnknownIonList { .. } => PyKeyError::new_err(message),
+            Error::Cancelled => PyInterruptedError::new_err(message),
+            Error::Panicked { .. } => PyRuntimeError::new_err(message),
+            Error::UnknownFormat { .. }
+            | Error::Config { .. }
+            | Error::IonListsJson { .. }
+            | Error::IonTable { .. }
+            | Error::DuplicateIonList { .. }
+            | Error::DuplicateCompound { .. }
+            | Error::InvalidIon { .. }
+            | Error::InvalidParameter { .. }
+            | Error::Spectrum { .. } => PyValueError::new_err(message),
+        }
+    }
+}
+
+/// A single MS spectrum
+#[pyclass(name = "Spectrum", module = "lcmspector_backend", frozen)]
+pub struct PySpectrum {
+    inner: MultiLayerSpectrum,
+}
+
+#[pymethods]
+impl PySpectrum {
+    /// The native ID of the spectrum, e.g. `scan=12`
+    #[getter]
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    /// The position of the spectrum in its file
+    #[getter]
+    fn index(&self) -> usize {
+        self.inner.index()
+    }
+
+    #[getter]
+    fn ms_level(&self) -> u8 {
+        self.inner.ms_level()
+    }
+
+    /// Retention time in minutes
+    #[getter]
+    fn rt(&self) -> f64 {
+        self.inner.start_time()
+    }
+
+    /// m/z of the first selected precursor ion, for MSn spectra
+    #[getter]
+    fn precursor_mz(&self) -> Option<f64> {
+        self.inner
+            .precursor()
+            .and_then(|precursor| precursor.ions.first())
+            .map(|ion| ion.mz)
+    }
+
+    /// The m/z array as a NumPy array
+    #[getter]
+    fn mz<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        match self.inner.arrays.as_ref().and_then(|arrays| arrays.mzs().ok()) {
+            Some(mzs) => PyArray1::from_slice(py, &mzs),
+            None => PyArray1::from_slice(py, &[]),
+        }
+    }
+
+    /// The intensity array as a NumPy array
+    #[getter]
+    fn intensity<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f32>> {
+        match self.inner.arrays.as_ref().and_then(|arrays| arrays.intensities().ok()) {
+            Some(intensities) => PyArray1::from_slice(py, &intensities),
+            None => PyArray1::from_slice(py, &[]),
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.arrays.as_ref().map_or(0, |arrays| arrays.mzs().map_or(0, |mzs| mzs.len()))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Spectrum(id={:?}, ms_level={}, rt={})",
+            self.inner.id(),
+            self.inner.ms_level(),
+            self.inner.start_time()
+        )
+    }
+}
+
+/// A compound of an ion list, with its results once XICs have been constructed
+#[pyclass(name = "Compound", module = "lcmspector_backend", frozen)]
+pub struct PyCompound {
+    inner: Compound,
+}
+
+#[pymethods]
+impl PyCompound {
+    #[new]
+    #[pyo3(signature = (name, ions, ion_info = Vec::new()))]
+    fn new(name: String, ions: Vec<f64>, ion_info: Vec<String>) -> Self {
+        PyCompound {
+            inner: Compound::new(name, ions, ion_info),
+        }
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// The ions of the compound in ion list order, each with its `m/z`, `RT`,
+    /// `MS Intensity` and `LC Intensity`
+    #[getter]
+    fn ions<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let ions = PyDict::new(py);
+        for ion in &self.inner.ions {
+            let results = PyDict::new(py);
+            results.set_item("m/z", ion.mz)?;
+            results.set_item("RT", ion.rt)?;
+            results.set_item("MS Intensity", ion.ms_intensity)?;
+            results.set_item("LC Intensity", ion.lc_intensity)?;
+            ions.set_item(ion.name(), results)?;
+        }
+        Ok(ions)
+    }
+
+    /// The labels of the ions, in ion list order
+    #[getter]
+    fn ion_info(&self) -> Vec<String> {
+        self.inner.ion_info()
+    }
+
+    #[getter]
+    fn calibration_curve(&self) -> BTreeMap<String, f64> {
+        self.inner.calibration_curve.clone()
+    }
+
+    /// The XIC of one ion as a 2 x N array of scan times and intensities
+    fn xic<'py>(&self, py: Python<'py>, ion: &str) -> Option<Bound<'py, PyArray2<f64>>> {
+        self.inner
+            .xics
+            .get(ion)
+            .map(|(scan_times, intensities)| xic_array(py, scan_times, intensities))
+    }
+
+    /// The XICs of all ions, keyed by ion
+    #[getter]
+    fn xics<'py>(&self, py: Python<'py>) -> HashMap<String, Bound<'py, PyArray2<f64>>> {
+        self.inner
+            .xics
+            .iter()
+            .map(|(ion, (scan_times, intensities))| (ion.clone(), xic_array(py, scan_times, intensities)))
+            .collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Compound(name={:?}, ions={})", self.inner.name, self.inner.ions.len())
+    }
+}
+
+/// The processed results of one file
+#[pyclass(name = "Measurement", module = "lcmspector_backend", frozen)]
+pub struct PyMeasurement {
+    /// The path of the file the measurement was read from
+    #[pyo3(get)]
+    path: String,
+    /// The position of the file in the list it was processed with
+    #[pyo3(get)]
+    sample_index: usize,
+    #[pyo3(get)]
+    mass_accuracy: f32,
+    ms1_scans: PySpectra,
+    ms2_scans: PySpectra,
+    xics: Vec<Py<PyCompound>>,
+    chromatograms: Vec<Chromatogram>,
+}
+
+#[pymethods]
+impl PyMeasurement {
+    /// MS1 scans, only kept when processing with `keep_scans=True`
+    #[getter]
+    fn ms1_scans(&self, py: Python<'_>) -> PySpectra {
+        self.ms1_scans.iter().map(|scan| scan.clone_ref(py)).collect()
+    }
+
+    /// MS2 scans, only kept when processing with `keep_scans=True`
+    #[getter]
+    fn ms2_scans(&self, py: Python<'_>) -> PySpectra {
+        self.ms2_scans.iter().map(|scan| scan.clone_ref(py)).collect()
+    }
+
+    /// The compounds of the ion list with their XICs and results
+    #[getter]
+    fn xics(&self, py: Python<'_>) -> Vec<Py<PyCompound>> {
+        self.xics.iter().map(|compound| compound.clone_ref(py)).collect()
+    }
+
+    /// The TIC of the MS1 scans as a 2xN NumPy array of scan times and intensities,
+    /// of the scans of one polarity (`"positive"` or `"negative"`) if given.
+    ///
+    /// Returns `None` if the measurement has no chromatogram for the polarity, which
+    /// is the case unless the run switches polarity.
+    #[pyo3(signature = (polarity = None))]
+    fn tic<'py>(&self, py: Python<'py>, polarity: Option<&str>) -> PyResult<Option<Bound<'py, PyArray2<f64>>>> {
+        let chromatogram = self.chromatogram(polarity)?;
+        Ok(chromatogram.map(|chromatogram| xic_array(py, &chromatogram.scan_times, &chromatogram.tic)))
+    }
+
+    /// The BPC of the MS1 scans, like `tic`
+    #[pyo3(signature = (polarity = None))]
+    fn bpc<'py>(&self, py: Python<'py>, polarity: Option<&str>) -> PyResult<Option<Bound<'py, PyArray2<f64>>>> {
+        let chromatogram = self.chromatogram(polarity)?;
+        Ok(chromatogram.map(|chromatogram| xic_array(py, &chromatogram.scan_times, &chromatogram.bpc)))
+    }
+
+    /// The scan of `ms_level` closest to `rt`, `None` unless processed with `keep_scans=True`
+    #[pyo3(signature = (rt, ms_level = 1))]
+    fn spectrum_at(&self, py: Python<'_>, rt: f64, ms_level: u8) -> Option<Py<PySpectrum>> {
+        let scans = self.scans_of(ms_level);
+        let inner: Vec<&MultiLayerSpectrum> = scans.iter().map(|scan| &scan.get().inner).collect();
+        let nearest = spectra::nearest_scan(&inner, rt, ms_level)?;
+        scans
+            .iter()
+            .find(|scan| std::ptr::eq(&scan.get().inner, nearest))
+            .map(|scan| scan.clone_ref(py))
+    }
+
+    /// The scans of `ms_level` from `start` to `end` minutes combined into one spectrum,
+    /// their mean intensity (`mode="mean"`) or sum (`mode="sum"`) in m/z bins of
+    /// `bin_width`, minus the mean of the scans of the `background` RT ranges.
+    ///
+    /// Returns `None` if there are no such scans, e.g. unless processed with
+    /// `keep_scans=True`. Raises `ValueError` for an unknown mode or a bin width that
+    /// is not positive.
+    #[pyo3(signature = (start, end, ms_level = 1, mode = "mean", bin_width = spectra::DEFAULT_BIN_WIDTH, background = Vec::new()))]
+    #[allow(clippy::too_many_arguments)]
+    fn combined_spectrum(
+        &self,
+        py: Python<'_>,
+        start: f64,
+        end: f64,
+        ms_level: u8,
+        mode: &str,
+        bin_width: f64,
+        background: Vec<(f64, f64)>,
+    ) -> PyResult<Option<PyExtractedSpectrum>> {
+        let mode = match mode {
+            "mean" => Combine::Mean,
+            "sum" => Combine::Sum,
+            other => return Err(PyValueError::new_err(format!("Unknown mode {other:?}, expected \"mean\" or \"sum\""))),
+        };
+        let options = CombineOptions { mode, bin_width, background };
+        let scans: Vec<&MultiLayerSpectrum> = self.scans_of(ms_level).iter().map(|scan| &scan.get().inner).collect();
+        let spectrum = py.allow_threads(|| spectra::combine_scans(&self.path, &scans, (start, end), ms_level, &options))?;
+        Ok(spectrum.map(|inner| PyExtractedSpectrum { inner }))
+    }
+}
+
+/// A spectrum combined from several scans by `Measurement.combined_spectrum`
+#[pyclass(name = "ExtractedSpectrum", module = "lcmspector_backend", frozen)]
+pub struct PyExtractedSpectrum {
+    inner: ExtractedSpectrum,
+}
+
+#[pymethods]
+impl PyExtractedSpectrum {
+    #[getter]
+    fn file(&self) -> &str {
+        &self.inner.file
+    }
+
+    #[getter]
+    fn ms_level(&self) -> u8 {
+        self.inner.ms_level
+    }
+
+    /// First and last retention time of the combined scans, in minutes
+    #[getter]
+    fn rt_range(&self) -> (f64, f64) {
+        self.inner.rt_range
+    }
+
+    /// Number of scans combined
+    #[getter]
+    fn scans(&self) -> usize {
+        self.inner.scans
+    }
+
+    /// Number of background scans subtracted
+    #[getter]
+    fn background_scans(&self) -> usize {
+        self.inner.background_scans
+    }
+
+    /// The m/z array as a NumPy array
+    #[getter]
+    fn mz<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        PyArray1::from_slice(py, &self.inner.mzs)
+    }
+
+    /// The intensity array as a NumPy array
+    #[getter]
+    fn intensity<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        PyArray1::from_slice(py, &self.inner.intensities)
+    }
+
+    /// Write the spectrum to `path`, as MGF if it ends in `.mgf` and as CSV otherwise
+    fn export(&self, path: PathBuf) -> PyResult<()> {
+        export_spectrum(path, &self.inner)?;
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.mzs.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ExtractedSpectrum(ms_level={}, rt_range={:?}, scans={})",
+            self.inner.ms_level, self.inner.rt_range, self.inner.scans
+        )
+    }
+}
+
+/// The outcome of processing one file with `process_files`
+#[pyclass(name = "FileResult", module = "lcmspector_backend", frozen)]
+pub struct PyFileResult {
+    #[pyo3(get)]
+    file: String,
+    /// The results, `None` if the file failed
+    #[pyo3(get)]
+    measurement: Option<Py<PyMeasurement>>,
+    /// Why the file failed, `None` if it succeeded
+    #[pyo3(get)]
+    error: Option<String>,
+}
+
+#[pymethods]
+impl PyFileResult {
+    fn __repr__(&self) -> String {
+        match &self.error {
+            Some(error) => format!("FileResult(file={:?}, error={:?})", self.file, error),
+            None => format!("FileResult(file={:?})", self.file),
+        }
+    }
+}
+
+impl PyMeasurement {
+    fn from_measurement(py: Python<'_>, measurement: MSMeasurement) -> PyResult<Self> {
+        Ok(PyMeasurement {
+            path: measurement.path,
+            sample_index: measurement.sample_index,
+            mass_accuracy: measurement.mass_accuracy,
+            ms1_scans: wrap_spectra(py, measurement.ms1_scans)?,
+            ms2_scans: wrap_spectra(py, measurement.ms2_scans)?,
+            xics: wrap_compounds(py, measurement.xics)?,
+            chromatograms: measurement.chromatograms,
+        })
+    }
+
+    /// The kept scans that hold `ms_level`
+    fn scans_of(&self, ms_level: u8) -> &PySpectra {
+        match ms_level {
+            1 => &self.ms1_scans,
+            _ => &self.ms2_scans,
+        }
+    }
+
+    /// The chromatogram over all m/z of the scans of `polarity`, or of all scans
+    fn chromatogram(&self, polarity: Option<&str>) -> PyResult<Option<&Chromatogram>> {
+        let polarity = match polarity {
+            None => None,
+            Some("positive") => Some(Polarity::Positive),
+            Some("negative") => Some(Polarity::Negative),
+            Some(other) => return Err(PyValueError::new_err(format!("Unknown polarity {other:?}"))),
+        };
+        Ok(self
+            .chromatograms
+            .iter()
+            .find(|chromatogram| chromatogram.filter.polarity == polarity && chromatogram.filter.mz_range.is_none()))
+    }
+}
+
+fn xic_array<'py>(py: Python<'py>, scan_times: &[f64], intensities: &[f64]) -> Bound<'py, PyArray2<f64>> {
+    Array2::from_shape_vec((2, intensities.len()), [scan_times, intensities].concat())
+        .expect("XIC traces have one scan time per intensity")
+        .to_pyarray(py)
+}
+
+fn wrap_spectra(py: Python<'_>, spectra: Vec<MultiLayerSpectrum>) -> PyResult<PySpectra> {
+    spectra
+        .into_iter()
+        .map(|inner| Py::new(py, PySpectrum { inner }))
+        .collect()
+}
+
+fn wrap_compounds(py: Python<'_>, compounds: Vec<Compound>) -> PyResult<Vec<Py<PyCompound>>> {
+    compounds
+        .into_iter()
+        .map(|inner| Py::new(py, PyCompound { inner }))
+        .collect()
+}
+
+/// How profile spectra are centroided, from the `centroid` and `vendor_centroids`
+/// arguments
+fn centroiding(centroid: &str, vendor_centroids: bool) -> PyResult<Centroiding> {
+    let method = match centroid {
+        "off" => CentroidMethod::Off,
+        "weighted-average" => CentroidMethod::WeightedAverage,
+        "gaussian" => CentroidMethod::Gaussian,
+        other => {
+            return Err(PyValueError::new_err(format!(
+                "Unknown centroid method {other:?}, expected \"off\", \"weighted-average\" or \"gaussian\""
+            )))
+        }
+    };
+    Ok(Centroiding { method, vendor_centroids })
+}
+
+/// Load the MS1 and MS2 scans of an mzML, mzXML or MGF file.
+///
+/// Profile spectra are centroided with `centroid`, one of "off", "weighted-average"
+/// and "gaussian"; spectra the file marks as centroided are kept as they are if
+/// `vendor_centroids` is true.
+///
+/// Raises `OSError` if the file cannot be read, `ValueError` if it is not in a
+/// supported format, and `InterruptedError` if `cancel` is triggered before the file
+/// is read.
+#[pyfunction]
+#[pyo3(signature = (file_path, centroid = "weighted-average", vendor_centroids = true, cancel = None))]
+fn load_ms_scans(
+    py: Python<'_>,
+    file_path: &str,
+    centroid: &str,
+    vendor_centroids: bool,
+    cancel: Option<&Bound<'_, PyCancellationToken>>,
+) -> PyResult<(PySpectra, PySpectra)> {
+    let centroiding = centroiding(centroid, vendor_centroids)?;
+    let cancel = token(cancel);
+    let (ms1_scans, ms2_scans) = py.allow_threads(|| {
+        loading::load_selected_ms_scans(file_path, &ScanSelection::default(), centroiding, &NoProgress, &cancel)
+    })?;
+    Ok((wrap_spectra(py, ms1_scans)?, wrap_spectra(py, ms2_scans)?))
+}
+
+/// Load an ion list by name from `ion_lists.json`.
+///
+/// Raises `KeyError` if there is no list of that name.
+#[pyfunction]
+fn load_ion_lists(py: Python<'_>, ion_list_name: &str) -> PyResult<Vec<Py<PyCompound>>> {
+    wrap_compounds(py, loading::load_ion_lists(ion_list_name)?)
+}
+
+/// Construct the XICs of an ion list from MS1 spectra.
+///
+/// Raises `ValueError` if an ion is not an m/z value, and `InterruptedError` if
+/// `cancel` is triggered before all spectra are matched.
+#[pyfunction]
+#[pyo3(signature = (spectra, ion_list, mass_accuracy = 0.0001, cancel = None))]
+fn construct_xics(
+    py: Python<'_>,
+    spectra: Vec<Bound<'_, PySpectrum>>,
+    ion_list: Vec<Bound<'_, PyCompound>>,
+    mass_accuracy: f64,
+    cancel: Option<&Bound<'_, PyCancellationToken>>,
+) -> PyResult<Vec<Py<PyCompound>>> {
+    let cancel = token(cancel);
+    let spectra: Vec<&MultiLayerSpectrum> = spectra.iter().map(|spectrum| &spectrum.get().inner).collect();
+    let ion_list: Vec<Compound> = ion_list.iter().map(|compound| compound.get().inner.clone()).collect();
+    let compounds = py.allow_threads(|| processing::construct_xics(&spectra, &ion_list, mass_accuracy, &cancel))?;
+    wrap_compounds(py, compounds)
+}
+
+/// Process files in parallel with an ion list from `ion_lists.json`.
+///
+/// `progress`, if given, is called with a dict for every progress event, e.g.
+/// `{"event": "file_finished", "file": "a.mzML", "seconds": 1.2}`. Profile spectra are
+/// centroided as with `load_ms_scans`.
+///
+/// Returns one `FileResult` per file, in the order given. A file that could not be
+/// processed has its `error` set instead of a `measurement`; when `cancel` is
+/// triggered, the files not completed by then fail as cancelled.
+#[pyfunction]
+#[pyo3(signature = (file_paths, ion_list_name, mass_accuracy = 0.0001, centroid = "weighted-average", vendor_centroids = true, keep_scans = false, cache_dir = None, progress = None, cancel = None))]
+#[allow(clippy::too_many_arguments)]
+fn process_files(
+    py: Python<'_>,
+    file_paths: Vec<String>,
+    ion_list_name: &str,
+    mass_accuracy: f64,
+    centroid: &str,
+    vendor_centroids: bool,
+    keep_scans: bool,
+    cache_dir: Option<PathBuf>,
+    progress: Option<PyObject>,
+    cancel: Option<&Bound<'_, PyCancellationToken>>,
+) -> PyResult<Vec<PyFileResult>> {
+    let centroiding = centroiding(centroid, vendor_centroids)?;
+    let cache = cache_dir.map(ScanCache::new);
+    let cancel = token(cancel);
+    let reporter = CallbackProgress(|event: ProgressEvent| {
+        if let Some(callback) = &progress {
+            Python::with_gil(|py| {
+                if let Err(e) = event_dict(py, &event).and_then(|dict| callback.call1(py, (dict,))) {
+                    e.print(py);
+                }
+            });
+        }
+    });
+    let ion_list = loading::load_ion_lists(ion_list_name)?;
+    let results = py.allow_threads(|| {
+        loading::process_files_in_parallel(
+            &file_paths,
+            &ion_list,
+            mass_accuracy,
+            centroiding,
+            keep_scans,
+            cache.as_ref(),
+            &reporter,
+            &cancel,
+        )
+    });
+    results
+        .into_iter()
+        .map(|FileResult { file, result }| {
+            let (measurement, error) = match result {
+                Ok(measurement) => (Some(Py::new(py, PyMeasurement::from_measurement(py, measurement)?)?), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            Ok(PyFileResult {
+                file,
+                measurement,
+                error,
+            })
+        })
+        .collect()
+}
+
+/// A progress event as a flat dict, tagged with its `event` name
+fn event_dict<'py>(py: Python<'py>, event: &ProgressEvent) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    if let Value::Object(fields) = serde_json::to_value(event).expect("Progress events serialize to JSON") {
+        for (key, value) in fields {
+            match value {
+                Value::String(text) => dict.set_item(key, text)?,
+                Value::Number(number) if number.is_u64() => dict.set_item(key, number.as_u64())?,
+                Value::Number(number) => dict.set_item(key, number.as_f64())?,
+                other => dict.set_item(key, other.to_string())?,
+            }
+        }
+    }
+    Ok(dict)
+}
+
+#[pymodule]
+fn lcmspector_backend(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_class::<PySpectrum>()?;
+    m.add_class::<PyCompound>()?;
+    m.add_class::<PyMeasurement>()?;
+    m.add_class::<PyExtractedSpectrum>()?;
+    m.add_class::<PyFileResult>()?;
+    m.add_class::<PyCancellationToken>()?;
+    m.add_function(wrap_pyfunction!(load_ms_scans, m)?)?;
+    m.add_function(wrap_pyfunction!(load_ion_lists, m)?)?;
+    m.add_function(wrap_pyfunction!(construct_xics, m)?)?;
+    m.add_function(wrap_pyfunction!(process_files, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::synthetic_run;
+    use pyo3::types::PyList;
+
+    #[test]
+    fn test_compound_ions_in_ion_list_order() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut inner = Compound::new("Lactate".to_string(), vec![89.0244, 43.0189, 71.0139], Vec::new());
+            inner.ions[1].ms_intensity = Some(1200.0);
+            let compound = PyCompound { inner };
+
+            let ions = compound.ions(py).unwrap();
+            let names: Vec<String> = ions.keys().extract().unwrap();
+            assert_eq!(names, ["89.0244", "43.0189", "71.0139"]);
+            let results = ions.get_item("43.0189").unwrap().unwrap();
+            let keys: Vec<String> = results.downcast::<PyDict>().unwrap().keys().extract().unwrap();
+            assert_eq!(keys, ["m/z", "RT", "MS Intensity", "LC Intensity"]);
+            assert_eq!(results.get_item("MS Intensity").unwrap().extract::<Option<f64>>().unwrap(), Some(1200.0));
+            assert_eq!(results.get_item("RT").unwrap().extract::<Option<f64>>().unwrap(), None);
+            assert!(ions.values().is_instance_of::<PyList>());
+        });
+    }
+
+    #[test]
+    fn test_measurement_spectrum_at() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let (ms1, ms2): (Vec<_>, Vec<_>) = synthetic_run().into_iter().partition(|scan| scan.ms_level() == 1);
+            let measurement = MSMeasurement::from_data(ms1, ms2, Vec::new(), 0.0001).with_source("run.mzML", 0);
+            let expected = spectra::nearest_scan(&measurement.ms2_scans, 0.52, 2).unwrap().id().to_string();
+            let measurement = PyMeasurement::from_measurement(py, measurement).unwrap();
+
+            let spectrum = measurement.spectrum_at(py, 0.52, 1).unwrap();
+            assert_eq!(spectrum.get().rt(), 0.5);
+            let spectrum = measurement.spectrum_at(py, 0.52, 2).unwrap();
+            assert_eq!(spectrum.get().id(), expected);
+            assert!(measurement.spectrum_at(py, 0.52, 3).is_none());
+        });
+    }
+}
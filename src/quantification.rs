@@ -0,0 +1,150 @@
+use crate::measurements::{Compound, MSMeasurement};
+use serde::Serialize;
+
+/// A linear calibration curve, `intensity = slope * concentration + intercept`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CalibrationCurve {
+    pub slope: f64,
+    pub intercept: f64,
+    pub r_squared: f64,
+}
+
+/// The concentration of one compound in one measurement
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Quantity {
+    pub compound: String,
+    /// Summed MS intensity of all ions of the compound
+    pub intensity: Option<f64>,
+    pub concentration: Option<f64>,
+}
+
+impl CalibrationCurve {
+    /// Fit a least-squares line through `(concentration, intensity)` points.
+    ///
+    /// Needs at least two points with distinct concentrations.
+    pub fn fit(points: &[(f64, f64)]) -> Option<Self> {
+        if points.len() < 2 {
+            return None;
+        }
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let sxy: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+        if sxx == 0.0 {
+            return None;
+        }
+        let slope = sxy / sxx;
+        let intercept = mean_y - slope * mean_x;
+
+        let ss_tot: f64 = points.iter().map(|(_, y)| (y - mean_y).powi(2)).sum();
+        let ss_res: f64 = points
+            .iter()
+            .map(|(x, y)| (y - (slope * x + intercept)).powi(2))
+            .sum();
+        let r_squared = if ss_tot == 0.0 { 1.0 } else { 1.0 - ss_res / ss_tot };
+
+        Some(CalibrationCurve {
+            slope,
+            intercept,
+            r_squared,
+        })
+    }
+
+    /// Read a curve back from the `calibration_curve` map of a compound
+    pub fn from_compound(compound: &Compound) -> Option<Self> {
+        let curve = &compound.calibration_curve;
+        Some(CalibrationCurve {
+            slope: *curve.get("slope")?,
+            intercept: *curve.get("intercept")?,
+            r_squared: curve.get("r_squared").copied().unwrap_or(f64::NAN),
+        })
+    }
+
+    /// Store the curve in the `calibration_curve` map of a compound
+    pub fn store_in(&self, compound: &mut Compound) {
+        compound.calibration_curve.insert("slope".to_string(), self.slope);
+        compound.calibration_curve.insert("intercept".to_string(), self.intercept);
+        compound.calibration_curve.insert("r_squared".to_string(), self.r_squared);
+    }
+
+    /// Concentration corresponding to a measured intensity
+    pub fn concentration(&self, intensity: f64) -> Option<f64> {
+        if self.slope == 0.0 {
+            return None;
+        }
+        Some((intensity - self.intercept) / self.slope)
+    }
+}
+
+/// Total MS intensity of a compound, summed over its ions, or `None` if no ion was found
+pub fn compound_intensity(compound: &Compound) -> Option<f64> {
+    let intensities: Vec<f64> = compound
+        .ions
+        .iter()
+        .filter_map(|ion| ion.ms_intensity)
+        .collect();
+    if intensities.is_empty() {
+        None
+    } else {
+        Some(intensities.iter().sum())
+    }
+}
+
+/// Fit a calibration curve for every compound of `ion_list` from measured standards.
+///
+/// `standards` pairs each standard measurement with its concentration. Compounds are
+/// matched by name; compounds with fewer than two usable standards stay uncalibrated.
+pub fn calibrate(ion_list: &mut [Compound], standards: &[(f64, &MSMeasurement)]) {
+    for compound in ion_list.iter_mut() {
+        let points: Vec<(f64, f64)> = standards
+            .iter()
+            .filter_map(|(concentration, measurement)| {
+                let measured = measurement.xics.iter().find(|c| c.name == compound.name)?;
+                Some((*concentration, compound_intensity(measured)?))
+            })
+            .collect();
+        if let Some(curve) = CalibrationCurve::fit(&points) {
+            curve.store_in(compound);
+        }
+    }
+}
+
+/// Concentrations of all compounds in a measurement, using the curves of `calibrated`
+pub fn quantify(measurement: &MSMeasurement, calibrated: &[Compound]) -> Vec<Quantity> {
+    measurement
+        .xics
+        .iter()
+        .map(|compound| {
+            let intensity = compound_intensity(compound);
+            let curve = calibrated
+                .iter()
+                .find(|c| c.name == compound.name)
+                .and_then(CalibrationCurve::from_compound);
+            Quantity {
+                compound: compound.name.clone(),
+                intensity,
+                concentration: intensity.zip(curve).and_then(|(i, curve)| curve.concentration(i)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_recovers_line() {
+        let points = [(0.0, 1.0), (1.0, 3.0), (2.0, 5.0), (4.0, 9.0)];
+        let curve = CalibrationCurve::fit(&points).unwrap();
+        assert!((curve.slope - 2.0).abs() < 1e-12);
+        assert!((curve.intercept - 1.0).abs() < 1e-12);
+        assert!((curve.r_squared - 1.0).abs() < 1e-12);
+        assert_eq!(curve.concentration(7.0), Some(3.0));
+
+        // A single point or identical concentrations don't define a line
+        assert!(CalibrationCurve::fit(&[(1.0, 2.0)]).is_none());
+        assert!(CalibrationCurve::fit(&[(1.0, 2.0), (1.0, 3.0)]).is_none());
+    }
+}
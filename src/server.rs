@@ -0,0 +1,699 @@
+//! Long-running JSON-RPC 2.0 server over stdin/stdout.
+//!
+//! The GUI sends one request per line and receives one response per line, plus
+//! `progress` notifications while files are being loaded or processed. Measurements
+//! stay resident between requests, keyed by file path, so the GUI can re-query XICs
+//! or re-run the extraction with another ion list without reloading its files.
+//!
+//! Methods:
+//!
+//! - `load` `{files, keep_scans?, centroiding?}`: read files into memory. With
+//!   `keep_scans` (the default) the MS1 and MS2 scans stay resident, so later
+//!   extractions don't need to read the files again. Profile spectra are centroided as
+//!   set by `centroiding`, e.g. `{"method": "gaussian", "vendor_centroids": false}`.
+//! - `extract` `{files, ion_list, mass_accuracy?, centroiding?}`: build the XICs of an
+//!   ion list from the configured ion lists files. Resident scans are reused as they
+//!   were loaded, other files are streamed and centroided as set by `centroiding`.
+//!   The XICs are integrated with the peak picking and integration settings of the
+//!   configuration, like the CLI does.
+//!
+//!   Both answer with one entry per file and a `cancelled` flag; a file that failed
+//!   has an `error` with the reason in place of its results, and does not fail the
+//!   other files. `extract` also answers with the `provenance` of the results.
+//! - `xic` `{file, compound}`: the XIC traces of a compound in a processed file.
+//! - `spectrum` `{file, rt, ms_level?}`: the scan closest to `rt` in a file loaded with
+//!   its scans. With `{file, rt_range, ms_level?, mode?, bin_width?, background?}`
+//!   instead, the scans of `rt_range` averaged (`mode` `"mean"`) or summed (`"sum"`),
+//!   minus the mean of the scans of the `background` RT ranges.
+//! - `list`: the resident files.
+//! - `unload` `{files?}`: drop resident files, all of them if `files` is omitted.
+//! - `cancel` `{id}`: cancel a running `load` or `extract` request. The cancelled
+//!   request is answered with the files it completed and `"cancelled": true`.
+//! - `shutdown`: stop reading requests once running requests are done.
+//!
+//! Parameters that are left out are taken from the [`Config`] the server was started
+//! with.
+//!
+//! `load` and `extract` run in the background, so `cancel` and queries can be handled
+//! while they are in progress. Besides the per-file `progress` notifications, they
+//! forward every [`ProgressEvent`] as an `event` notification.
+
+use crate::batch::integrate;
+use crate::cancel::CancellationToken;
+use crate::centroiding::Centroiding;
+use crate::config::Config;
+use crate::error::Error;
+use crate::ion_lists::IonLists;
+use crate::loading::{self, detect_format, process_file_streaming, ScanSelection};
+use crate::measurements::MSMeasurement;
+use crate::processing::construct_xics;
+use crate::progress::{ProgressEvent, ProgressReporter};
+use crate::provenance::Provenance;
+use crate::spectra::{combined_spectrum, spectrum_at, CombineOptions};
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// A request failed, e.g. because a file is not loaded
+pub const SERVER_ERROR: i64 = -32000;
+
+/// A JSON-RPC error: code and message
+type RpcError = (i64, String);
+
+/// Cancellation tokens of the running background requests, by request id
+type RunningRequests = Arc<Mutex<HashMap<String, CancellationToken>>>;
+
+/// Run the server on stdin/stdout until stdin is closed or `shutdown` is received
+pub fn serve_stdio(config: &Config) -> io::Result<()> {
+    serve(io::stdin().lock(), io::stdout(), config)
+}
+
+/// Run the server, reading requests from `input` and writing messages to `output`
+pub fn serve<R: BufRead, W: Write + Send + 'static>(input: R, output: W, config: &Config) -> io::Result<()> {
+    let server = Server::new(output, config.clone());
+    for line in input.lines() {
+        if !server.handle(&line?) {
+            break;
+        }
+    }
+    server.join();
+    Ok(())
+}
+
+/// Server state shared between the request loop and background requests
+pub struct Server {
+    /// Where ion lists are read from and the defaults of request parameters
+    config: Arc<Config>,
+    measurements: Arc<RwLock<HashMap<String, MSMeasurement>>>,
+    output: Arc<Mutex<Box<dyn Write + Send>>>,
+    running: RunningRequests,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Server {
+    pub fn new<W: Write + Send + 'static>(output: W, config: Config) -> Self {
+        Server {
+            config: Arc::new(config),
+            measurements: Arc::new(RwLock::new(HashMap::new())),
+            output: Arc::new(Mutex::new(Box::new(output))),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Handle one line of input. Returns `false` once the server should stop reading.
+    pub fn handle(&self, line: &str) -> bool {
+        if line.trim().is_empty() {
+            return true;
+        }
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => {
+                self.send(&error_response(&Value::Null, (PARSE_ERROR, e.to_string())));
+                return true;
+            }
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            self.send(&error_response(&id, (INVALID_REQUEST, "Missing method".to_string())));
+            return true;
+        };
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+
+        let result = match method {
+            "load" | "extract" => {
+                self.spawn(id, method.to_string(), params);
+                return true;
+            }
+            "xic" => self.xic(&params),
+            "spectrum" => self.spectrum(&params),
+            "list" => Ok(self.list()),
+            "unload" => self.unload(&params),
+            "cancel" => self.cancel(&params),
+            "shutdown" => {
+                self.send(&result_response(&id, Value::Null));
+                return false;
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {method}"))),
+        };
+        // Requests without an id are notifications and get no response
+        if !id.is_null() {
+            match result {
+                Ok(value) => self.send(&result_response(&id, value)),
+                Err(error) => self.send(&error_response(&id, error)),
+            }
+        }
+        true
+    }
+
+    /// Wait for all background requests to finish
+    pub fn join(&self) {
+        let handles: Vec<JoinHandle<()>> = self.handles.lock().unwrap().drain(..).collect();
+        for handle in handles {
+            handle.join().ok();
+        }
+    }
+
+    fn send(&self, message: &Value) {
+        send(&self.output, message);
+    }
+
+    /// Run a `load` or `extract` request on its own thread
+    fn spawn(&self, id: Value, method: String, params: Value) {
+        let cancel = CancellationToken::new();
+        self.running.lock().unwrap().insert(id.to_string(), cancel.clone());
+        let job = Job {
+            id,
+            cancel,
+            config: Arc::clone(&self.config),
+            measurements: Arc::clone(&self.measurements),
+            output: Arc::clone(&self.output),
+        };
+        let running = Arc::clone(&self.running);
+        let handle = thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| match method.as_str() {
+                "load" => job.load(&params),
+                _ => job.extract(&params),
+            }))
+            .unwrap_or_else(|_| Err((SERVER_ERROR, format!("The {method} request failed unexpectedly"))));
+            running.lock().unwrap().remove(&job.id.to_string());
+            match result {
+                Ok(value) => send(&job.output, &result_response(&job.id, value)),
+                Err(error) => send(&job.output, &error_response(&job.id, error)),
+            }
+        });
+        self.handles.lock().unwrap().push(handle);
+    }
+
+    fn xic(&self, params: &Value) -> Result<Value, RpcError> {
+        let file = str_param(params, "file")?;
+        let compound_name = str_param(params, "compound")?;
+        let measurements = self.measurements.read().unwrap();
+        let measurement = measurements
+            .get(file)
+            .ok_or_else(|| (SERVER_ERROR, format!("File is not loaded: {file}")))?;
+        let compound = measurement
+            .xics
+            .iter()
+            .find(|compound| compound.name == compound_name)
+            .ok_or_else(|| (SERVER_ERROR, format!("No XICs for {compound_name} in {file}, run extract first")))?;
+        let xics: serde_json::Map<String, Value> = compound
+            .xics
+            .iter()
+            .map(|(ion, (scan_times, intensities))| {
+                (ion.clone(), json!({"scan_times": scan_times, "intensities": intensities}))
+            })
+            .collect();
+        Ok(json!({"file": file, "compound": compound_name, "xics": xics}))
+    }
+
+    fn spectrum(&self, params: &Value) -> Result<Value, RpcError> {
+        let file = str_param(params, "file")?;
+        let ms_level = match params.get("ms_level") {
+            None => 1,
+            Some(level) => level
+                .as_u64()
+                .and_then(|level| u8::try_from(level).ok())
+                .ok_or_else(|| (INVALID_PARAMS, "Parameter ms_level must be an MS level".to_string()))?,
+        };
+        let measurements = self.measurements.read().unwrap();
+        let measurement = measurements
+            .get(file)
+            .ok_or_else(|| (SERVER_ERROR, format!("File is not loaded: {file}")))?;
+        let (spectrum, missing) = match (params.get("rt"), params.get("rt_range")) {
+            (Some(rt), None) => {
+                let rt = rt.as_f64().ok_or_else(|| (INVALID_PARAMS, "Parameter rt must be a number".to_string()))?;
+                let missing = format!("No MS{ms_level} scans of {file} are loaded, load it with keep_scans");
+                (spectrum_at(measurement, rt, ms_level), missing)
+            }
+            (None, Some(rt_range)) => {
+                let rt_range: (f64, f64) = serde_json::from_value(rt_range.clone())
+                    .map_err(|_| (INVALID_PARAMS, "Parameter rt_range must be [start, end]".to_string()))?;
+                let options: CombineOptions = serde_json::from_value(params.clone())
+                    .map_err(|e| (INVALID_PARAMS, format!("Invalid parameters: {e}")))?;
+                let missing = format!("No MS{ms_level} scans of {file} from {} to {} min are loaded", rt_range.0, rt_range.1);
+                (combined_spectrum(measurement, rt_range, ms_level, &options), missing)
+            }
+            _ => return Err((INVALID_PARAMS, "Either rt or rt_range is required".to_string())),
+        };
+        match spectrum {
+            Ok(Some(spectrum)) => Ok(json!(spectrum)),
+            Ok(None) => Err((SERVER_ERROR, missing)),
+            Err(Error::InvalidParameter { name, message }) => Err((INVALID_PARAMS, format!("Invalid {name}: {message}"))),
+            Err(e) => Err((SERVER_ERROR, e.to_string())),
+        }
+    }
+
+    fn list(&self) -> Value {
+        let measurements = self.measurements.read().unwrap();
+        let mut files: Vec<Value> = measurements
+            .iter()
+            .map(|(file, measurement)| file_summary(file, measurement))
+            .collect();
+        files.sort_by(|a, b| a["file"].as_str().cmp(&b["file"].as_str()));
+        json!({ "files": files })
+    }
+
+    fn unload(&self, params: &Value) -> Result<Value, RpcError> {
+        let mut measurements = self.measurements.write().unwrap();
+        let unloaded = match params.get("files") {
+            Some(_) => files_param(params)?
+                .iter()
+                .filter(|file| measurements.remove(**file).is_some())
+                .count(),
+            None => measurements.drain().count(),
+        };
+        Ok(json!({ "unloaded": unloaded }))
+    }
+
+    fn cancel(&self, params: &Value) -> Result<Value, RpcError> {
+        let id = params
+            .get("id")
+            .ok_or_else(|| (INVALID_PARAMS, "Missing parameter: id".to_string()))?;
+        match self.running.lock().unwrap().get(&id.to_string()) {
+            Some(cancel) => {
+                cancel.cancel();
+                Ok(json!({ "cancelled": true }))
+            }
+            None => Err((SERVER_ERROR, format!("No running request with id {id}"))),
+        }
+    }
+}
+
+/// A background request with its cancellation flag
+struct Job {
+    id: Value,
+    cancel: CancellationToken,
+    config: Arc<Config>,
+    measurements: Arc<RwLock<HashMap<String, MSMeasurement>>>,
+    output: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Job {
+    fn load(&self, params: &Value) -> Result<Value, RpcError> {
+        let files = files_param(params)?;
+        let keep_scans = params.get("keep_scans").and_then(Value::as_bool).unwrap_or(true);
+        let centroiding = centroiding_param(params, self.config.centroiding)?;
+        let completed = AtomicUsize::new(0);
+
+        let results: Vec<Value> = files
+            .par_iter()
+            .enumerate()
+            .filter(|_| !self.cancel.is_cancelled())
+            .filter_map(|(index, file)| {
+                let loaded = std::fs::metadata(file)
+                    .map_err(|e| Error::io(file, e))
+                    .and_then(|_| detect_format(file))
+                    .and_then(|_| match keep_scans {
+                        true => loading::load_selected_ms_scans(file, &ScanSelection::default(), centroiding, self, &self.cancel),
+                        false => Ok((Vec::new(), Vec::new())),
+                    });
+                let summary = match loaded {
+                    Ok((ms1_scans, ms2_scans)) => {
+                        let measurement = MSMeasurement::from_data(ms1_scans, ms2_scans, Vec::new(), 0.0).with_source(file, index);
+                        let summary = file_summary(file, &measurement);
+                        self.measurements.write().unwrap().insert(file.to_string(), measurement);
+                        summary
+                    }
+                    // Files interrupted by `cancel` are left out, the completed ones are kept
+                    Err(Error::Cancelled) => return None,
+                    Err(e) => json!({"file": file, "error": e.to_string()}),
+                };
+                self.progress(&completed, files.len(), file);
+                Some(summary)
+            })
+            .collect();
+        Ok(json!({ "files": results, "cancelled": self.cancel.is_cancelled() }))
+    }
+
+    fn extract(&self, params: &Value) -> Result<Value, RpcError> {
+        let files = files_param(params)?;
+        let ion_list_name = str_param(params, "ion_list")?;
+        let mass_accuracy = params
+            .get("mass_accuracy")
+            .and_then(Value::as_f64)
+            .unwrap_or(self.config.tolerances.mass_accuracy);
+        let centroiding = centroiding_param(params, self.config.centroiding)?;
+        let ion_list = IonLists::from_files(&self.config.ion_lists.paths)
+            .and_then(|ion_lists| ion_lists.compounds(ion_list_name))
+            .map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+        let parameters = json!({
+            "files": files,
+            "ion_list": ion_list_name,
+            "mass_accuracy": mass_accuracy,
+            "centroiding": centroiding,
+            "peak_picking": self.config.peak_picking,
+            "integration": self.config.integration,
+        });
+        let mut provenance = Provenance::start(&parameters, &[ion_list_name], &ion_list);
+        let completed = AtomicUsize::new(0);
+
+        let results: Vec<Value> = files
+            .par_iter()
+            .filter(|_| !self.cancel.is_cancelled())
+            .filter_map(|file| {
+                // Reuse resident scans, stream the file otherwise
+                let resident_xics = self
+                    .measurements
+                    .read()
+                    .unwrap()
+                    .get(*file)
+                    .filter(|measurement| !measurement.ms1_scans.is_empty())
+                    .map(|measurement| construct_xics(&measurement.ms1_scans, &ion_list, mass_accuracy, &self.cancel));
+                let extracted = match resident_xics {
+                    Some(xics) => xics.map(|xics| {
+                        let mut measurements = self.measurements.write().unwrap();
+                        let measurement = measurements.get_mut(*file).unwrap();
+                        measurement.xics = xics;
+                        integrate(&mut measurement.xics, &self.config);
+                        measurement.mass_accuracy = mass_accuracy as f32;
+                        json!({"file": file, "compounds": measurement.xics})
+                    }),
+                    None => process_file_streaming(file, &ion_list, mass_accuracy, centroiding, false, None, self, &self.cancel)
+                        .map(|mut measurement| {
+                            integrate(&mut measurement.xics, &self.config);
+                            let summary = json!({"file": file, "compounds": measurement.xics});
+                            self.measurements.write().unwrap().insert(file.to_string(), measurement);
+                            summary
+                        }),
+                };
+                let summary = match extracted {
+                    Ok(summary) => summary,
+                    Err(Error::Cancelled) => return None,
+                    Err(e) => json!({"file": file, "error": e.to_string()}),
+                };
+                self.progress(&completed, files.len(), file);
+                Some(summary)
+            })
+            .collect();
+        provenance.finish(&files);
+        Ok(json!({ "files": results, "cancelled": self.cancel.is_cancelled(), "provenance": provenance }))
+    }
+
+    /// Count a finished file and notify the client
+    fn progress(&self, completed: &AtomicUsize, total: usize, file: &str) {
+        let completed = completed.fetch_add(1, Ordering::SeqCst) + 1;
+        send(
+            &self.output,
+            &json!({
+                "jsonrpc": "2.0",
+                "method": "progress",
+                "params": {"id": self.id, "completed": completed, "total": total, "file": file},
+            }),
+        );
+    }
+}
+
+impl ProgressReporter for Job {
+    fn report(&self, event: ProgressEvent) {
+        let mut params = serde_json::to_value(event).expect("Progress events serialize to JSON");
+        params["id"] = self.id.clone();
+        send(&self.output, &json!({"jsonrpc": "2.0", "method": "event", "params": params}));
+    }
+}
+
+fn send(output: &Mutex<Box<dyn Write + Send>>, message: &Value) {
+    let mut output = output.lock().unwrap();
+    if let Err(e) = writeln!(output, "{message}").and_then(|_| output.flush()) {
+        eprintln!("Could not write JSON-RPC message: {e}");
+    }
+}
+
+fn result_response(id: &Value, result: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "result": result})
+}
+
+fn error_response(id: &Value, (code, message): RpcError) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn file_summary(file: &str, measurement: &MSMeasurement) -> Value {
+    json!({
+        "file": file,
+        "ms1_scans": measurement.ms1_scans.len(),
+        "ms2_scans": measurement.ms2_scans.len(),
+        "compounds": measurement.xics.len(),
+    })
+}
+
+fn str_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| (INVALID_PARAMS, format!("Missing parameter: {name}")))
+}
+
+fn centroiding_param(params: &Value, default: Centroiding) -> Result<Centroiding, RpcError> {
+    match params.get("centroiding") {
+        None => Ok(default),
+        Some(centroiding) => serde_json::from_value(centroiding.clone())
+            .map_err(|e| (INVALID_PARAMS, format!("Invalid parameter centroiding: {e}"))),
+    }
+}
+
+fn files_param(params: &Value) -> Result<Vec<&str>, RpcError> {
+    params
+        .get("files")
+        .and_then(Value::as_array)
+        .and_then(|files| files.iter().map(Value::as_str).collect())
+        .ok_or_else(|| (INVALID_PARAMS, "Parameter files must be a list of paths".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{synthetic_run, temp_path, write_mzml};
+
+    /// A writer whose contents can be read back while the server holds it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        /// Take the messages written so far
+        fn messages(&self) -> Vec<Value> {
+            let bytes: Vec<u8> = self.0.lock().unwrap().drain(..).collect();
+            String::from_utf8(bytes)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    /// Cancels the running requests of a server as soon as the first file of one is done
+    #[derive(Clone, Default)]
+    struct CancelOnProgress {
+        buffer: SharedBuffer,
+        line: Arc<Mutex<Vec<u8>>>,
+        running: Arc<std::sync::OnceLock<RunningRequests>>,
+    }
+
+    impl Write for CancelOnProgress {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.line.lock().unwrap().extend_from_slice(buf);
+            self.buffer.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            // Every message is flushed once it is written
+            let line = std::mem::take(&mut *self.line.lock().unwrap());
+            let message: Value = serde_json::from_slice(&line).unwrap();
+            if message["method"] == "progress" {
+                for cancel in self.running.get().unwrap().lock().unwrap().values() {
+                    cancel.cancel();
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn response(messages: &[Value], id: i64) -> &Value {
+        messages.iter().find(|m| m["id"] == id).expect("no response")
+    }
+
+    #[test]
+    fn test_load_extract_and_query() {
+        let path = write_mzml("server.mzML", &synthetic_run());
+        let file = path.to_str().unwrap();
+        let output = SharedBuffer::default();
+        let server = Server::new(output.clone(), Config::default());
+
+        server.handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "load", "params": {"files": [file]}}).to_string());
+        server.join();
+        let messages = output.messages();
+        assert_eq!(messages.iter().filter(|m| m["method"] == "progress").count(), 1);
+        let loaded = &response(&messages, 1)["result"]["files"][0];
+        assert_eq!((loaded["ms1_scans"].as_u64(), loaded["ms2_scans"].as_u64()), (Some(10), Some(10)));
+
+        server.handle(&json!({"jsonrpc": "2.0", "id": 2, "method": "extract",
+            "params": {"files": [file], "ion_list": "scfas", "mass_accuracy": 0.0001}}).to_string());
+        server.join();
+        let messages = output.messages();
+        let compounds = response(&messages, 2)["result"]["files"][0]["compounds"].as_array().unwrap();
+        let acetate = compounds.iter().find(|c| c["name"] == "Acetate").unwrap();
+        assert_eq!(acetate["ions"]["59.0139"]["MS Intensity"], 25500.0);
+
+        // Queries are answered from the resident measurement
+        server.handle(&json!({"jsonrpc": "2.0", "id": 3, "method": "xic",
+            "params": {"file": file, "compound": "Acetate"}}).to_string());
+        server.handle(&json!({"jsonrpc": "2.0", "id": 4, "method": "xic",
+            "params": {"file": "missing.mzML", "compound": "Acetate"}}).to_string());
+        server.handle(&json!({"jsonrpc": "2.0", "id": 5, "method": "cancel", "params": {"id": 2}}).to_string());
+        server.handle(&json!({"jsonrpc": "2.0", "id": 6, "method": "frobnicate"}).to_string());
+        server.handle("{not json");
+        let messages = output.messages();
+        let xic = &response(&messages, 3)["result"]["xics"]["59.0139"];
+        assert_eq!(xic["intensities"].as_array().unwrap().len(), 10);
+        assert_eq!(xic["intensities"][5], 5000.0);
+        assert_eq!(response(&messages, 4)["error"]["code"], SERVER_ERROR);
+        assert_eq!(response(&messages, 5)["error"]["code"], SERVER_ERROR);
+        assert_eq!(response(&messages, 6)["error"]["code"], METHOD_NOT_FOUND);
+        assert!(messages.iter().any(|m| m["error"]["code"] == PARSE_ERROR));
+
+        // Spectra at the clicked RT, or combined over a peak, from the resident scans
+        server.handle(&json!({"jsonrpc": "2.0", "id": 9, "method": "spectrum",
+            "params": {"file": file, "rt": 0.52}}).to_string());
+        server.handle(&json!({"jsonrpc": "2.0", "id": 10, "method": "spectrum",
+            "params": {"file": file, "rt_range": [0.35, 0.65], "mode": "sum", "background": [[0.0, 0.05]]}}).to_string());
+        server.handle(&json!({"jsonrpc": "2.0", "id": 11, "method": "spectrum",
+            "params": {"file": file, "rt_range": [0.35, 0.65], "bin_width": -1}}).to_string());
+        let messages = output.messages();
+        let spectrum = &response(&messages, 9)["result"];
+        assert_eq!((spectrum["rt_range"][0].as_f64(), spectrum["intensities"][1].as_f64()), (Some(0.5), Some(5000.0)));
+        let combined = &response(&messages, 10)["result"];
+        assert_eq!((combined["scans"].as_u64(), combined["background_scans"].as_u64()), (Some(3), Some(1)));
+        // 4000 + 5000 + 4000 minus 3 times the background of 500; the constant peaks cancel out
+        assert_eq!(combined["intensities"], json!([11500.0]));
+        assert_eq!(response(&messages, 11)["error"]["code"], INVALID_PARAMS);
+
+        server.handle(&json!({"jsonrpc": "2.0", "id": 7, "method": "unload", "params": {}}).to_string());
+        server.handle(&json!({"jsonrpc": "2.0", "id": 8, "method": "list"}).to_string());
+        let messages = output.messages();
+        assert_eq!(response(&messages, 7)["result"]["unloaded"], 1);
+        assert_eq!(response(&messages, 8)["result"]["files"], json!([]));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_extract_uses_the_config() {
+        let path = write_mzml("server.mzML", &synthetic_run());
+        let file = path.to_str().unwrap();
+        let ion_lists_path = temp_path("ion_lists.json");
+        std::fs::write(&ion_lists_path, r#"{"custom": {"Acetate": {"ions": [59.0139], "info": ["Acetate-neg"]}}}"#).unwrap();
+        let mut config = Config::default();
+        config.ion_lists.paths = vec![ion_lists_path.clone()];
+        config.tolerances.mass_accuracy = 0.0002;
+        let output = SharedBuffer::default();
+        let server = Server::new(output.clone(), config);
+
+        server.handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "extract",
+            "params": {"files": [file], "ion_list": "custom"}}).to_string());
+        server.handle(&json!({"jsonrpc": "2.0", "id": 2, "method": "extract",
+            "params": {"files": [file], "ion_list": "scfas"}}).to_string());
+        server.join();
+        let messages = output.messages();
+        let result = &response(&messages, 1)["result"];
+        assert_eq!(result["files"][0]["compounds"][0]["ions"]["59.0139"]["MS Intensity"], 25500.0);
+        assert_eq!(result["provenance"]["parameters"]["mass_accuracy"], 0.0002);
+        assert_eq!(result["provenance"]["parameters"]["centroiding"]["method"], "weighted-average");
+        // Only the configured ion lists files are searched
+        assert_eq!(response(&messages, 2)["error"]["code"], INVALID_PARAMS);
+        for path in [path, ion_lists_path] {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[test]
+    fn test_extract_integrates_like_the_cli() {
+        let path = write_mzml("integrated.mzML", &synthetic_run());
+        let file = path.to_str().unwrap().to_string();
+        let mut config = Config::default();
+        config.peak_picking.min_intensity = 2500.0;
+        config.integration.rt_range = Some((0.3, 0.8));
+        let ion_list = IonLists::from_files(&config.ion_lists.paths).unwrap().compounds("scfas").unwrap();
+        let files = [file.clone()];
+        let batch = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(crate::batch::process_batch(&files, ion_list, &config, None, Arc::new(crate::NoProgress), CancellationToken::new()));
+        let expected = json!(batch[0].result.as_ref().unwrap().xics);
+
+        let output = SharedBuffer::default();
+        let server = Server::new(output.clone(), config);
+        server.handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "extract",
+            "params": {"files": [file], "ion_list": "scfas"}}).to_string());
+        server.join();
+        // Resident scans go through the same integration
+        server.handle(&json!({"jsonrpc": "2.0", "id": 2, "method": "load", "params": {"files": [file]}}).to_string());
+        server.join();
+        server.handle(&json!({"jsonrpc": "2.0", "id": 3, "method": "extract",
+            "params": {"files": [file], "ion_list": "scfas"}}).to_string());
+        server.join();
+        let messages = output.messages();
+        for id in [1, 3] {
+            assert_eq!(response(&messages, id)["result"]["files"][0]["compounds"], expected);
+        }
+        let acetate = expected.as_array().unwrap().iter().find(|c| c["name"] == "Acetate").unwrap();
+        assert_eq!(acetate["ions"]["59.0139"]["MS Intensity"], 19000.0);
+        assert_eq!(response(&messages, 1)["result"]["provenance"]["parameters"]["peak_picking"]["min_intensity"], 2500.0);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_cancelled_requests_answer_completed_files() {
+        let path = write_mzml("cancelled.mzML", &synthetic_run());
+        let total = rayon::current_num_threads() * 4 + 4;
+        let files = vec![path.to_str().unwrap(); total];
+        let output = CancelOnProgress::default();
+        let server = Server::new(output.clone(), Config::default());
+        output.running.set(Arc::clone(&server.running)).ok();
+
+        server.handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "load", "params": {"files": files}}).to_string());
+        server.join();
+        server.handle(&json!({"jsonrpc": "2.0", "id": 2, "method": "extract",
+            "params": {"files": files, "ion_list": "scfas"}}).to_string());
+        server.join();
+        let messages = output.buffer.messages();
+        for (id, key) in [(1, "ms1_scans"), (2, "compounds")] {
+            let result = &response(&messages, id)["result"];
+            assert_eq!(result["cancelled"], true);
+            let completed = result["files"].as_array().unwrap();
+            assert!(!completed.is_empty() && completed.len() < total, "{} of {total} files", completed.len());
+            assert!(completed.iter().all(|file| file.get(key).is_some()));
+        }
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_serve_stops_on_shutdown() {
+        let input = [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "list"}).to_string(),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}).to_string(),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "list"}).to_string(),
+        ]
+        .join("\n");
+        let output = SharedBuffer::default();
+        serve(io::Cursor::new(input), output.clone(), &Config::default()).unwrap();
+        let ids: Vec<Value> = output.messages().iter().map(|m| m["id"].clone()).collect();
+        assert_eq!(ids, vec![json!(1), json!(2)]);
+    }
+}
@@ -0,0 +1,303 @@
+//! Spectra at a point of a run, e.g. for a click on an XIC: the scan closest to a
+//! retention time, or the scans of an RT range combined into one spectrum, such as the
+//! spectrum under a chromatographic peak with the background around it subtracted.
+//!
+//! Both work on the scans kept in memory, so the measurement has to be loaded with
+//! its scans (`retain_scans`). [`write_spectrum_csv`](crate::export::write_spectrum_csv),
+//! [`write_mgf`](crate::export::write_mgf) and
+//! [`export_spectrum`](crate::export::export_spectrum) write the spectra.
+
+use crate::error::{Error, Result};
+use crate::measurements::MSMeasurement;
+use crate::processing::peak_arrays;
+use mzdata::spectrum::{MultiLayerSpectrum, SpectrumLike};
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+
+/// Default width of the m/z bins scans are combined in
+pub const DEFAULT_BIN_WIDTH: f64 = 0.001;
+
+/// A spectrum taken from a run: a single scan, or several scans combined
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExtractedSpectrum {
+    /// The file the scans were read from
+    pub file: String,
+    pub ms_level: u8,
+    /// First and last retention time of the scans, in minutes
+    pub rt_range: (f64, f64),
+    /// Number of scans combined
+    pub scans: usize,
+    /// Number of scans the subtracted background was averaged over
+    pub background_scans: usize,
+    /// m/z of the first precursor ion, for a single MSn scan
+    pub precursor_mz: Option<f64>,
+    pub mzs: Vec<f64>,
+    pub intensities: Vec<f64>,
+}
+
+impl ExtractedSpectrum {
+    /// The peaks of a single scan of `file`.
+    ///
+    /// Fails with [`Error::Spectrum`] if its arrays cannot be decoded.
+    pub fn from_scan(file: &str, scan: &MultiLayerSpectrum) -> Result<Self> {
+        let (mzs, intensities) = match peak_arrays(scan)? {
+            Some((mzs, intensities)) => (mzs.to_vec(), intensities.iter().map(|i| *i as f64).collect()),
+            None => (Vec::new(), Vec::new()),
+        };
+        Ok(ExtractedSpectrum {
+            file: file.to_string(),
+            ms_level: scan.ms_level(),
+            rt_range: (scan.start_time(), scan.start_time()),
+            scans: 1,
+            background_scans: 0,
+            precursor_mz: scan.precursor().and_then(|precursor| precursor.ions.first()).map(|ion| ion.mz),
+            mzs,
+            intensities,
+        })
+    }
+
+    /// The retention time of the spectrum: the middle of its RT range
+    pub fn rt(&self) -> f64 {
+        (self.rt_range.0 + self.rt_range.1) / 2.0
+    }
+
+    /// A title naming the file and the RT, or RT range, of the spectrum
+    pub fn title(&self) -> String {
+        match self.scans {
+            1 => format!("{} MS{} {:.4} min", self.file, self.ms_level, self.rt_range.0),
+            _ => format!("{} MS{} {:.4}-{:.4} min", self.file, self.ms_level, self.rt_range.0, self.rt_range.1),
+        }
+    }
+}
+
+/// How the intensities of the combined scans are added up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Combine {
+    /// The mean intensity per scan
+    #[default]
+    Mean,
+    /// The summed intensity of all scans
+    Sum,
+}
+
+/// How scans are combined into one spectrum by [`combine_scans`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CombineOptions {
+    pub mode: Combine,
+    /// Width of the m/z bins the peaks of all scans are merged in
+    pub bin_width: f64,
+    /// RT ranges of background scans, e.g. on either side of a peak. Their mean
+    /// intensity per bin is subtracted from every combined scan.
+    pub background: Vec<(f64, f64)>,
+}
+
+impl Default for CombineOptions {
+    fn default() -> Self {
+        CombineOptions {
+            mode: Combine::Mean,
+            bin_width: DEFAULT_BIN_WIDTH,
+            background: Vec::new(),
+        }
+    }
+}
+
+/// The scan of `ms_level` with the retention time closest to `rt`, `None` if there
+/// are no such scans
+pub fn nearest_scan<S: Borrow<MultiLayerSpectrum>>(scans: &[S], rt: f64, ms_level: u8) -> Option<&MultiLayerSpectrum> {
+    scans
+        .iter()
+        .map(Borrow::borrow)
+        .filter(|scan| scan.ms_level() == ms_level)
+        .min_by(|a, b| (a.start_time() - rt).abs().total_cmp(&(b.start_time() - rt).abs()))
+}
+
+/// Combine the scans of `ms_level` within `rt_range` into one spectrum.
+///
+/// The peaks of all scans are merged in m/z bins of `options.bin_width`; every bin
+/// becomes one peak, at the intensity-weighted mean m/z of the peaks in it. With
+/// background ranges, the mean background of every bin is subtracted. Bins left
+/// without intensity are dropped.
+///
+/// Returns `None` if there are no scans of `ms_level` within `rt_range`. Fails with
+/// [`Error::InvalidParameter`] if the bin width is not positive, and with
+/// [`Error::Spectrum`] if a scan cannot be decoded.
+pub fn combine_scans<S: Borrow<MultiLayerSpectrum>>(
+    file: &str,
+    scans: &[S],
+    rt_range: (f64, f64),
+    ms_level: u8,
+    options: &CombineOptions,
+) -> Result<Option<ExtractedSpectrum>> {
+    if !(options.bin_width > 0.0 && options.bin_width.is_finite()) {
+        return Err(Error::InvalidParameter {
+            name: "bin width".to_string(),
+            message: format!("{} is not a positive m/z width", options.bin_width),
+        });
+    }
+    let in_range = |scan: &MultiLayerSpectrum, (start, end): (f64, f64)| {
+        scan.ms_level() == ms_level && (start..=end).contains(&scan.start_time())
+    };
+    let peak_scans: Vec<&MultiLayerSpectrum> = scans
+        .iter()
+        .map(Borrow::borrow)
+        .filter(|scan| in_range(scan, rt_range))
+        .collect();
+    let Some((first, last)) = peak_scans.first().zip(peak_scans.last()) else {
+        return Ok(None);
+    };
+    let background_scans: Vec<&MultiLayerSpectrum> = scans
+        .iter()
+        .map(Borrow::borrow)
+        .filter(|scan| options.background.iter().any(|range| in_range(scan, *range)))
+        .collect();
+
+    let peaks = bin_peaks(&peak_scans, options.bin_width)?;
+    let background = bin_peaks(&background_scans, options.bin_width)?;
+    let scale = match options.mode {
+        Combine::Mean => 1.0 / peak_scans.len() as f64,
+        Combine::Sum => 1.0,
+    };
+    // The background of one scan, scaled like the combined scans
+    let background_scale = match options.mode {
+        Combine::Mean => 1.0,
+        Combine::Sum => peak_scans.len() as f64,
+    } / background_scans.len().max(1) as f64;
+
+    let (mut mzs, mut intensities) = (Vec::new(), Vec::new());
+    for (bin, (total, weighted_mz)) in &peaks {
+        let background = background.get(bin).map_or(0.0, |(intensity, _)| *intensity);
+        let intensity = total * scale - background * background_scale;
+        if intensity > 0.0 {
+            mzs.push(weighted_mz / total);
+            intensities.push(intensity);
+        }
+    }
+    let spectrum = ExtractedSpectrum {
+        file: file.to_string(),
+        ms_level,
+        rt_range: (first.start_time(), last.start_time()),
+        scans: peak_scans.len(),
+        background_scans: background_scans.len(),
+        precursor_mz: None,
+        mzs,
+        intensities,
+    };
+    Ok(Some(spectrum))
+}
+
+/// The scan of `ms_level` of a measurement closest to `rt`, see [`nearest_scan`]
+pub fn spectrum_at(measurement: &MSMeasurement, rt: f64, ms_level: u8) -> Result<Option<ExtractedSpectrum>> {
+    nearest_scan(scans_of(measurement, ms_level), rt, ms_level)
+        .map(|scan| ExtractedSpectrum::from_scan(&measurement.path, scan))
+        .transpose()
+}
+
+/// The scans of `ms_level` of a measurement within `rt_range` combined into one
+/// spectrum, see [`combine_scans`]
+pub fn combined_spectrum(
+    measurement: &MSMeasurement,
+    rt_range: (f64, f64),
+    ms_level: u8,
+    options: &CombineOptions,
+) -> Result<Option<ExtractedSpectrum>> {
+    combine_scans(&measurement.path, scans_of(measurement, ms_level), rt_range, ms_level, options)
+}
+
+/// The scans of a measurement that hold `ms_level`
+fn scans_of(measurement: &MSMeasurement, ms_level: u8) -> &[MultiLayerSpectrum] {
+    match ms_level {
+        1 => &measurement.ms1_scans,
+        _ => &measurement.ms2_scans,
+    }
+}
+
+/// Summed intensity and summed intensity-weighted m/z of the peaks of `scans` per
+/// m/z bin, keyed by bin number
+fn bin_peaks(scans: &[&MultiLayerSpectrum], bin_width: f64) -> Result<BTreeMap<i64, (f64, f64)>> {
+    let mut bins: BTreeMap<i64, (f64, f64)> = BTreeMap::new();
+    for scan in scans {
+        let Some((mzs, intensities)) = peak_arrays(scan)? else {
+            continue;
+        };
+        for (mz, intensity) in mzs.iter().zip(intensities.iter()) {
+            let bin = bins.entry((mz / bin_width).floor() as i64).or_default();
+            bin.0 += *intensity as f64;
+            bin.1 += mz * *intensity as f64;
+        }
+    }
+    Ok(bins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{synthetic_run, synthetic_spectrum};
+
+    #[test]
+    fn test_spectrum_at() {
+        let (ms1, ms2): (Vec<_>, Vec<_>) = synthetic_run().into_iter().partition(|scan| scan.ms_level() == 1);
+        let measurement = MSMeasurement::from_data(ms1, ms2, Vec::new(), 0.0001).with_source("run.mzML", 0);
+
+        let spectrum = spectrum_at(&measurement, 0.52, 1).unwrap().unwrap();
+        assert_eq!(spectrum.rt_range, (0.5, 0.5));
+        assert_eq!(spectrum.mzs, [50.0, 59.0139, 73.0295, 100.0]);
+        assert_eq!(spectrum.intensities[1], 5000.0);
+        assert_eq!(spectrum.title(), "run.mzML MS1 0.5000 min");
+        let spectrum = spectrum_at(&measurement, 0.52, 2).unwrap().unwrap();
+        assert_eq!((spectrum.ms_level, spectrum.rt_range.0), (2, 0.55));
+
+        let empty = MSMeasurement::from_data(Vec::new(), Vec::new(), Vec::new(), 0.0001);
+        assert_eq!(spectrum_at(&empty, 0.5, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_combine_scans() {
+        let scans = vec![
+            synthetic_spectrum(0, 1, 0.1, &[100.001, 200.001], &[10.0, 10.0]),
+            synthetic_spectrum(1, 1, 0.2, &[100.0012, 150.001, 200.001], &[100.0, 50.0, 10.0]),
+            synthetic_spectrum(2, 1, 0.3, &[100.0008, 150.001, 200.001], &[100.0, 30.0, 10.0]),
+            synthetic_spectrum(3, 1, 0.4, &[100.001, 200.001], &[10.0, 10.0]),
+        ];
+
+        let options = CombineOptions { bin_width: 0.01, ..CombineOptions::default() };
+        let mean = combine_scans("run.mzML", &scans, (0.15, 0.35), 1, &options).unwrap().unwrap();
+        assert_eq!((mean.scans, mean.rt_range), (2, (0.2, 0.3)));
+        assert_eq!(mean.intensities, [100.0, 40.0, 10.0]);
+        // Peaks within a bin are merged at their weighted mean m/z
+        assert!((mean.mzs[0] - 100.001).abs() < 1e-9);
+        let summed = combine_scans(
+            "run.mzML",
+            &scans,
+            (0.15, 0.35),
+            1,
+            &CombineOptions { mode: Combine::Sum, ..options.clone() },
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(summed.intensities, [200.0, 80.0, 20.0]);
+
+        // The background scans on either side are subtracted, emptied bins dropped
+        let subtracted = combine_scans(
+            "run.mzML",
+            &scans,
+            (0.15, 0.35),
+            1,
+            &CombineOptions { background: vec![(0.0, 0.15), (0.35, 0.5)], ..options.clone() },
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(subtracted.background_scans, 2);
+        assert_eq!(subtracted.intensities, [90.0, 40.0]);
+        assert!((subtracted.mzs[1] - 150.001).abs() < 1e-9);
+
+        assert_eq!(combine_scans("run.mzML", &scans, (1.0, 2.0), 1, &options).unwrap(), None);
+        let invalid = CombineOptions { bin_width: 0.0, ..options };
+        assert!(matches!(
+            combine_scans("run.mzML", &scans, (0.0, 1.0), 1, &invalid),
+            Err(Error::InvalidParameter { .. })
+        ));
+    }
+}
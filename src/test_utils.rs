@@ -0,0 +1,105 @@
+//! Helpers shared by the unit tests: synthetic spectra and throwaway files.
+
+use base64_simd::STANDARD as BASE64;
+use mzdata::io::SpectrumWriter;
+use mzdata::params::ParamList;
+use mzdata::spectrum::bindata::{ArrayType, BinaryArrayMap, BinaryDataArrayType, DataArray};
+use mzdata::spectrum::{
+    Acquisition, MultiLayerSpectrum, ScanEvent, ScanPolarity, SignalContinuity,
+    SpectrumDescription,
+};
+use mzdata::MzMLWriter;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A unique path in the system temp directory, ending with `name`
+pub fn temp_path(name: &str) -> PathBuf {
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    std::env::temp_dir().join(format!("lcmspector-test-{}-{}-{}", std::process::id(), n, name))
+}
+
+/// Build a spectrum with the given m/z and intensity arrays
+pub fn synthetic_spectrum(
+    index: usize,
+    ms_level: u8,
+    rt: f64,
+    mzs: &[f64],
+    intensities: &[f32],
+) -> MultiLayerSpectrum {
+    let mut mz_array = DataArray::from_name_and_type(&ArrayType::MZArray, BinaryDataArrayType::Float64);
+    mz_array.update_buffer(mzs).unwrap();
+    let mut intensity_array =
+        DataArray::from_name_and_type(&ArrayType::IntensityArray, BinaryDataArrayType::Float32);
+    intensity_array.update_buffer(intensities).unwrap();
+    let mut arrays = BinaryArrayMap::new();
+    arrays.add(mz_array);
+    arrays.add(intensity_array);
+
+    let description = SpectrumDescription::new(
+        format!("scan={}", index + 1),
+        index,
+        ms_level,
+        ScanPolarity::Negative,
+        SignalContinuity::Centroid,
+        ParamList::new(),
+        Acquisition {
+            scans: vec![ScanEvent {
+                start_time: rt,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        None,
+    );
+    MultiLayerSpectrum::from_arrays_and_description(arrays, description)
+}
+
+/// Encode m/z and intensity pairs as the base64 `<peaks>` content of an mzXML scan,
+/// 32-bit in network byte order
+pub fn encode_peaks(pairs: &[(f32, f32)]) -> String {
+    let bytes: Vec<u8> = pairs
+        .iter()
+        .flat_map(|(mz, intensity)| [mz.to_be_bytes(), intensity.to_be_bytes()])
+        .flatten()
+        .collect();
+    BASE64.encode_to_string(bytes)
+}
+
+/// A small run: ten MS1 scans with an acetate-like peak at m/z 59.0139 that
+/// peaks at scan 5, each followed by one MS2 scan
+pub fn synthetic_run() -> Vec<MultiLayerSpectrum> {
+    let mut spectra = Vec::new();
+    for i in 0..10 {
+        let apex = 1000.0 * (5.0 - (i as f32 - 5.0).abs()).max(0.5);
+        spectra.push(synthetic_spectrum(
+            spectra.len(),
+            1,
+            i as f64 * 0.1,
+            &[50.0, 59.0139, 73.0295, 100.0],
+            &[10.0, apex, 20.0, 5.0],
+        ));
+        spectra.push(synthetic_spectrum(
+            spectra.len(),
+            2,
+            i as f64 * 0.1 + 0.05,
+            &[41.0, 59.0139],
+            &[3.0, 7.0],
+        ));
+    }
+    spectra
+}
+
+/// Write spectra to an indexed mzML file and return its path
+pub fn write_mzml(name: &str, spectra: &[MultiLayerSpectrum]) -> PathBuf {
+    let path = temp_path(name);
+    let file = std::fs::File::create(&path).unwrap();
+    let mut writer = MzMLWriter::new(std::io::BufWriter::new(file));
+    writer.set_spectrum_count(spectra.len() as u64);
+    for spectrum in spectra {
+        writer.write(spectrum).unwrap();
+    }
+    writer.close().unwrap();
+    path
+}
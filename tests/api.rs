@@ -0,0 +1,236 @@
+//! Exercises the library API end to end: load, extract, quantify and export.
+
+use lcmspector_backend::export::chromatograms_path;
+use lcmspector_backend::{
+    calibrate, detect_format, export_results, load_selected_ms_scans, process_file_streaming,
+    process_files_in_parallel, quantify, CallbackProgress, CancellationToken, Centroiding, Compound, MSFileFormat, NoProgress,
+    ProgressEvent, Provenance, ScanSelection, XicBuilder,
+};
+use mzdata::io::SpectrumWriter;
+use mzdata::params::ParamList;
+use mzdata::spectrum::bindata::{ArrayType, BinaryArrayMap, BinaryDataArrayType, DataArray};
+use mzdata::spectrum::{
+    Acquisition, MultiLayerSpectrum, ScanEvent, ScanPolarity, SignalContinuity,
+    SpectrumDescription,
+};
+use mzdata::MzMLWriter;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const ACETATE: f64 = 59.0139;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("lcmspector-api-{}-{}", std::process::id(), name))
+}
+
+fn spectrum(index: usize, ms_level: u8, rt: f64, mzs: &[f64], intensities: &[f32]) -> MultiLayerSpectrum {
+    let mut mz_array = DataArray::from_name_and_type(&ArrayType::MZArray, BinaryDataArrayType::Float64);
+    mz_array.update_buffer(mzs).unwrap();
+    let mut intensity_array =
+        DataArray::from_name_and_type(&ArrayType::IntensityArray, BinaryDataArrayType::Float32);
+    intensity_array.update_buffer(intensities).unwrap();
+    let mut arrays = BinaryArrayMap::new();
+    arrays.add(mz_array);
+    arrays.add(intensity_array);
+
+    let description = SpectrumDescription::new(
+        format!("scan={}", index + 1),
+        index,
+        ms_level,
+        ScanPolarity::Negative,
+        SignalContinuity::Centroid,
+        ParamList::new(),
+        Acquisition {
+            scans: vec![ScanEvent {
+                start_time: rt,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        None,
+    );
+    MultiLayerSpectrum::from_arrays_and_description(arrays, description)
+}
+
+/// Write a run whose acetate peak is scaled by `amount` and return the mzML path
+fn write_run(name: &str, amount: f32) -> PathBuf {
+    let spectra: Vec<MultiLayerSpectrum> = (0..10)
+        .map(|i| {
+            let apex = amount * (5.0 - (i as f32 - 5.0).abs()).max(0.0);
+            spectrum(i, 1, i as f64 * 0.1, &[50.0, ACETATE, 100.0], &[10.0, apex, 5.0])
+        })
+        .collect();
+    write_spectra(name, &spectra)
+}
+
+/// Write spectra to an mzML file and return its path
+fn write_spectra(name: &str, spectra: &[MultiLayerSpectrum]) -> PathBuf {
+    let path = temp_path(name);
+    let file = std::fs::File::create(&path).unwrap();
+    let mut writer = MzMLWriter::new(std::io::BufWriter::new(file));
+    writer.set_spectrum_count(spectra.len() as u64);
+    for spectrum in spectra {
+        writer.write(spectrum).unwrap();
+    }
+    writer.close().unwrap();
+    path
+}
+
+fn ion_list() -> Vec<Compound> {
+    vec![Compound::new(
+        "Acetic acid".to_string(),
+        vec![ACETATE],
+        vec!["[M-H]-".to_string()],
+    )]
+}
+
+#[test]
+fn test_load_extract_quantify_export() {
+    let standards: Vec<(f64, PathBuf)> = [(1.0, 100.0), (2.0, 200.0), (4.0, 400.0)]
+        .iter()
+        .map(|&(concentration, amount)| {
+            (concentration, write_run(&format!("std-{concentration}.mzML"), amount))
+        })
+        .collect();
+    let sample = write_run("sample.mzML", 300.0);
+    let cancel = CancellationToken::new();
+
+    // Load
+    let sample_path = sample.to_str().unwrap();
+    assert_eq!(detect_format(sample_path).unwrap(), MSFileFormat::MzML);
+    let (ms1, ms2) =
+        load_selected_ms_scans(sample_path, &ScanSelection::default(), Centroiding::default(), &NoProgress, &cancel).unwrap();
+    assert_eq!((ms1.len(), ms2.len()), (10, 0));
+
+    // Extract, both from the file and from already loaded spectra
+    let mut ion_list = ion_list();
+    let events = Mutex::new(Vec::new());
+    let progress = CallbackProgress(|event| events.lock().unwrap().push(event));
+    let measurement =
+        process_file_streaming(sample_path, &ion_list, 0.0001, Centroiding::default(), false, None, &progress, &cancel).unwrap();
+    let events = events.into_inner().unwrap();
+    assert!(matches!(&events[0], ProgressEvent::FileStarted { file } if file == sample_path));
+    assert!(matches!(events[1], ProgressEvent::ScansLoaded { ms1_scans: 10, ms2_scans: 0, .. }));
+    assert!(matches!(events[2], ProgressEvent::XicsBuilt { compounds: 1, .. }));
+    assert!(matches!(events[3], ProgressEvent::FileFinished { .. }));
+    let ion = measurement.xics[0].ion(ACETATE).unwrap();
+    assert_eq!(ion.ms_intensity, Some(7500.0));
+    assert_eq!(ion.rt, Some(0.5));
+
+    let mut builder = XicBuilder::new(&ion_list, 0.0001).unwrap();
+    for spectrum in &ms1 {
+        builder.add_spectrum(spectrum).unwrap();
+    }
+    assert_eq!(builder.finish()[0].ion(ACETATE).unwrap().ms_intensity, Some(7500.0));
+
+    // Quantify
+    let measured: Vec<_> = standards
+        .iter()
+        .map(|(_, path)| {
+            process_file_streaming(path.to_str().unwrap(), &ion_list, 0.0001, Centroiding::default(), false, None, &NoProgress, &cancel)
+                .unwrap()
+        })
+        .collect();
+    let points: Vec<_> = standards
+        .iter()
+        .map(|(concentration, _)| *concentration)
+        .zip(measured.iter())
+        .collect();
+    calibrate(&mut ion_list, &points);
+    assert!((ion_list[0].calibration_curve["slope"] - 2500.0).abs() < 1e-9);
+
+    let quantities = quantify(&measurement, &ion_list);
+    assert_eq!(quantities.len(), 1);
+    assert!((quantities[0].concentration.unwrap() - 3.0).abs() < 1e-9);
+
+    // Export, with the provenance of the run
+    let mut provenance = Provenance::start(&serde_json::json!({"mass_accuracy": 0.0001}), &["acids"], &ion_list);
+    provenance.finish(&[sample_path]);
+    assert_eq!(provenance.files[0].error, None);
+    let csv_path = temp_path("results.csv");
+    export_results(&csv_path, std::slice::from_ref(&measurement), &provenance).unwrap();
+    let csv = std::fs::read_to_string(&csv_path).unwrap();
+    assert!(csv.contains(&format!("0,{},Acetic acid,[M-H]-,59.0139,0.5,7500,", sample_path)));
+    // The chromatograms are written next to the results, one row per MS1 scan
+    let chromatograms_path = chromatograms_path(&csv_path);
+    let chromatograms = std::fs::read_to_string(&chromatograms_path).unwrap();
+    assert_eq!(chromatograms.lines().filter(|line| !line.starts_with('#')).count(), 1 + measurement.chromatograms[0].tic.len());
+
+    let json_path = temp_path("results.json");
+    export_results(&json_path, &[measurement], &provenance).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap()).unwrap();
+    assert_eq!(json["provenance"]["files"][0]["sha256"], provenance.files[0].sha256.as_deref().unwrap());
+    let results = &json["results"];
+    assert_eq!(results[0]["file"], sample_path);
+    assert_eq!(results[0]["compounds"][0]["name"], "Acetic acid");
+    assert_eq!(results[0]["compounds"][0]["ions"]["59.0139"]["MS Intensity"], 7500.0);
+    assert!(results[0]["chromatograms"][0]["tic"].as_array().is_some_and(|tic| !tic.is_empty()));
+
+    for path in standards.into_iter().map(|(_, p)| p).chain([sample, csv_path, chromatograms_path, json_path]) {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+#[test]
+fn test_batches_keep_input_order() {
+    let mut files: Vec<String> = (0..12)
+        .map(|i| write_run(&format!("order-{i}.mzML"), 100.0 * (i + 1) as f32).to_str().unwrap().to_string())
+        .collect();
+    // A failed file keeps its place instead of shifting the others
+    files.insert(5, temp_path("missing.mzML").to_str().unwrap().to_string());
+    let cancel = CancellationToken::new();
+
+    let check = |results: &[lcmspector_backend::FileResult]| {
+        assert_eq!(results.iter().map(|r| r.file.as_str()).collect::<Vec<_>>(), files);
+        for (index, result) in results.iter().enumerate() {
+            match &result.result {
+                Ok(measurement) => {
+                    assert_eq!((measurement.sample_index, measurement.path.as_str()), (index, result.file.as_str()));
+                }
+                Err(_) => assert_eq!(index, 5),
+            }
+        }
+    };
+    check(&process_files_in_parallel(&files, &ion_list(), 0.0001, Centroiding::default(), false, None, &NoProgress, &cancel));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    check(&runtime.block_on(lcmspector_backend::batch::process_large_batch(
+        &files,
+        ion_list(),
+        0.0001,
+        Centroiding::default(),
+        false,
+        None,
+        Arc::new(NoProgress),
+        cancel.clone(),
+    )));
+
+    for file in files {
+        std::fs::remove_file(file).ok();
+    }
+}
+
+#[test]
+fn test_batch_size_does_not_change_matching() {
+    // A peak right at the edge of the m/z window, +3 mass accuracies from the ion
+    let edge = ACETATE + 3.0 * 0.0001;
+    let spectra: Vec<MultiLayerSpectrum> = (0..3).map(|i| spectrum(i, 1, i as f64 * 0.1, &[50.0, edge], &[10.0, 300.0])).collect();
+    let path = write_spectra("window-edge.mzML", &spectra);
+    let files = vec![path.to_str().unwrap().to_string()];
+    let cancel = CancellationToken::new();
+
+    let small = process_files_in_parallel(&files, &ion_list(), 0.0001, Centroiding::default(), false, None, &NoProgress, &cancel);
+    let large = tokio::runtime::Runtime::new().unwrap().block_on(lcmspector_backend::batch::process_large_batch(
+        &files,
+        ion_list(),
+        0.0001,
+        Centroiding::default(),
+        false,
+        None,
+        Arc::new(NoProgress),
+        cancel,
+    ));
+    let intensity = |results: &[lcmspector_backend::FileResult]| results[0].result.as_ref().unwrap().xics[0].ions[0].ms_intensity;
+    assert_eq!(intensity(&small), Some(900.0));
+    assert_eq!(intensity(&large), intensity(&small));
+    std::fs::remove_file(path).ok();
+}